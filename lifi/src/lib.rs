@@ -15,7 +15,7 @@ use tools::ToolsResponse;
 
 use crate::client::LiFiClientError;
 
-use self::quote::{Order, QuoteResponse};
+use self::quote::{Order, QuoteOptions, QuoteResponse};
 
 pub struct LiFi {
     client: LiFiClient,
@@ -116,9 +116,36 @@ impl LiFi {
         from_address: &str,
         to_address: &str,
         from_amount_with_decimals: &str,
+    ) -> Result<QuoteResponse, LiFiError> {
+        self.get_quote_with_options(
+            from_chain,
+            to_chain,
+            from_token,
+            to_token,
+            from_address,
+            to_address,
+            from_amount_with_decimals,
+            &QuoteOptions::default(),
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_quote_with_options(
+        &self,
+        from_chain: &str,
+        to_chain: &str,
+        from_token: &str,
+        to_token: &str,
+        from_address: &str,
+        to_address: &str,
+        from_amount_with_decimals: &str,
+        options: &QuoteOptions,
     ) -> Result<QuoteResponse, LiFiError> {
         let order = Order::Fastest.to_string();
-        let params = vec![
+        let slippage = options.slippage.map(|s| s.to_string());
+        let max_price_impact = options.max_price_impact.map(|s| s.to_string());
+        let mut params = vec![
             ("fromChain", from_chain),
             ("toChain", to_chain),
             ("fromToken", from_token),
@@ -128,6 +155,12 @@ impl LiFi {
             ("fromAmount", from_amount_with_decimals),
             ("order", &order),
         ];
+        if let Some(slippage) = &slippage {
+            params.push(("slippage", slippage));
+        }
+        if let Some(max_price_impact) = &max_price_impact {
+            params.push(("maxPriceImpact", max_price_impact));
+        }
 
         self.client
             .get("/quote", &params)
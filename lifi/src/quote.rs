@@ -8,6 +8,14 @@ pub enum Order {
     Cheapest,
 }
 
+/// Optional execution limits for a quote, fractions as LiFi expects them,
+/// e.g. 0.005 for 0.5%
+#[derive(Debug, Clone, Default)]
+pub struct QuoteOptions {
+    pub slippage: Option<f64>,
+    pub max_price_impact: Option<f64>,
+}
+
 impl std::fmt::Display for Order {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -1,7 +1,7 @@
 use crate::util::create_redis_pool;
 use anyhow::{Context, Result};
 use bb8_redis::{bb8, RedisConnectionManager};
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::price::PriceUpdate;
 
@@ -15,17 +15,66 @@ pub trait MessageQueue: Send + Sync + 'static {
     ) -> Result<(), Self::Error>;
 }
 
+// Names shared with the engine, see `listen-engine/src/redis/subscriber.rs`
+pub const PRICE_UPDATES_CHANNEL: &str = "price_updates";
+pub const PRICE_UPDATES_STREAM: &str = "price_updates:stream";
+
+/// Approximate cap on the stream length, bounds how far back an engine that
+/// was down can replay
+const PRICE_UPDATES_STREAM_MAXLEN: usize = 250_000;
+
+/// Where price updates are published, set with `PRICE_UPDATES_TRANSPORT`.
+/// Pub/sub is fire and forget, the stream keeps updates around for engines
+/// that weren't listening. `both` is for moving engines over one by one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PriceTransport {
+    #[default]
+    PubSub,
+    Stream,
+    Both,
+}
+
+impl PriceTransport {
+    pub fn from_env() -> Self {
+        match std::env::var("PRICE_UPDATES_TRANSPORT").as_deref() {
+            Err(_) | Ok("pubsub") => PriceTransport::PubSub,
+            Ok("stream") => PriceTransport::Stream,
+            Ok("both") => PriceTransport::Both,
+            Ok(other) => {
+                warn!(
+                    "Unknown PRICE_UPDATES_TRANSPORT {:?}, using pubsub",
+                    other
+                );
+                PriceTransport::PubSub
+            }
+        }
+    }
+
+    fn publishes(&self) -> bool {
+        matches!(self, PriceTransport::PubSub | PriceTransport::Both)
+    }
+
+    fn streams(&self) -> bool {
+        matches!(self, PriceTransport::Stream | PriceTransport::Both)
+    }
+}
+
 // Redis implementation of MessageQueue
 #[derive(Debug)]
 pub struct RedisMessageQueue {
     pool: bb8::Pool<RedisConnectionManager>,
+    transport: PriceTransport,
 }
 
 impl RedisMessageQueue {
     pub async fn new(redis_url: &str) -> Result<Self> {
         let pool = create_redis_pool(redis_url).await?;
-        info!("Connected to Redis message queue at {}", redis_url);
-        Ok(Self { pool })
+        let transport = PriceTransport::from_env();
+        info!(
+            "Connected to Redis message queue at {} ({:?})",
+            redis_url, transport
+        );
+        Ok(Self { pool, transport })
     }
 }
 
@@ -60,10 +109,24 @@ impl MessageQueue for RedisMessageQueue {
             ))
         })?;
 
-        redis::cmd("PUBLISH")
-            .arg("price_updates")
-            .arg(payload)
-            .query_async(&mut *conn)
-            .await
+        let mut pipe = redis::pipe();
+        if self.transport.publishes() {
+            pipe.cmd("PUBLISH")
+                .arg(PRICE_UPDATES_CHANNEL)
+                .arg(&payload)
+                .ignore();
+        }
+        if self.transport.streams() {
+            pipe.cmd("XADD")
+                .arg(PRICE_UPDATES_STREAM)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(PRICE_UPDATES_STREAM_MAXLEN)
+                .arg("*")
+                .arg("data")
+                .arg(&payload)
+                .ignore();
+        }
+        pipe.query_async(&mut *conn).await
     }
 }
@@ -0,0 +1,358 @@
+//! Relative order amounts, besides base units `SwapOrder.amount` can be a
+//! percentage of the wallet's `input_token` balance (e.g. "50%"),
+//! [`PARENT_OUTPUT`], the amount of `input_token` received by the parent
+//! step's transaction, or a multiple of the USD size of a copied trade (e.g.
+//! "0.5x", see `copy`). They are resolved right before the order executes.
+
+use std::str::FromStr;
+
+use crate::engine::{
+    chain::{self, ChainError},
+    copy::usd_to_base_units,
+    execute::DRY_RUN_TX_PREFIX,
+    order::{is_solana, SwapOrder},
+    pipeline::{Action, PipelineStep},
+    EngineError,
+};
+
+pub const PARENT_OUTPUT: &str = "parent_output";
+
+/// Kept out of percentage orders of native SOL, so that the wallet can still
+/// pay for fees and token account rent
+const SOL_FEE_RESERVE_LAMPORTS: u128 = 10_000_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AmountError {
+    #[error("[Amount] Invalid percentage: {0}")]
+    InvalidPercent(String),
+
+    #[error("[Amount] Invalid leader size: {0}")]
+    InvalidLeaderSize(String),
+
+    #[error("[Amount] Invalid parent step: {0}")]
+    InvalidParent(String),
+
+    #[error("[Amount] Parent transaction failed: {0}")]
+    ParentTransactionFailed(String),
+
+    #[error("[Amount] Unsupported: {0}")]
+    Unsupported(String),
+
+    #[error("[Amount] {0}")]
+    ChainError(#[from] ChainError),
+
+    #[error("[Amount] Resolved amount is zero")]
+    ZeroAmount,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderAmount {
+    /// base units, passed through as is
+    Absolute(String),
+    /// percentage of the wallet balance, within (0, 100]
+    PercentOfBalance(f64),
+    ParentOutput,
+    /// multiple of the leader's trade size in USD, within (0, 100]
+    LeaderSize(f64),
+}
+
+impl FromStr for OrderAmount {
+    type Err = AmountError;
+
+    fn from_str(amount: &str) -> Result<Self, Self::Err> {
+        let amount = amount.trim();
+        if amount == PARENT_OUTPUT {
+            return Ok(OrderAmount::ParentOutput);
+        }
+        if let Some(ratio) = amount.strip_suffix('x') {
+            return match ratio.trim().parse::<f64>() {
+                Ok(ratio) if ratio > 0.0 && ratio <= 100.0 => Ok(OrderAmount::LeaderSize(ratio)),
+                _ => Err(AmountError::InvalidLeaderSize(format!(
+                    "{} is not within (0x, 100x]",
+                    amount
+                ))),
+            };
+        }
+        match amount.strip_suffix('%') {
+            Some(pct) => match pct.trim().parse::<f64>() {
+                Ok(pct) if pct > 0.0 && pct <= 100.0 => Ok(OrderAmount::PercentOfBalance(pct)),
+                _ => Err(AmountError::InvalidPercent(format!(
+                    "{} is not within (0%, 100%]",
+                    amount
+                ))),
+            },
+            None => Ok(OrderAmount::Absolute(amount.to_string())),
+        }
+    }
+}
+
+/// Returns the order with its amount in base units. `parents` are the
+/// parent steps of the order's step. `None` means that the parent's
+/// transaction isn't confirmed yet, the step should be retried on the next
+/// evaluation.
+pub async fn resolve_order_amount(
+    order: &SwapOrder,
+    wallet_address: Option<&str>,
+    pubkey: Option<&str>,
+    parents: &[PipelineStep],
+) -> Result<Option<SwapOrder>, EngineError> {
+    let owner = || {
+        if order.is_evm() {
+            wallet_address.ok_or(EngineError::EVMWalletNotAvailable)
+        } else {
+            pubkey.ok_or(EngineError::SolanaWalletNotAvailable)
+        }
+    };
+
+    let resolved = match order
+        .amount
+        .parse::<OrderAmount>()
+        .map_err(EngineError::AmountError)?
+    {
+        OrderAmount::Absolute(_) => return Ok(Some(order.clone())),
+        OrderAmount::PercentOfBalance(pct) => {
+            let balance = wallet_balance(order, owner()?)
+                .await
+                .map_err(EngineError::AmountError)?;
+            percent_of(balance, pct)
+        }
+        OrderAmount::ParentOutput => match parent_output(order, owner()?, parents)
+            .await
+            .map_err(EngineError::AmountError)?
+        {
+            Some(output) => output,
+            None => return Ok(None),
+        },
+        // resolved by `Engine::mirror_order` with the copied trade
+        OrderAmount::LeaderSize(_) => {
+            return Err(EngineError::AmountError(AmountError::Unsupported(
+                "leader size without a copied trade".to_string(),
+            )))
+        }
+    };
+    if resolved == 0 {
+        return Err(EngineError::AmountError(AmountError::ZeroAmount));
+    }
+
+    tracing::info!(amount = %order.amount, %resolved, input_token = %order.input_token, "Resolved relative order amount");
+
+    Ok(Some(SwapOrder {
+        amount: resolved.to_string(),
+        ..order.clone()
+    }))
+}
+
+fn percent_of(balance: u128, pct: f64) -> u128 {
+    // basis points keep the math in integers
+    let bps = (pct * 100.0).round() as u128;
+    balance
+        .checked_mul(bps)
+        .map(|scaled| scaled / 10_000)
+        .unwrap_or(balance / 10_000 * bps)
+}
+
+/// Balance of the order's input token, minus the fee reserve for SOL
+async fn wallet_balance(order: &SwapOrder, owner: &str) -> Result<u128, AmountError> {
+    Ok(spendable_balance(&order.from_chain_caip2, owner, &order.input_token).await?)
+}
+
+/// Balance of the token in base units, minus the fee reserve for native SOL
+pub async fn spendable_balance(caip2: &str, owner: &str, token: &str) -> Result<u128, ChainError> {
+    let balance = chain::wallet_balance(caip2, owner, token).await?;
+    if is_solana(caip2) && chain::is_native(caip2, token) {
+        return Ok(balance.saturating_sub(SOL_FEE_RESERVE_LAMPORTS));
+    }
+    Ok(balance)
+}
+
+/// Amount of the order's input token received by the parent step, `None`
+/// while the parent's transaction is not confirmed
+async fn parent_output(
+    order: &SwapOrder,
+    owner: &str,
+    parents: &[PipelineStep],
+) -> Result<Option<u128>, AmountError> {
+    let [parent] = parents else {
+        return Err(AmountError::InvalidParent(format!(
+            "{} needs exactly one parent step, found {}",
+            PARENT_OUTPUT,
+            parents.len()
+        )));
+    };
+    let Action::Order(parent_order) = &parent.action else {
+        return Err(AmountError::InvalidParent(
+            "the parent step is not an order".to_string(),
+        ));
+    };
+    // bridged funds arrive in a different transaction than the one recorded
+    if parent_order.from_chain_caip2 != parent_order.to_chain_caip2
+        || parent_order.to_chain_caip2 != order.from_chain_caip2
+    {
+        return Err(AmountError::Unsupported(format!(
+            "{} of a cross-chain order",
+            PARENT_OUTPUT
+        )));
+    }
+    let Some(transaction_hash) = &parent.transaction_hash else {
+        return Err(AmountError::InvalidParent(
+            "the parent step has no transaction".to_string(),
+        ));
+    };
+    if transaction_hash.starts_with(DRY_RUN_TX_PREFIX) {
+        return simulated_output(parent_order, parent).await.map(Some);
+    }
+
+    if let Some(output_amount) = parent
+        .fill
+        .as_ref()
+        .and_then(|fill| fill.output_amount.as_ref())
+    {
+        return output_amount
+            .parse::<u128>()
+            .map(Some)
+            .map_err(|e| AmountError::InvalidParent(format!("invalid output amount: {}", e)));
+    }
+
+    // parents confirmed without recording their fill
+    match chain::get_transaction(&order.from_chain_caip2, transaction_hash).await? {
+        Some(transaction) => {
+            if let Some(error) = transaction.error() {
+                return Err(AmountError::ParentTransactionFailed(error));
+            }
+            let change = transaction.balance_change(owner, &order.input_token)?;
+            Ok(Some(change.max(0) as u128))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Output of a dry-run parent, its simulated input valued at the prices
+/// recorded with the fill
+async fn simulated_output(
+    parent_order: &SwapOrder,
+    parent: &PipelineStep,
+) -> Result<u128, AmountError> {
+    let invalid = |reason: &str| AmountError::InvalidParent(format!("dry-run fill {}", reason));
+    let fill = parent
+        .simulated_fill
+        .as_ref()
+        .ok_or_else(|| invalid("is missing"))?;
+    let (Some(input_price), Some(output_price)) = (fill.input_price, fill.output_price) else {
+        return Err(invalid("has no prices"));
+    };
+    let amount = fill
+        .amount
+        .parse::<u128>()
+        .map_err(|e| invalid(&format!("has an invalid amount: {}", e)))?;
+    let input_decimals =
+        chain::token_decimals(&parent_order.from_chain_caip2, &parent_order.input_token).await?;
+    let output_decimals =
+        chain::token_decimals(&parent_order.to_chain_caip2, &parent_order.output_token).await?;
+
+    let usd = amount as f64 / 10f64.powi(input_decimals as i32) * input_price;
+    Ok(usd_to_base_units(usd, output_price, output_decimals))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::chain::SOLANA_NATIVE_MINTS;
+    use crate::engine::pipeline::{SimulatedFill, Status};
+    use uuid::Uuid;
+
+    const OWNER: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+
+    #[test]
+    fn test_parse_order_amount() {
+        assert_eq!(
+            "1000".parse::<OrderAmount>().unwrap(),
+            OrderAmount::Absolute("1000".to_string())
+        );
+        assert_eq!(
+            "50%".parse::<OrderAmount>().unwrap(),
+            OrderAmount::PercentOfBalance(50.0)
+        );
+        assert_eq!(
+            PARENT_OUTPUT.parse::<OrderAmount>().unwrap(),
+            OrderAmount::ParentOutput
+        );
+        assert_eq!(
+            "0.5x".parse::<OrderAmount>().unwrap(),
+            OrderAmount::LeaderSize(0.5)
+        );
+        for invalid in ["0%", "100.5%", "-5%", "half%", "0x", "-1x"] {
+            assert!(invalid.parse::<OrderAmount>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_percent_of() {
+        assert_eq!(percent_of(1_000_000, 50.0), 500_000);
+        assert_eq!(percent_of(1_000_000, 100.0), 1_000_000);
+        assert_eq!(percent_of(1_000_000, 0.25), 2_500);
+        assert_eq!(percent_of(u128::MAX, 100.0), u128::MAX / 10_000 * 10_000);
+    }
+
+    #[tokio::test]
+    async fn test_parent_output_of_dry_run_fill() {
+        // buys wrapped SOL with SOL, priced apart to check the conversion
+        let order = SwapOrder {
+            input_token: SOLANA_NATIVE_MINTS[0].to_string(),
+            from_chain_caip2: privy::caip2::Caip2::SOLANA.to_string(),
+            to_chain_caip2: privy::caip2::Caip2::SOLANA.to_string(),
+            amount: PARENT_OUTPUT.to_string(),
+            ..Default::default()
+        };
+        let mut parent = PipelineStep {
+            id: Uuid::new_v4(),
+            action: Action::Order(SwapOrder {
+                input_token: SOLANA_NATIVE_MINTS[1].to_string(),
+                output_token: SOLANA_NATIVE_MINTS[0].to_string(),
+                amount: "2000000000".to_string(),
+                ..order.clone()
+            }),
+            conditions: vec![],
+            next_steps: vec![],
+            status: Status::Completed,
+            transaction_hash: Some(format!("{}{}", DRY_RUN_TX_PREFIX, Uuid::new_v4())),
+            error: None,
+            oco_group: None,
+            simulated_fill: None,
+            submitted_at: None,
+            reserved_usd: 0,
+            fill: None,
+            expires_at: None,
+            edited_at: None,
+            slices: Vec::new(),
+            remaining_amount: None,
+            rebalance: None,
+        };
+        assert!(matches!(
+            resolve_order_amount(&order, None, Some(OWNER), &[parent.clone()]).await,
+            Err(EngineError::AmountError(AmountError::InvalidParent(_)))
+        ));
+
+        parent.simulated_fill = Some(SimulatedFill {
+            amount: "2000000000".to_string(),
+            input_price: Some(150.0),
+            output_price: Some(300.0),
+            filled_at: chrono::Utc::now(),
+        });
+        let resolved = resolve_order_amount(&order, None, Some(OWNER), &[parent.clone()])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resolved.amount, "1000000000");
+
+        // not priced when the fill was simulated
+        parent.simulated_fill.as_mut().unwrap().output_price = None;
+        assert!(matches!(
+            resolve_order_amount(&order, None, Some(OWNER), &[parent]).await,
+            Err(EngineError::AmountError(AmountError::InvalidParent(_)))
+        ));
+        assert!(matches!(
+            resolve_order_amount(&order, None, Some(OWNER), &[]).await,
+            Err(EngineError::AmountError(AmountError::InvalidParent(_)))
+        ));
+    }
+}
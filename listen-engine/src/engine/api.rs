@@ -1,12 +1,23 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
 use serde::Deserialize;
-use std::collections::HashMap;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::str::FromStr;
 use uuid::Uuid;
 
+use super::amount::{OrderAmount, PARENT_OUTPUT};
+use super::copy::{mirrors_leader, watches_wallet, LEADER_MINT};
+use super::market::MAX_VOLUME_WINDOW_SECS;
+use super::notifications::webhook::is_public;
 use super::order::SwapOrder;
 use super::pipeline::{
-    Action, Condition, ConditionType, Notification, Pipeline, PipelineStep, Status,
+    Action, Condition, ConditionType, Notification, NotificationTarget, Pipeline, PipelineStep,
+    PriceGuard, PriceSource, Rebalance, Status, TradeSide,
 };
+use super::slices::{MAX_SLICED_DURATION_SECS, MAX_SLICES};
+use crate::jup::PriorityFee;
 
 #[derive(Debug, Deserialize)]
 pub enum WireActionType {
@@ -22,47 +33,531 @@ pub enum WireConditionType {
     PriceAbove,
     #[serde(rename = "PriceBelow")]
     PriceBelow,
+    #[serde(rename = "TrailingStop")]
+    TrailingStop,
+    #[serde(rename = "MarketCapAbove")]
+    MarketCapAbove,
+    #[serde(rename = "MarketCapBelow")]
+    MarketCapBelow,
+    #[serde(rename = "RollingVolumeAbove")]
+    RollingVolumeAbove,
+    #[serde(rename = "LargeBuy")]
+    LargeBuy,
+    #[serde(rename = "WalletTraded")]
+    WalletTraded,
     #[serde(rename = "Now")]
     Now,
+    #[serde(rename = "At")]
+    At,
+    #[serde(rename = "After")]
+    After,
+    #[serde(rename = "Cron")]
+    Cron,
+    #[serde(rename = "And")]
+    And,
+    #[serde(rename = "Or")]
+    Or,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WireSwapOrder {
+    pub input_token: String,
+    pub output_token: String,
+    pub amount: String,
+    #[serde(default)]
+    pub from_chain_caip2: Option<String>,
+    #[serde(default)]
+    pub to_chain_caip2: Option<String>,
+    #[serde(default)]
+    pub slippage_bps: Option<u16>,
+    #[serde(default)]
+    pub priority_fee: Option<PriorityFee>,
+    #[serde(default)]
+    pub max_priority_fee_lamports: Option<u64>,
+    #[serde(default)]
+    pub max_price_impact_pct: Option<f64>,
+    /// RFC 3339 timestamp after which the order is no longer executed
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+impl WireSwapOrder {
+    fn validate(&self) -> Result<(), WirePipelineError> {
+        self.amount
+            .parse::<OrderAmount>()
+            .map_err(|e| WirePipelineError::InvalidOrder(e.to_string()))?;
+        if self.slippage_bps.is_some_and(|bps| bps > 10_000) {
+            return Err(WirePipelineError::InvalidOrder(
+                "slippage_bps must be at most 10000".to_string(),
+            ));
+        }
+        if self
+            .max_price_impact_pct
+            .is_some_and(|pct| !(pct > 0.0 && pct <= 100.0))
+        {
+            return Err(WirePipelineError::InvalidOrder(
+                "max_price_impact_pct must be within (0, 100]".to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum WireAction {
     #[serde(rename = "SwapOrder")]
-    SwapOrder {
-        input_token: String,
-        output_token: String,
-        amount: String,
-        #[serde(default)]
-        from_chain_caip2: Option<String>,
-        #[serde(default)]
-        to_chain_caip2: Option<String>,
-    },
+    SwapOrder(WireSwapOrder),
     #[serde(rename = "Notification")]
     Notification {
         input_token: String,
         message: String,
+        /// delivery channel, the user's email when omitted
+        #[serde(default)]
+        channel: NotificationTarget,
+    },
+    /// `count` equal slices of the order, `interval` seconds apart
+    #[serde(rename = "Dca")]
+    Dca {
+        #[serde(flatten)]
+        order: WireSwapOrder,
+        interval: u64,
+        count: u32,
+    },
+    /// `slices` equal slices of the order, spread evenly over `duration` seconds
+    #[serde(rename = "Twap")]
+    Twap {
+        #[serde(flatten)]
+        order: WireSwapOrder,
+        duration: u64,
+        slices: u32,
+    },
+    /// Swaps the wallet's balances of the target mints towards their weights
+    #[serde(rename = "Rebalance")]
+    Rebalance {
+        /// mint -> target weight in percent, adding up to 100
+        targets: BTreeMap<String, f64>,
+        /// percentage points a weight may drift before rebalancing, the step
+        /// keeps watching the weights when set
+        #[serde(default)]
+        drift_pct: Option<f64>,
+        #[serde(default)]
+        slippage_bps: Option<u16>,
+        #[serde(default)]
+        max_price_impact_pct: Option<f64>,
     },
 }
 
+fn validate_slice_count(count: u32) -> Result<(), WirePipelineError> {
+    if count == 0 || count > MAX_SLICES {
+        return Err(WirePipelineError::InvalidOrder(format!(
+            "sliced orders need between 1 and {} slices",
+            MAX_SLICES
+        )));
+    }
+    Ok(())
+}
+
+fn validate_sliced_duration(duration: u64) -> Result<(), WirePipelineError> {
+    if duration > MAX_SLICED_DURATION_SECS {
+        return Err(WirePipelineError::InvalidOrder(format!(
+            "sliced orders can run for at most {} seconds",
+            MAX_SLICED_DURATION_SECS
+        )));
+    }
+    Ok(())
+}
+
+impl WireAction {
+    fn validate(&self) -> Result<(), WirePipelineError> {
+        let channel = match self {
+            WireAction::SwapOrder(order) => return order.validate(),
+            WireAction::Dca {
+                order,
+                interval,
+                count,
+            } => {
+                order.validate()?;
+                validate_slice_count(*count)?;
+                if *interval == 0 {
+                    return Err(WirePipelineError::InvalidOrder(
+                        "interval must be at least one second".to_string(),
+                    ));
+                }
+                validate_sliced_duration(interval.saturating_mul(*count as u64))?;
+                return Ok(());
+            }
+            WireAction::Twap {
+                order,
+                duration,
+                slices,
+            } => {
+                order.validate()?;
+                validate_slice_count(*slices)?;
+                if *duration < *slices as u64 {
+                    return Err(WirePipelineError::InvalidOrder(
+                        "duration must be at least one second per slice".to_string(),
+                    ));
+                }
+                validate_sliced_duration(*duration)?;
+                return Ok(());
+            }
+            WireAction::Rebalance {
+                targets,
+                drift_pct,
+                slippage_bps,
+                max_price_impact_pct,
+            } => {
+                if targets.len() < 2 {
+                    return Err(WirePipelineError::InvalidRebalance(
+                        "at least two target mints are required".to_string(),
+                    ));
+                }
+                if let Some(mint) = targets.keys().find(|mint| Pubkey::from_str(mint).is_err()) {
+                    return Err(WirePipelineError::InvalidRebalance(format!(
+                        "{} is not a Solana mint",
+                        mint
+                    )));
+                }
+                if !targets
+                    .values()
+                    .all(|weight| weight.is_finite() && *weight > 0.0)
+                {
+                    return Err(WirePipelineError::InvalidRebalance(
+                        "target weights must be positive".to_string(),
+                    ));
+                }
+                if (targets.values().sum::<f64>() - 100.0).abs() > 0.01 {
+                    return Err(WirePipelineError::InvalidRebalance(
+                        "target weights must add up to 100".to_string(),
+                    ));
+                }
+                if drift_pct.is_some_and(|pct| !(pct > 0.0 && pct < 100.0)) {
+                    return Err(WirePipelineError::InvalidRebalance(
+                        "drift_pct must be within (0, 100)".to_string(),
+                    ));
+                }
+                if slippage_bps.is_some_and(|bps| bps > 10_000) {
+                    return Err(WirePipelineError::InvalidRebalance(
+                        "slippage_bps must be at most 10000".to_string(),
+                    ));
+                }
+                if max_price_impact_pct.is_some_and(|pct| !(pct > 0.0 && pct <= 100.0)) {
+                    return Err(WirePipelineError::InvalidRebalance(
+                        "max_price_impact_pct must be within (0, 100]".to_string(),
+                    ));
+                }
+                return Ok(());
+            }
+            WireAction::Notification { channel, .. } => channel,
+        };
+        match channel {
+            NotificationTarget::Email => Ok(()),
+            NotificationTarget::Webhook { url, secret } => {
+                // hostnames are resolved and checked on delivery, see
+                // `WebhookChannel`, addresses and localhost are caught here
+                let parsed = reqwest::Url::parse(url).map_err(|e| {
+                    WirePipelineError::InvalidNotificationChannel(format!(
+                        "invalid webhook url: {}",
+                        e
+                    ))
+                })?;
+                if parsed.scheme() != "https" {
+                    return Err(WirePipelineError::InvalidNotificationChannel(
+                        "webhook url must use https".to_string(),
+                    ));
+                }
+                let public = parsed.host_str().is_some_and(|host| {
+                    let host = host.trim_start_matches('[').trim_end_matches(']');
+                    match host.parse::<IpAddr>() {
+                        Ok(ip) => is_public(&ip),
+                        Err(_) => {
+                            let host = host.trim_end_matches('.');
+                            host != "localhost" && !host.ends_with(".localhost")
+                        }
+                    }
+                });
+                if !public {
+                    return Err(WirePipelineError::InvalidNotificationChannel(
+                        "webhook url must point to a public host".to_string(),
+                    ));
+                }
+                if secret.len() < 16 {
+                    return Err(WirePipelineError::InvalidNotificationChannel(
+                        "webhook secret must be at least 16 characters".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+            NotificationTarget::Telegram { chat_id } => {
+                // the bot can only message private chats of users who started
+                // it, groups and channels it was added to belong to others
+                if !chat_id.parse::<i64>().is_ok_and(|id| id > 0) {
+                    return Err(WirePipelineError::InvalidNotificationChannel(
+                        "telegram chat_id must be the id of a private chat with the bot"
+                            .to_string(),
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WireCondition {
     pub r#type: WireConditionType,
+    #[serde(default)]
     pub asset: String,
+    /// price, market cap or USD threshold, trail percentage, unix timestamp
+    /// (At) or seconds since creation (After), depending on the condition type
+    #[serde(default)]
     pub value: f64,
+    /// volume window in seconds, only used by the RollingVolumeAbove condition
+    #[serde(default)]
+    pub window: Option<u64>,
+    /// cron expression, only used by the Cron condition
+    #[serde(default)]
+    pub expr: Option<String>,
+    /// watched wallet and the side of its swaps, only used by the
+    /// WalletTraded condition, `value` is the minimum swap size in USD
+    #[serde(default)]
+    pub wallet: Option<String>,
+    #[serde(default)]
+    pub side: TradeSide,
+    /// max price age, confirmation count and price source, only used by
+    /// price and market cap conditions
+    #[serde(default)]
+    pub guard: PriceGuard,
+    /// sub-conditions, only used by the And and Or conditions
+    #[serde(default)]
+    pub conditions: Vec<WireCondition>,
+}
+
+/// Upper bound on `PriceGuard::confirmations`
+const MAX_PRICE_CONFIRMATIONS: u32 = 100;
+
+/// Upper bound on the delay of an After condition, ten years
+const MAX_AFTER_SECS: f64 = 10.0 * 365.0 * 24.0 * 60.0 * 60.0;
+
+impl WireCondition {
+    fn validate_guard(&self) -> Result<(), WirePipelineError> {
+        if self.guard == PriceGuard::default() {
+            return Ok(());
+        }
+        if !matches!(
+            self.r#type,
+            WireConditionType::PriceAbove
+                | WireConditionType::PriceBelow
+                | WireConditionType::TrailingStop
+                | WireConditionType::MarketCapAbove
+                | WireConditionType::MarketCapBelow
+        ) {
+            return Err(WirePipelineError::InvalidCondition(format!(
+                "{:?} does not take a price guard",
+                self.r#type
+            )));
+        }
+        if self.guard.max_age_secs == Some(0) {
+            return Err(WirePipelineError::InvalidCondition(
+                "max_age_secs must be at least one second".to_string(),
+            ));
+        }
+        if self
+            .guard
+            .confirmations
+            .is_some_and(|n| n == 0 || n > MAX_PRICE_CONFIRMATIONS)
+        {
+            return Err(WirePipelineError::InvalidCondition(format!(
+                "confirmations must be between 1 and {}",
+                MAX_PRICE_CONFIRMATIONS
+            )));
+        }
+        match self.guard.source {
+            PriceSource::Vwap { window } | PriceSource::Median { window }
+                if window == 0 || window > MAX_VOLUME_WINDOW_SECS =>
+            {
+                Err(WirePipelineError::InvalidCondition(format!(
+                    "price window must be between 1 and {} seconds",
+                    MAX_VOLUME_WINDOW_SECS
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validate(&self) -> Result<(), WirePipelineError> {
+        self.validate_guard()?;
+        match self.r#type {
+            WireConditionType::And | WireConditionType::Or => {
+                if self.conditions.is_empty() {
+                    return Err(WirePipelineError::InvalidCondition(format!(
+                        "{:?} requires at least one sub-condition",
+                        self.r#type
+                    )));
+                }
+                self.conditions.iter().try_for_each(WireCondition::validate)
+            }
+            WireConditionType::At => {
+                if !self.value.is_finite()
+                    || self.value < 0.0
+                    || DateTime::from_timestamp(self.value as i64, 0).is_none()
+                {
+                    return Err(WirePipelineError::InvalidCondition(format!(
+                        "At requires a unix timestamp, got {}",
+                        self.value
+                    )));
+                }
+                Ok(())
+            }
+            WireConditionType::After => {
+                if !(self.value.is_finite() && (0.0..=MAX_AFTER_SECS).contains(&self.value)) {
+                    return Err(WirePipelineError::InvalidCondition(format!(
+                        "After requires a delay between 0 and {} seconds, got {}",
+                        MAX_AFTER_SECS, self.value
+                    )));
+                }
+                Ok(())
+            }
+            WireConditionType::Cron => match self.expr.as_deref().map(Schedule::from_str) {
+                Some(Ok(_)) => Ok(()),
+                Some(Err(e)) => Err(WirePipelineError::InvalidCondition(format!(
+                    "invalid cron expression: {}",
+                    e
+                ))),
+                None => Err(WirePipelineError::InvalidCondition(
+                    "Cron requires an expr".to_string(),
+                )),
+            },
+            WireConditionType::TrailingStop => {
+                if !(self.value > 0.0 && self.value < 100.0) {
+                    return Err(WirePipelineError::InvalidCondition(format!(
+                        "trail percentage must be between 0 and 100, got {}",
+                        self.value
+                    )));
+                }
+                Ok(())
+            }
+            WireConditionType::RollingVolumeAbove => match self.window {
+                Some(window) if (1..=MAX_VOLUME_WINDOW_SECS).contains(&window) => Ok(()),
+                _ => Err(WirePipelineError::InvalidCondition(format!(
+                    "volume window must be between 1 and {} seconds",
+                    MAX_VOLUME_WINDOW_SECS
+                ))),
+            },
+            WireConditionType::WalletTraded => match self.wallet.as_deref().map(Pubkey::from_str) {
+                Some(Ok(_)) => Ok(()),
+                _ => Err(WirePipelineError::InvalidCondition(
+                    "WalletTraded requires a Solana wallet".to_string(),
+                )),
+            },
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct WireStep {
+    /// client-side id, used to reference the step in `depends_on`
+    #[serde(default)]
+    pub id: Option<String>,
     pub action: WireAction,
     #[serde(default)]
     pub conditions: Vec<WireCondition>,
+    /// ids of the steps that have to complete before this one is evaluated
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// one-cancels-other group, sibling steps sharing a group are mutually
+    /// exclusive, e.g. a take-profit and a stop-loss
+    #[serde(default)]
+    pub oco_group: Option<String>,
+    /// good-till-time, the step and its downstream steps are cancelled if it
+    /// hasn't executed by then
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct WirePipeline {
     pub steps: Vec<WireStep>,
+    /// simulate orders at cached prices instead of sending transactions
+    #[serde(default)]
+    pub dry_run: bool,
+    /// the pipeline is cancelled once this time has passed
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Changes to a pending step, omitted fields are left as they are
+#[derive(Debug, Deserialize)]
+pub struct WireStepEdit {
+    /// replaces all of the step's conditions, e.g. to move a stop
+    #[serde(default)]
+    pub conditions: Option<Vec<WireCondition>>,
+    /// only for swap orders
+    #[serde(default)]
+    pub amount: Option<String>,
+    /// only for notifications
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl WireStepEdit {
+    /// Applies the edit to the given step of the pipeline, the step has to exist
+    pub fn apply(&self, pipeline: &mut Pipeline, step_id: Uuid) -> Result<(), WirePipelineError> {
+        if self.conditions.is_none() && self.amount.is_none() && self.message.is_none() {
+            return Err(WirePipelineError::InvalidStepEdit(
+                "nothing to edit".to_string(),
+            ));
+        }
+        if let Some(conditions) = &self.conditions {
+            conditions.iter().try_for_each(WireCondition::validate)?;
+        }
+
+        let Some(step) = pipeline.steps.get_mut(&step_id) else {
+            return Err(WirePipelineError::InvalidStepEdit(format!(
+                "unknown step {}",
+                step_id
+            )));
+        };
+        // the conditions of sliced orders have already been met
+        if step.is_slicing() {
+            return Err(WirePipelineError::InvalidStepEdit(
+                "the sliced order has already started".to_string(),
+            ));
+        }
+        match (&mut step.action, &self.amount, &self.message) {
+            (action, Some(_), _) if action.order().is_none() => {
+                return Err(WirePipelineError::InvalidStepEdit(
+                    "amount can only be set on swap orders".to_string(),
+                ));
+            }
+            (Action::Notification(notification), None, Some(message)) => {
+                notification.message = message.clone();
+            }
+            (_, _, Some(_)) => {
+                return Err(WirePipelineError::InvalidStepEdit(
+                    "message can only be set on notifications".to_string(),
+                ));
+            }
+            (action, Some(amount), None) => {
+                amount
+                    .parse::<OrderAmount>()
+                    .map_err(|e| WirePipelineError::InvalidOrder(e.to_string()))?;
+                if let Some(order) = action.order_mut() {
+                    order.amount = amount.clone();
+                }
+            }
+            _ => {}
+        }
+        if let Some(conditions) = &self.conditions {
+            step.conditions = step_conditions(conditions);
+        }
+        step.edited_at = Some(Utc::now());
+
+        validate_parent_outputs(&pipeline.steps)?;
+        validate_copy_orders(&pipeline.steps)
+    }
 }
 
 pub struct PipelineParams {
@@ -71,44 +566,149 @@ pub struct PipelineParams {
     pub pubkey: Option<String>,
 }
 
-impl From<(WirePipeline, PipelineParams)> for Pipeline {
-    fn from((wire, params): (WirePipeline, PipelineParams)) -> Self {
-        let mut steps: HashMap<Uuid, PipelineStep> = HashMap::new();
-        let mut current_steps = Vec::new();
+#[derive(Debug, thiserror::Error)]
+pub enum WirePipelineError {
+    #[error("Duplicate step id: {0}")]
+    DuplicateStepId(String),
 
-        let step_ids: Vec<Uuid> = wire.steps.iter().map(|_| Uuid::new_v4()).collect();
+    #[error("Step {step} depends on unknown step {dependency}")]
+    UnknownDependency { step: String, dependency: String },
 
-        let now_step_indices: Vec<usize> = wire
-            .steps
-            .iter()
-            .enumerate()
-            .filter(|(_, step)| {
-                step.conditions
-                    .iter()
-                    .any(|c| matches!(c.r#type, WireConditionType::Now))
-            })
-            .map(|(i, _)| i)
-            .collect();
+    #[error("Step dependencies contain a cycle, involving steps: {0}")]
+    CyclicDependency(String),
 
-        if now_step_indices.is_empty() && !wire.steps.is_empty() {
-            current_steps.push(step_ids[step_ids.len() - 1]);
-        } else {
-            for &idx in &now_step_indices {
-                current_steps.push(step_ids[idx]);
+    #[error("Invalid condition: {0}")]
+    InvalidCondition(String),
+
+    #[error("Invalid OCO group: {0}")]
+    InvalidOcoGroup(String),
+
+    #[error("Invalid notification channel: {0}")]
+    InvalidNotificationChannel(String),
+
+    #[error("Invalid order: {0}")]
+    InvalidOrder(String),
+
+    #[error("Invalid expiry: {0}")]
+    InvalidExpiry(String),
+
+    #[error("Invalid step edit: {0}")]
+    InvalidStepEdit(String),
+
+    #[error("Invalid rebalance: {0}")]
+    InvalidRebalance(String),
+}
+
+impl WirePipeline {
+    /// Pipelines where steps carry ids or dependencies are linked as a graph,
+    /// otherwise the steps are chained in order
+    fn is_graph(&self) -> bool {
+        self.steps.iter().any(|step| {
+            step.id.is_some() || !step.depends_on.is_empty() || step.oco_group.is_some()
+        })
+    }
+
+    /// OCO groups need at least two steps, all with the same dependencies
+    fn validate_oco_groups(&self) -> Result<(), WirePipelineError> {
+        let mut groups: HashMap<&str, Vec<&WireStep>> = HashMap::new();
+        for step in &self.steps {
+            if let Some(group) = &step.oco_group {
+                groups.entry(group.as_str()).or_default().push(step);
             }
         }
 
-        for (i, (step, id)) in wire.steps.iter().zip(step_ids.iter()).enumerate() {
-            let mut pipeline_step: PipelineStep = step.into();
-
-            if !now_step_indices.contains(&i) && i > 0 {
-                pipeline_step.next_steps.push(step_ids[i - 1]);
+        for (group, steps) in groups {
+            if steps.len() < 2 {
+                return Err(WirePipelineError::InvalidOcoGroup(format!(
+                    "{} needs at least two steps",
+                    group
+                )));
+            }
+            let dependencies = |step: &WireStep| {
+                let mut deps = step.depends_on.clone();
+                deps.sort();
+                deps.dedup();
+                deps
+            };
+            let first = dependencies(steps[0]);
+            if steps.iter().any(|step| dependencies(step) != first) {
+                return Err(WirePipelineError::InvalidOcoGroup(format!(
+                    "steps in {} must have the same dependencies",
+                    group
+                )));
             }
+        }
+
+        Ok(())
+    }
 
-            steps.insert(*id, pipeline_step);
+    fn validate_expiries(&self, now: DateTime<Utc>) -> Result<(), WirePipelineError> {
+        let expiries = self.expires_at.iter().chain(
+            self.steps
+                .iter()
+                .filter_map(|step| step.expires_at.as_ref()),
+        );
+        for expires_at in expiries {
+            if *expires_at <= now {
+                return Err(WirePipelineError::InvalidExpiry(format!(
+                    "{} is in the past",
+                    expires_at
+                )));
+            }
         }
+        Ok(())
+    }
+}
 
-        Pipeline {
+impl TryFrom<(WirePipeline, PipelineParams)> for Pipeline {
+    type Error = WirePipelineError;
+
+    fn try_from((wire, params): (WirePipeline, PipelineParams)) -> Result<Self, Self::Error> {
+        wire.into_pipeline(params, Utc::now())
+    }
+}
+
+impl WirePipeline {
+    /// Builds the pipeline as if it was created at `created_at`, backtests
+    /// create their pipelines at the start of the replayed range
+    pub fn into_pipeline(
+        self,
+        params: PipelineParams,
+        created_at: DateTime<Utc>,
+    ) -> Result<Pipeline, WirePipelineError> {
+        let wire = self;
+        wire.steps
+            .iter()
+            .flat_map(|step| step.conditions.iter())
+            .try_for_each(WireCondition::validate)?;
+        wire.validate_oco_groups()?;
+        wire.validate_expiries(created_at)?;
+        wire.steps
+            .iter()
+            .try_for_each(|step| step.action.validate())?;
+
+        let step_ids: Vec<Uuid> = wire.steps.iter().map(|_| Uuid::new_v4()).collect();
+
+        let mut steps: HashMap<Uuid, PipelineStep> = wire
+            .steps
+            .iter()
+            .zip(step_ids.iter())
+            .map(|(step, id)| {
+                let mut pipeline_step: PipelineStep = step.into();
+                pipeline_step.id = *id;
+                (*id, pipeline_step)
+            })
+            .collect();
+
+        let current_steps = if wire.is_graph() {
+            link_dependencies(&wire.steps, &step_ids, &mut steps)?
+        } else {
+            link_sequential(&wire.steps, &step_ids, &mut steps)
+        };
+        validate_parent_outputs(&steps)?;
+        validate_copy_orders(&steps)?;
+
+        Ok(Pipeline {
             id: Uuid::new_v4(),
             user_id: params.user_id,
             wallet_address: params.wallet_address,
@@ -116,33 +716,226 @@ impl From<(WirePipeline, PipelineParams)> for Pipeline {
             current_steps,
             steps,
             status: Status::Pending,
-            created_at: Utc::now(),
+            created_at,
+            dry_run: wire.dry_run,
+            expires_at: wire.expires_at,
+        })
+    }
+}
+
+/// Chains each step to the one before it, returns the entry steps
+fn link_sequential(
+    wire_steps: &[WireStep],
+    step_ids: &[Uuid],
+    steps: &mut HashMap<Uuid, PipelineStep>,
+) -> Vec<Uuid> {
+    let mut current_steps = Vec::new();
+
+    let now_step_indices: Vec<usize> = wire_steps
+        .iter()
+        .enumerate()
+        .filter(|(_, step)| {
+            step.conditions
+                .iter()
+                .any(|c| matches!(c.r#type, WireConditionType::Now))
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    if now_step_indices.is_empty() && !wire_steps.is_empty() {
+        current_steps.push(step_ids[step_ids.len() - 1]);
+    } else {
+        for &idx in &now_step_indices {
+            current_steps.push(step_ids[idx]);
+        }
+    }
+
+    for (i, id) in step_ids.iter().enumerate() {
+        if !now_step_indices.contains(&i) && i > 0 {
+            if let Some(step) = steps.get_mut(id) {
+                step.next_steps.push(step_ids[i - 1]);
+            }
         }
     }
+
+    current_steps
 }
 
-impl From<&WireStep> for PipelineStep {
-    fn from(wire: &WireStep) -> Self {
-        let conditions = if wire.conditions.is_empty() {
-            vec![Condition {
-                condition_type: ConditionType::Now {
-                    asset: String::new(),
-                },
-                triggered: false,
-                last_evaluated: None,
-            }]
-        } else {
-            wire.conditions.iter().map(Into::into).collect()
+/// Links steps according to their `depends_on` lists, validating that all
+/// references exist and that there are no cycles. Returns the entry steps,
+/// i.e. the ones without dependencies.
+fn link_dependencies(
+    wire_steps: &[WireStep],
+    step_ids: &[Uuid],
+    steps: &mut HashMap<Uuid, PipelineStep>,
+) -> Result<Vec<Uuid>, WirePipelineError> {
+    let step_name = |i: usize| {
+        wire_steps[i]
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("#{}", i))
+    };
+
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (i, step) in wire_steps.iter().enumerate() {
+        if let Some(id) = &step.id {
+            if index.insert(id.as_str(), i).is_some() {
+                return Err(WirePipelineError::DuplicateStepId(id.clone()));
+            }
+        }
+    }
+
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); wire_steps.len()];
+    let mut in_degree = vec![0usize; wire_steps.len()];
+    for (i, step) in wire_steps.iter().enumerate() {
+        for dependency in &step.depends_on {
+            let parent = *index.get(dependency.as_str()).ok_or_else(|| {
+                WirePipelineError::UnknownDependency {
+                    step: step_name(i),
+                    dependency: dependency.clone(),
+                }
+            })?;
+            if !children[parent].contains(&i) {
+                children[parent].push(i);
+                in_degree[i] += 1;
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..wire_steps.len())
+        .filter(|i| in_degree[*i] == 0)
+        .collect();
+
+    // Kahn's algorithm, any step left with unresolved dependencies is in a cycle
+    let mut remaining = in_degree;
+    let mut queue = roots.clone();
+    while let Some(i) = queue.pop() {
+        for &child in &children[i] {
+            remaining[child] -= 1;
+            if remaining[child] == 0 {
+                queue.push(child);
+            }
+        }
+    }
+    let cyclic: Vec<String> = (0..wire_steps.len())
+        .filter(|i| remaining[*i] > 0)
+        .map(step_name)
+        .collect();
+    if !cyclic.is_empty() {
+        return Err(WirePipelineError::CyclicDependency(cyclic.join(", ")));
+    }
+
+    for (parent, children) in children.iter().enumerate() {
+        if let Some(step) = steps.get_mut(&step_ids[parent]) {
+            step.next_steps = children.iter().map(|child| step_ids[*child]).collect();
+        }
+    }
+
+    Ok(roots.into_iter().map(|i| step_ids[i]).collect())
+}
+
+/// Orders using the parent's output need a single parent order
+fn validate_parent_outputs(steps: &HashMap<Uuid, PipelineStep>) -> Result<(), WirePipelineError> {
+    for (id, step) in steps {
+        let Some(order) = step.action.order() else {
+            continue;
         };
+        if !matches!(order.amount.parse(), Ok(OrderAmount::ParentOutput)) {
+            continue;
+        }
+        let parents: Vec<&PipelineStep> = steps
+            .values()
+            .filter(|parent| parent.next_steps.contains(id))
+            .collect();
+        if !matches!(parents.as_slice(), [parent] if matches!(parent.action, Action::Order(_))) {
+            return Err(WirePipelineError::InvalidOrder(format!(
+                "{} needs exactly one parent swap order",
+                PARENT_OUTPUT
+            )));
+        }
+    }
+    Ok(())
+}
 
+/// Orders mirroring a trade need the WalletTraded condition that copies it
+fn validate_copy_orders(steps: &HashMap<Uuid, PipelineStep>) -> Result<(), WirePipelineError> {
+    for step in steps.values() {
+        let Some(order) = step.action.order() else {
+            continue;
+        };
+        if mirrors_leader(order) && !watches_wallet(&step.conditions) {
+            return Err(WirePipelineError::InvalidOrder(format!(
+                "{} and leader sized amounts need a WalletTraded condition",
+                LEADER_MINT
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Steps without conditions execute right away
+fn step_conditions(wire: &[WireCondition]) -> Vec<Condition> {
+    if wire.is_empty() {
+        vec![Condition {
+            condition_type: ConditionType::Now {
+                asset: String::new(),
+            },
+            triggered: false,
+            last_evaluated: None,
+            guard: PriceGuard::default(),
+            confirmations: 0,
+            observed: None,
+        }]
+    } else {
+        wire.iter().map(Into::into).collect()
+    }
+}
+
+impl From<&WireStep> for PipelineStep {
+    fn from(wire: &WireStep) -> Self {
         PipelineStep {
             id: Uuid::new_v4(),
             action: (&wire.action).into(),
-            conditions,
+            conditions: step_conditions(&wire.conditions),
             next_steps: Vec::new(),
             status: Status::Pending,
             transaction_hash: None,
             error: None,
+            oco_group: wire.oco_group.clone(),
+            simulated_fill: None,
+            submitted_at: None,
+            reserved_usd: 0,
+            fill: None,
+            expires_at: wire.expires_at,
+            edited_at: None,
+            slices: Vec::new(),
+            remaining_amount: None,
+            rebalance: None,
+        }
+    }
+}
+
+impl From<&WireSwapOrder> for SwapOrder {
+    fn from(wire: &WireSwapOrder) -> Self {
+        const DEFAULT_SOLANA_CHAIN: &str = "solana:5eykt4UsFv8P8NJdTREpY1vzqKqZKvdp";
+
+        SwapOrder {
+            input_token: wire.input_token.clone(),
+            output_token: wire.output_token.clone(),
+            amount: wire.amount.clone(),
+            from_chain_caip2: wire
+                .from_chain_caip2
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SOLANA_CHAIN.to_string()),
+            to_chain_caip2: wire
+                .to_chain_caip2
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SOLANA_CHAIN.to_string()),
+            slippage_bps: wire.slippage_bps,
+            priority_fee: wire.priority_fee,
+            max_priority_fee_lamports: wire.max_priority_fee_lamports,
+            max_price_impact_pct: wire.max_price_impact_pct,
+            deadline: wire.deadline,
         }
     }
 }
@@ -150,29 +943,41 @@ impl From<&WireStep> for PipelineStep {
 impl From<&WireAction> for Action {
     fn from(wire: &WireAction) -> Self {
         match wire {
-            WireAction::SwapOrder {
-                input_token,
-                output_token,
-                amount,
-                from_chain_caip2,
-                to_chain_caip2,
-            } => {
-                const DEFAULT_SOLANA_CHAIN: &str = "solana:5eykt4UsFv8P8NJdTREpY1vzqKqZKvdp";
-
-                Action::Order(SwapOrder {
-                    input_token: input_token.clone(),
-                    output_token: output_token.clone(),
-                    amount: amount.clone(),
-                    from_chain_caip2: from_chain_caip2
-                        .clone()
-                        .unwrap_or_else(|| DEFAULT_SOLANA_CHAIN.to_string()),
-                    to_chain_caip2: to_chain_caip2
-                        .clone()
-                        .unwrap_or_else(|| DEFAULT_SOLANA_CHAIN.to_string()),
-                })
-            }
-            WireAction::Notification { message, .. } => Action::Notification(Notification {
+            WireAction::SwapOrder(order) => Action::Order(order.into()),
+            WireAction::Notification {
+                message, channel, ..
+            } => Action::Notification(Notification {
                 message: message.clone(),
+                channel: channel.clone(),
+            }),
+            WireAction::Dca {
+                order,
+                interval,
+                count,
+            } => Action::Dca {
+                order: order.into(),
+                interval: *interval,
+                count: *count,
+            },
+            WireAction::Twap {
+                order,
+                duration,
+                slices,
+            } => Action::Twap {
+                order: order.into(),
+                duration: *duration,
+                slices: *slices,
+            },
+            WireAction::Rebalance {
+                targets,
+                drift_pct,
+                slippage_bps,
+                max_price_impact_pct,
+            } => Action::Rebalance(Rebalance {
+                targets: targets.clone(),
+                drift_pct: *drift_pct,
+                slippage_bps: *slippage_bps,
+                max_price_impact_pct: *max_price_impact_pct,
             }),
         }
     }
@@ -189,15 +994,63 @@ impl From<&WireCondition> for Condition {
                 asset: wire.asset.clone(),
                 value: wire.value,
             },
+            WireConditionType::MarketCapAbove => ConditionType::MarketCapAbove {
+                asset: wire.asset.clone(),
+                value: wire.value,
+            },
+            WireConditionType::MarketCapBelow => ConditionType::MarketCapBelow {
+                asset: wire.asset.clone(),
+                value: wire.value,
+            },
+            WireConditionType::RollingVolumeAbove => ConditionType::RollingVolumeAbove {
+                asset: wire.asset.clone(),
+                window: wire.window.unwrap_or_default(),
+                value: wire.value,
+            },
+            WireConditionType::LargeBuy => ConditionType::LargeBuy {
+                asset: wire.asset.clone(),
+                min_usd: wire.value,
+            },
+            WireConditionType::WalletTraded => ConditionType::WalletTraded {
+                wallet: wire.wallet.clone().unwrap_or_default(),
+                side: wire.side,
+                min_usd: wire.value,
+                trade: None,
+            },
+            // for trailing stops, value is the trail percentage (e.g. 10.0 for 10%)
+            WireConditionType::TrailingStop => ConditionType::TrailingStop {
+                asset: wire.asset.clone(),
+                trail_pct: wire.value,
+                peak: None,
+            },
             WireConditionType::Now => ConditionType::Now {
                 asset: wire.asset.clone(),
             },
+            WireConditionType::At => ConditionType::At {
+                timestamp: DateTime::from_timestamp(wire.value as i64, 0).unwrap_or_default(),
+            },
+            WireConditionType::After => ConditionType::After {
+                duration_since_created: wire.value as u64,
+            },
+            WireConditionType::Cron => ConditionType::Cron {
+                expr: wire.expr.clone().unwrap_or_default(),
+                next_run: None,
+            },
+            WireConditionType::And => {
+                ConditionType::And(wire.conditions.iter().map(Into::into).collect())
+            }
+            WireConditionType::Or => {
+                ConditionType::Or(wire.conditions.iter().map(Into::into).collect())
+            }
         };
 
         Condition {
             condition_type,
             triggered: false,
             last_evaluated: None,
+            guard: wire.guard.clone(),
+            confirmations: 0,
+            observed: None,
         }
     }
 }
@@ -205,6 +1058,7 @@ impl From<&WireCondition> for Condition {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::constants::TEST_ADDRESS_SOL;
     use serde_json::json;
 
     #[test]
@@ -219,13 +1073,14 @@ mod tests {
         let wire_action: WireAction = serde_json::from_value(json).unwrap();
 
         match &wire_action {
-            WireAction::SwapOrder {
+            WireAction::SwapOrder(WireSwapOrder {
                 input_token,
                 output_token,
                 amount,
                 from_chain_caip2,
                 to_chain_caip2,
-            } => {
+                ..
+            }) => {
                 assert_eq!(input_token, "SOL");
                 assert_eq!(output_token, "USDC");
                 assert_eq!(amount, "1.0");
@@ -273,6 +1128,125 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dca_deserialize() {
+        let dca = |count: u32| {
+            serde_json::from_value::<WireAction>(json!({
+                "type": "Dca",
+                "input_token": "SOL",
+                "output_token": "USDC",
+                "amount": "1000000",
+                "interval": 3600,
+                "count": count
+            }))
+            .unwrap()
+        };
+
+        let wire = dca(24);
+        assert!(wire.validate().is_ok());
+        let action: Action = (&wire).into();
+        assert_eq!(action.slicing(), Some((24, 3600)));
+        assert_eq!(action.order().unwrap().amount, "1000000");
+
+        assert!(matches!(
+            dca(0).validate(),
+            Err(WirePipelineError::InvalidOrder(_))
+        ));
+        assert!(matches!(
+            dca(MAX_SLICES + 1).validate(),
+            Err(WirePipelineError::InvalidOrder(_))
+        ));
+
+        // 500 days of daily buys
+        let wire: WireAction = serde_json::from_value(json!({
+            "type": "Dca",
+            "input_token": "SOL",
+            "output_token": "USDC",
+            "amount": "1000000",
+            "interval": 86400,
+            "count": 500
+        }))
+        .unwrap();
+        assert!(matches!(
+            wire.validate(),
+            Err(WirePipelineError::InvalidOrder(_))
+        ));
+    }
+
+    #[test]
+    fn test_price_guard_deserialize() {
+        let condition = |r#type: &str, guard: serde_json::Value| {
+            serde_json::from_value::<WireCondition>(json!({
+                "type": r#type,
+                "asset": "So11111111111111111111111111111111111111112",
+                "value": 100.0,
+                "guard": guard
+            }))
+            .unwrap()
+        };
+
+        let wire = condition(
+            "PriceBelow",
+            json!({
+                "max_age_secs": 30,
+                "confirmations": 3,
+                "source": { "type": "Median", "window": 60 }
+            }),
+        );
+        assert!(wire.validate().is_ok());
+        let condition_guard = Condition::from(&wire).guard;
+        assert_eq!(condition_guard.confirmations, Some(3));
+        assert_eq!(condition_guard.source, PriceSource::Median { window: 60 });
+
+        for (r#type, guard) in [
+            ("PriceBelow", json!({ "confirmations": 0 })),
+            (
+                "PriceBelow",
+                json!({ "source": { "type": "Vwap", "window": 0 } }),
+            ),
+            ("RollingVolumeAbove", json!({ "max_age_secs": 30 })),
+        ] {
+            assert!(matches!(
+                condition(r#type, guard).validate(),
+                Err(WirePipelineError::InvalidCondition(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rebalance_deserialize() {
+        let rebalance = |targets: serde_json::Value| {
+            serde_json::from_value::<WireAction>(json!({
+                "type": "Rebalance",
+                "targets": targets,
+                "drift_pct": 5.0
+            }))
+            .unwrap()
+        };
+        let sol = "So11111111111111111111111111111111111111112";
+        let usdc = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+        let wire = rebalance(json!({ sol: 60.0, usdc: 40.0 }));
+        assert!(wire.validate().is_ok());
+        let Action::Rebalance(action) = Action::from(&wire) else {
+            panic!("expected a rebalance");
+        };
+        assert_eq!(action.targets[sol], 60.0);
+        assert_eq!(action.drift_pct, Some(5.0));
+
+        for targets in [
+            json!({ sol: 100.0 }),
+            json!({ sol: 60.0, usdc: 30.0 }),
+            json!({ sol: 110.0, usdc: -10.0 }),
+            json!({ sol: 50.0, "SOL": 50.0 }),
+        ] {
+            assert!(matches!(
+                rebalance(targets).validate(),
+                Err(WirePipelineError::InvalidRebalance(_))
+            ));
+        }
+    }
+
     #[test]
     fn test_wire_step_with_no_conditions() {
         let json = json!({
@@ -297,4 +1271,530 @@ mod tests {
             _ => panic!("Expected Now condition type"),
         }
     }
+
+    #[test]
+    fn test_wire_trailing_stop_condition() {
+        let json = json!({
+            "type": "TrailingStop",
+            "asset": "So11111111111111111111111111111111111111112",
+            "value": 15.0
+        });
+
+        let wire_condition: WireCondition = serde_json::from_value(json).unwrap();
+        let condition: Condition = (&wire_condition).into();
+
+        match condition.condition_type {
+            ConditionType::TrailingStop {
+                asset,
+                trail_pct,
+                peak,
+            } => {
+                assert_eq!(asset, "So11111111111111111111111111111111111111112");
+                assert_eq!(trail_pct, 15.0);
+                assert_eq!(peak, None);
+            }
+            _ => panic!("Expected TrailingStop condition type"),
+        }
+    }
+
+    #[test]
+    fn test_wire_time_conditions() {
+        let at: WireCondition =
+            serde_json::from_value(json!({ "type": "At", "value": 1735689600 })).unwrap();
+        match Condition::from(&at).condition_type {
+            ConditionType::At { timestamp } => assert_eq!(timestamp.timestamp(), 1735689600),
+            _ => panic!("Expected At condition type"),
+        }
+
+        let after: WireCondition =
+            serde_json::from_value(json!({ "type": "After", "value": 3600 })).unwrap();
+        match Condition::from(&after).condition_type {
+            ConditionType::After {
+                duration_since_created,
+            } => assert_eq!(duration_since_created, 3600),
+            _ => panic!("Expected After condition type"),
+        }
+
+        let cron: WireCondition =
+            serde_json::from_value(json!({ "type": "Cron", "expr": "0 0 * * * *" })).unwrap();
+        match Condition::from(&cron).condition_type {
+            ConditionType::Cron { expr, next_run } => {
+                assert_eq!(expr, "0 0 * * * *");
+                assert_eq!(next_run, None);
+            }
+            _ => panic!("Expected Cron condition type"),
+        }
+        for wire in [&at, &after, &cron] {
+            assert!(wire.validate().is_ok());
+        }
+
+        for condition in [
+            json!({ "type": "At", "value": -1.0 }),
+            json!({ "type": "At", "value": 1e300 }),
+            json!({ "type": "After", "value": -60.0 }),
+            json!({ "type": "After", "value": 1e18 }),
+            json!({ "type": "Cron" }),
+            json!({ "type": "Cron", "expr": "every hour" }),
+            json!({ "type": "TrailingStop", "asset": "A", "value": 0.0 }),
+            json!({ "type": "TrailingStop", "asset": "A", "value": 100.0 }),
+            json!({ "type": "RollingVolumeAbove", "asset": "A", "value": 1.0 }),
+            json!({ "type": "RollingVolumeAbove", "asset": "A", "window": 0, "value": 1.0 }),
+        ] {
+            let wire: WireCondition = serde_json::from_value(condition.clone()).unwrap();
+            assert!(
+                matches!(wire.validate(), Err(WirePipelineError::InvalidCondition(_))),
+                "{} should be rejected",
+                condition
+            );
+        }
+    }
+
+    fn params() -> PipelineParams {
+        PipelineParams {
+            user_id: "user".to_string(),
+            wallet_address: None,
+            pubkey: Some(TEST_ADDRESS_SOL.to_string()),
+        }
+    }
+
+    // the step id doubles as the amount, so steps can be found after conversion
+    fn swap_step(id: &str, depends_on: &[&str]) -> serde_json::Value {
+        json!({
+            "id": id,
+            "depends_on": depends_on,
+            "action": {
+                "type": "SwapOrder",
+                "input_token": "SOL",
+                "output_token": "USDC",
+                "amount": id
+            }
+        })
+    }
+
+    fn find_step(pipeline: &Pipeline, id: &str) -> Uuid {
+        *pipeline
+            .steps
+            .iter()
+            .find(|(_, step)| matches!(&step.action, Action::Order(o) if o.amount == id))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_wire_pipeline_fan_out() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [
+                swap_step("buy", &[]),
+                swap_step("take_profit", &["buy"]),
+                swap_step("stop_loss", &["buy"]),
+            ]
+        }))
+        .unwrap();
+        let pipeline = Pipeline::try_from((wire, params())).unwrap();
+
+        let buy = find_step(&pipeline, "buy");
+        let take_profit = find_step(&pipeline, "take_profit");
+        let stop_loss = find_step(&pipeline, "stop_loss");
+
+        assert_eq!(pipeline.current_steps, vec![buy]);
+        assert_eq!(pipeline.steps[&buy].id, buy);
+        assert_eq!(
+            pipeline.steps[&buy].next_steps,
+            vec![take_profit, stop_loss]
+        );
+        assert!(pipeline.steps[&take_profit].next_steps.is_empty());
+        assert!(pipeline.steps[&stop_loss].next_steps.is_empty());
+    }
+
+    #[test]
+    fn test_wire_pipeline_unknown_dependency() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap_step("buy", &[]), swap_step("sell", &["missing"])]
+        }))
+        .unwrap();
+
+        match Pipeline::try_from((wire, params())) {
+            Err(WirePipelineError::UnknownDependency { step, dependency }) => {
+                assert_eq!(step, "sell");
+                assert_eq!(dependency, "missing");
+            }
+            other => panic!("Expected UnknownDependency, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wire_pipeline_cycle() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [
+                swap_step("a", &[]),
+                swap_step("b", &["a", "c"]),
+                swap_step("c", &["b"]),
+            ]
+        }))
+        .unwrap();
+
+        match Pipeline::try_from((wire, params())) {
+            Err(WirePipelineError::CyclicDependency(steps)) => assert_eq!(steps, "b, c"),
+            other => panic!("Expected CyclicDependency, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wire_pipeline_duplicate_id() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap_step("a", &[]), swap_step("a", &[])]
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::DuplicateStepId(_))
+        ));
+    }
+
+    #[test]
+    fn test_wire_nested_conditions() {
+        let json = json!({
+            "type": "Or",
+            "conditions": [
+                {
+                    "type": "And",
+                    "conditions": [
+                        { "type": "PriceAbove", "asset": "A", "value": 2.0 },
+                        { "type": "PriceBelow", "asset": "B", "value": 1.0 }
+                    ]
+                },
+                { "type": "TrailingStop", "asset": "C", "value": 10.0 }
+            ]
+        });
+
+        let wire_condition: WireCondition = serde_json::from_value(json).unwrap();
+        let condition: Condition = (&wire_condition).into();
+
+        let ConditionType::Or(or) = &condition.condition_type else {
+            panic!("Expected Or condition type");
+        };
+        assert_eq!(or.len(), 2);
+        let ConditionType::And(and) = &or[0].condition_type else {
+            panic!("Expected And condition type");
+        };
+        assert!(matches!(
+            &and[0].condition_type,
+            ConditionType::PriceAbove { asset, value } if asset == "A" && *value == 2.0
+        ));
+        assert!(matches!(
+            &and[1].condition_type,
+            ConditionType::PriceBelow { asset, value } if asset == "B" && *value == 1.0
+        ));
+        assert!(matches!(
+            &or[1].condition_type,
+            ConditionType::TrailingStop { asset, .. } if asset == "C"
+        ));
+    }
+
+    #[test]
+    fn test_wire_empty_compound_condition() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [{
+                "action": {
+                    "type": "Notification",
+                    "input_token": "A",
+                    "message": "hello"
+                },
+                "conditions": [{
+                    "type": "And",
+                    "conditions": [{ "type": "Or" }]
+                }]
+            }]
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::InvalidCondition(_))
+        ));
+    }
+
+    #[test]
+    fn test_wire_pipeline_oco_bracket() {
+        let mut take_profit = swap_step("take_profit", &["buy"]);
+        take_profit["oco_group"] = json!("exit");
+        let mut stop_loss = swap_step("stop_loss", &["buy"]);
+        stop_loss["oco_group"] = json!("exit");
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap_step("buy", &[]), take_profit, stop_loss]
+        }))
+        .unwrap();
+        let mut pipeline = Pipeline::try_from((wire, params())).unwrap();
+
+        let take_profit = find_step(&pipeline, "take_profit");
+        let stop_loss = find_step(&pipeline, "stop_loss");
+        assert_eq!(
+            pipeline.steps[&stop_loss].oco_group.as_deref(),
+            Some("exit")
+        );
+
+        pipeline.steps.get_mut(&take_profit).unwrap().status = Status::Completed;
+        assert_eq!(pipeline.cancel_oco_siblings(take_profit), vec![stop_loss]);
+        assert!(matches!(
+            pipeline.steps[&stop_loss].status,
+            Status::Cancelled
+        ));
+        assert!(matches!(
+            pipeline.steps[&take_profit].status,
+            Status::Completed
+        ));
+    }
+
+    #[test]
+    fn test_wire_pipeline_invalid_oco_group() {
+        let mut take_profit = swap_step("take_profit", &["buy"]);
+        take_profit["oco_group"] = json!("exit");
+        let mut stop_loss = swap_step("stop_loss", &[]);
+        stop_loss["oco_group"] = json!("exit");
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap_step("buy", &[]), take_profit, stop_loss]
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::InvalidOcoGroup(_))
+        ));
+    }
+
+    #[test]
+    fn test_wire_notification_channel() {
+        let notification = |channel: serde_json::Value| {
+            serde_json::from_value::<WirePipeline>(json!({
+                "steps": [{
+                    "action": {
+                        "type": "Notification",
+                        "input_token": "",
+                        "message": "hello",
+                        "channel": channel
+                    },
+                    "conditions": []
+                }]
+            }))
+            .unwrap()
+        };
+
+        let wire = notification(json!({
+            "type": "Webhook",
+            "url": "https://example.com/hook",
+            "secret": "0123456789abcdef"
+        }));
+        let pipeline = Pipeline::try_from((wire, params())).unwrap();
+        let step = pipeline.steps.values().next().unwrap();
+        assert!(matches!(
+            &step.action,
+            Action::Notification(Notification {
+                channel: NotificationTarget::Webhook { url, .. },
+                ..
+            }) if url == "https://example.com/hook"
+        ));
+        // pipelines are logged with their steps
+        let logged = format!("{:?}", pipeline);
+        assert!(logged.contains("https://example.com/hook"));
+        assert!(!logged.contains("0123456789abcdef"));
+
+        let wire = notification(json!({
+            "type": "Webhook",
+            "url": "http://127.0.0.1:6901/internal/create_pipeline",
+            "secret": "0123456789abcdef"
+        }));
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::InvalidNotificationChannel(_))
+        ));
+
+        for url in [
+            "https://localhost/hook",
+            "https://10.0.0.5/hook",
+            "https://169.254.169.254/latest/meta-data",
+            "https://[::1]:6901/internal/create_pipeline",
+            "not a url",
+        ] {
+            let wire = notification(json!({
+                "type": "Webhook",
+                "url": url,
+                "secret": "0123456789abcdef"
+            }));
+            assert!(
+                matches!(
+                    Pipeline::try_from((wire, params())),
+                    Err(WirePipelineError::InvalidNotificationChannel(_))
+                ),
+                "{}",
+                url
+            );
+        }
+
+        let wire = notification(json!({ "type": "Telegram", "chat_id": "123456789" }));
+        assert!(Pipeline::try_from((wire, params())).is_ok());
+        for chat_id in ["", "@somechannel", "-1001234567890", "0", "12ab"] {
+            let wire = notification(json!({ "type": "Telegram", "chat_id": chat_id }));
+            assert!(
+                matches!(
+                    Pipeline::try_from((wire, params())),
+                    Err(WirePipelineError::InvalidNotificationChannel(_))
+                ),
+                "{}",
+                chat_id
+            );
+        }
+    }
+
+    #[test]
+    fn test_wire_pipeline_dry_run() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap_step("buy", &[])],
+            "dry_run": true
+        }))
+        .unwrap();
+        let pipeline = Pipeline::try_from((wire, params())).unwrap();
+        assert!(pipeline.dry_run);
+
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap_step("buy", &[])]
+        }))
+        .unwrap();
+        let pipeline = Pipeline::try_from((wire, params())).unwrap();
+        assert!(!pipeline.dry_run);
+    }
+
+    #[test]
+    fn test_wire_swap_order_execution_params() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [{
+                "action": {
+                    "type": "SwapOrder",
+                    "input_token": "SOL",
+                    "output_token": "USDC",
+                    "amount": "1000",
+                    "slippage_bps": 1500,
+                    "priority_fee": "veryHigh",
+                    "max_priority_fee_lamports": 10000000,
+                    "max_price_impact_pct": 20.0,
+                    "deadline": "2030-01-01T00:00:00Z"
+                }
+            }]
+        }))
+        .unwrap();
+        let pipeline = Pipeline::try_from((wire, params())).unwrap();
+        let Action::Order(order) = &pipeline.steps.values().next().unwrap().action else {
+            panic!("Expected SwapOrder action");
+        };
+        assert_eq!(order.slippage_bps, Some(1500));
+        assert_eq!(order.priority_fee, Some(PriorityFee::VeryHigh));
+        assert_eq!(order.max_priority_fee_lamports, Some(10_000_000));
+        assert_eq!(order.max_price_impact_pct, Some(20.0));
+        assert_eq!(
+            order.deadline.unwrap().to_rfc3339(),
+            "2030-01-01T00:00:00+00:00"
+        );
+
+        let mut step = swap_step("buy", &[]);
+        step["action"]["slippage_bps"] = json!(20_000);
+        let wire: WirePipeline = serde_json::from_value(json!({ "steps": [step] })).unwrap();
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::InvalidOrder(_))
+        ));
+    }
+
+    #[test]
+    fn test_wire_relative_amounts() {
+        let mut buy = swap_step("buy", &[]);
+        buy["action"]["amount"] = json!("25%");
+        let mut sell = swap_step("sell", &["buy"]);
+        sell["action"]["amount"] = json!(PARENT_OUTPUT);
+        let wire: WirePipeline =
+            serde_json::from_value(json!({ "steps": [buy.clone(), sell.clone()] })).unwrap();
+        assert!(Pipeline::try_from((wire, params())).is_ok());
+
+        // no parent to take the output from
+        sell["depends_on"] = json!([]);
+        let wire: WirePipeline = serde_json::from_value(json!({ "steps": [sell] })).unwrap();
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::InvalidOrder(_))
+        ));
+
+        buy["action"]["amount"] = json!("150%");
+        let wire: WirePipeline = serde_json::from_value(json!({ "steps": [buy] })).unwrap();
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::InvalidOrder(_))
+        ));
+    }
+
+    #[test]
+    fn test_wire_expiry() {
+        let expires_at = Utc::now() + chrono::Duration::days(1);
+        let mut buy = swap_step("buy", &[]);
+        buy["expires_at"] = json!(expires_at);
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [buy.clone()],
+            "expires_at": expires_at
+        }))
+        .unwrap();
+        let pipeline = Pipeline::try_from((wire, params())).unwrap();
+        assert_eq!(pipeline.expires_at, Some(expires_at));
+        assert!(pipeline
+            .steps
+            .values()
+            .all(|step| step.expires_at == Some(expires_at)));
+
+        buy["expires_at"] = json!(Utc::now() - chrono::Duration::minutes(1));
+        let wire: WirePipeline = serde_json::from_value(json!({ "steps": [buy] })).unwrap();
+        assert!(matches!(
+            Pipeline::try_from((wire, params())),
+            Err(WirePipelineError::InvalidExpiry(_))
+        ));
+    }
+
+    #[test]
+    fn test_wire_step_edit() {
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap_step("buy", &[]), swap_step("sell", &["buy"])]
+        }))
+        .unwrap();
+        let mut pipeline = Pipeline::try_from((wire, params())).unwrap();
+        let sell = find_step(&pipeline, "sell");
+
+        let edit: WireStepEdit = serde_json::from_value(json!({
+            "amount": "50%",
+            "conditions": [{ "type": "PriceBelow", "asset": "SOL", "value": 90.0 }]
+        }))
+        .unwrap();
+        edit.apply(&mut pipeline, sell).unwrap();
+        let step = &pipeline.steps[&sell];
+        assert!(matches!(&step.action, Action::Order(order) if order.amount == "50%"));
+        assert!(matches!(
+            step.conditions[0].condition_type,
+            ConditionType::PriceBelow { value, .. } if value == 90.0
+        ));
+        assert!(step.edited_at.is_some());
+
+        for invalid in [
+            json!({}),
+            json!({ "message": "hi" }),
+            json!({ "amount": "150%" }),
+            json!({ "conditions": [{ "type": "And" }] }),
+        ] {
+            let edit: WireStepEdit = serde_json::from_value(invalid.clone()).unwrap();
+            assert!(edit.apply(&mut pipeline, sell).is_err(), "{}", invalid);
+        }
+
+        // the buy step has no parent order to take the output of
+        let buy = find_step(&pipeline, "buy");
+        let edit: WireStepEdit =
+            serde_json::from_value(json!({ "amount": PARENT_OUTPUT })).unwrap();
+        assert!(matches!(
+            edit.apply(&mut pipeline, buy),
+            Err(WirePipelineError::InvalidOrder(_))
+        ));
+    }
 }
@@ -0,0 +1,610 @@
+//! Backtests replay the `price_updates` history that listen-data writes to
+//! ClickHouse through an engine running on the in-memory store. Conditions
+//! and steps go through the same evaluation code as live pipelines, only the
+//! clock follows the replayed updates and orders are filled at the replayed
+//! prices, see `Engine::execute_order`.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use privy::caip2::Caip2;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::engine::{
+    amount::OrderAmount,
+    api::{PipelineParams, WirePipeline, WirePipelineError},
+    chain::{self, SOLANA_NATIVE_MINTS},
+    clock::Clock,
+    constants::{TEST_ADDRESS_EVM, TEST_ADDRESS_SOL, USDC_MINT, USDT_MINT},
+    copy::{is_priced_key, queue_trade, wallet_key},
+    events::PipelineEvent,
+    executor::SimulatedExecutor,
+    market::MarketSnapshot,
+    pipeline::{Action, Pipeline, Status},
+    Engine, EngineError,
+};
+use crate::redis::subscriber::{make_redis_subscriber, PriceUpdate};
+use crate::store::MemoryStore;
+
+pub const MAX_BACKTEST_RANGE_DAYS: i64 = 31;
+/// Replays stop after this many price updates, see `BacktestReport::truncated`.
+/// The rows are buffered in full, this keeps a run to a few hundred MB.
+const MAX_BACKTEST_ROWS: usize = 200_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BacktestError {
+    #[error("[Backtest] Invalid pipeline: {0}")]
+    InvalidPipeline(WirePipelineError),
+
+    #[error("[Backtest] Invalid range: {0}")]
+    InvalidRange(String),
+
+    #[error("[Backtest] Unsupported: {0}")]
+    Unsupported(String),
+
+    #[error("[Backtest] ClickHouse config error: {0}")]
+    ClickhouseConfigError(String),
+
+    #[error("[Backtest] ClickHouse error: {0}")]
+    ClickhouseError(String),
+
+    #[error("[Backtest] {0}")]
+    EngineError(EngineError),
+
+    #[error("[Backtest] Runtime error: {0}")]
+    RuntimeError(String),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BacktestRequest {
+    pub pipeline: WirePipeline,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// USD prices of tokens without price history, held for the whole run.
+    /// listen-data doesn't write SOL to ClickHouse, so SOL orders need one.
+    /// USDC and USDT default to 1.0.
+    #[serde(default)]
+    pub fixed_prices: HashMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestFill {
+    pub step_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub input_token: String,
+    pub output_token: String,
+    /// base units of `input_token`
+    pub amount: String,
+    pub input_price: Option<f64>,
+    pub output_price: Option<f64>,
+    pub input_usd: Option<f64>,
+    /// UI units of `output_token` bought at the replayed prices
+    pub output_amount: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacktestReport {
+    /// final state of the pipeline
+    pub pipeline: Pipeline,
+    pub fills: Vec<BacktestFill>,
+    pub events: Vec<PipelineEvent>,
+    /// net change of each traded token, in UI units
+    pub balance_changes: HashMap<String, f64>,
+    /// balance changes marked to the last replayed prices, before fees and
+    /// slippage. `None` when a traded token is missing a price or decimals
+    pub pnl_usd: Option<f64>,
+    pub updates_replayed: usize,
+    /// the range held more than the row limit, the replay stopped early
+    pub truncated: bool,
+}
+
+/// Reads `price_updates` over the ClickHouse HTTP interface, configured with
+/// the same variables as listen-data
+pub struct PriceHistory {
+    url: String,
+    user: String,
+    password: String,
+    database: String,
+    client: reqwest::Client,
+}
+
+impl PriceHistory {
+    pub fn from_env() -> Result<Self, BacktestError> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .map_err(|_| BacktestError::ClickhouseConfigError(format!("{} is not set", name)))
+        };
+        Ok(Self {
+            url: var("CLICKHOUSE_URL")?,
+            user: var("CLICKHOUSE_USER")?,
+            password: var("CLICKHOUSE_PASSWORD")?,
+            database: var("CLICKHOUSE_DATABASE")?,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Updates of the given assets within [from, to), in the order they happened
+    pub async fn price_updates(
+        &self,
+        assets: &[String],
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceUpdate>, BacktestError> {
+        if assets.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = "SELECT name, pubkey, price, market_cap, timestamp, slot, swap_amount, \
+            owner, signature, multi_hop, is_buy, is_pump FROM price_updates \
+            WHERE pubkey IN {assets:Array(String)} \
+            AND timestamp >= {from:UInt64} AND timestamp < {to:UInt64} \
+            ORDER BY timestamp, slot LIMIT {limit:UInt64} FORMAT JSONEachRow";
+        // assets are validated pubkeys, they never contain quotes
+        let assets = format!(
+            "[{}]",
+            assets
+                .iter()
+                .map(|asset| format!("'{}'", asset))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        let response = self
+            .client
+            .post(&self.url)
+            .header("X-ClickHouse-User", &self.user)
+            .header("X-ClickHouse-Key", &self.password)
+            .query(&[
+                ("database", self.database.clone()),
+                ("output_format_json_quote_64bit_integers", "0".to_string()),
+                ("param_assets", assets),
+                ("param_from", from.timestamp().to_string()),
+                ("param_to", to.timestamp().to_string()),
+                ("param_limit", MAX_BACKTEST_ROWS.to_string()),
+            ])
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| BacktestError::ClickhouseError(e.to_string()))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| BacktestError::ClickhouseError(e.to_string()))?;
+        if !status.is_success() {
+            return Err(BacktestError::ClickhouseError(format!(
+                "{}: {}",
+                status, body
+            )));
+        }
+
+        body.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| BacktestError::ClickhouseError(format!("invalid row: {}", e)))
+            })
+            .collect()
+    }
+}
+
+/// Replays the request's range through the pipeline, see the module docs
+pub async fn run_backtest(
+    request: BacktestRequest,
+    history: &PriceHistory,
+) -> Result<BacktestReport, BacktestError> {
+    let BacktestRequest {
+        pipeline,
+        from,
+        to,
+        fixed_prices,
+    } = request;
+    if from >= to {
+        return Err(BacktestError::InvalidRange(
+            "from has to be before to".to_string(),
+        ));
+    }
+    if to - from > Duration::days(MAX_BACKTEST_RANGE_DAYS) {
+        return Err(BacktestError::InvalidRange(format!(
+            "at most {} days can be replayed at once",
+            MAX_BACKTEST_RANGE_DAYS
+        )));
+    }
+
+    let pipeline = backtest_pipeline(pipeline, from)?;
+    let fixed_prices = with_stablecoins(fixed_prices);
+    let (engine, store) = backtest_engine(from).map_err(BacktestError::EngineError)?;
+    let assets: Vec<String> = replayed_assets(&engine, &pipeline)
+        .into_iter()
+        .filter(|asset| !fixed_prices.contains_key(asset))
+        .collect();
+
+    let updates = history.price_updates(&assets, from, to).await?;
+    let truncated = updates.len() >= MAX_BACKTEST_ROWS;
+    tracing::info!(pipeline_id = %pipeline.id, updates = updates.len(), truncated, %from, %to, "Running backtest");
+
+    let mut decimals = HashMap::new();
+    for token in traded_tokens(&pipeline) {
+        match chain::token_decimals(Caip2::SOLANA, &token).await {
+            Ok(token_decimals) => {
+                decimals.insert(token, token_decimals);
+            }
+            Err(e) => tracing::warn!(%token, error = %e, "Failed to fetch token decimals"),
+        }
+    }
+
+    let replay = replay(&engine, &store, pipeline, &updates, &fixed_prices)
+        .await
+        .map_err(BacktestError::EngineError)?;
+
+    Ok(report(replay, &decimals, truncated))
+}
+
+/// Builds the pipeline as created at the start of the range. Only absolute
+/// amounts can be replayed, relative ones depend on the wallet's balances.
+fn backtest_pipeline(wire: WirePipeline, from: DateTime<Utc>) -> Result<Pipeline, BacktestError> {
+    let pipeline = wire
+        .into_pipeline(
+            PipelineParams {
+                user_id: "backtest".to_string(),
+                wallet_address: Some(TEST_ADDRESS_EVM.to_string()),
+                pubkey: Some(TEST_ADDRESS_SOL.to_string()),
+            },
+            from,
+        )
+        .map_err(BacktestError::InvalidPipeline)?;
+
+    for step in pipeline.steps.values() {
+        // the wallet's balances can't be replayed
+        if matches!(step.action, Action::Rebalance(_)) {
+            return Err(BacktestError::Unsupported("rebalance".to_string()));
+        }
+        if let Some(order) = step.action.order() {
+            if !matches!(order.amount.parse(), Ok(OrderAmount::Absolute(_))) {
+                return Err(BacktestError::Unsupported(format!(
+                    "relative order amount {}",
+                    order.amount
+                )));
+            }
+        }
+    }
+
+    Ok(pipeline)
+}
+
+fn with_stablecoins(mut fixed_prices: HashMap<String, f64>) -> HashMap<String, f64> {
+    for stablecoin in [USDC_MINT, USDT_MINT] {
+        fixed_prices.entry(stablecoin.to_string()).or_insert(1.0);
+    }
+    fixed_prices
+}
+
+fn traded_tokens(pipeline: &Pipeline) -> HashSet<String> {
+    pipeline
+        .steps
+        .values()
+        .filter_map(|step| step.action.order())
+        .flat_map(|order| [order.input_token.clone(), order.output_token.clone()])
+        .collect()
+}
+
+/// Assets watched by the conditions and traded by the orders, only Solana
+/// mints have price history
+fn replayed_assets(engine: &Engine, pipeline: &Pipeline) -> HashSet<String> {
+    let mut assets: HashSet<String> = engine.extract_assets(pipeline).into_iter().collect();
+    assets.extend(traded_tokens(pipeline));
+    assets.retain(|asset| {
+        Pubkey::from_str(asset).is_ok() && !SOLANA_NATIVE_MINTS.contains(&asset.as_str())
+    });
+    assets
+}
+
+/// Engine on the in-memory store, with its clock at the start of the range
+fn backtest_engine(from: DateTime<Utc>) -> Result<(Engine, Arc<MemoryStore>), EngineError> {
+    let store = Arc::new(MemoryStore::new());
+    // never started, the updates are fed in by `replay`
+    let (tx, _) = mpsc::channel(1);
+    let mut engine = Engine::new(
+        store.clone(),
+        make_redis_subscriber(tx, "backtest").map_err(EngineError::RedisSubscriberError)?,
+        None,
+        Arc::new(SimulatedExecutor),
+    );
+    engine.backtest = true;
+    engine.clock = Clock::replay(from);
+    Ok((engine, store))
+}
+
+struct Replay {
+    pipeline: Pipeline,
+    fills: Vec<BacktestFill>,
+    events: Vec<PipelineEvent>,
+    last_prices: HashMap<String, f64>,
+    updates_replayed: usize,
+}
+
+/// Feeds the updates to a backtest engine, evaluating the pipeline after
+/// every update that could change its outcome
+async fn replay(
+    engine: &Engine,
+    store: &MemoryStore,
+    mut pipeline: Pipeline,
+    updates: &[PriceUpdate],
+    fixed_prices: &HashMap<String, f64>,
+) -> Result<Replay, EngineError> {
+    let from = pipeline.created_at;
+    {
+        let mut cache = engine.price_cache.write().await;
+        for (token, price) in fixed_prices {
+            cache.insert(
+                token.clone(),
+                MarketSnapshot {
+                    price: *price,
+                    timestamp: from.timestamp() as u64,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    let watched: HashSet<String> = engine.extract_assets(&pipeline).into_iter().collect();
+    let time_based = watched.contains("NOW") || watched.contains("TIME");
+    let priced: Vec<&String> = watched.iter().filter(|key| is_priced_key(key)).collect();
+
+    let mut last_prices = fixed_prices.clone();
+    let mut fills = Vec::new();
+    let mut filled: HashSet<String> = HashSet::new();
+    let mut updates_replayed = 0;
+
+    for update in updates {
+        if !matches!(pipeline.status, Status::Pending) {
+            break;
+        }
+        updates_replayed += 1;
+        engine
+            .clock
+            .set(DateTime::from_timestamp(update.timestamp as i64, 0).unwrap_or(from));
+        let all_priced = {
+            let mut cache = engine.price_cache.write().await;
+            cache
+                .entry(update.pubkey.clone())
+                .or_default()
+                .apply(update, watched.contains(&update.pubkey));
+            priced.iter().all(|asset| cache.contains_key(*asset))
+        };
+        last_prices.insert(update.pubkey.clone(), update.price);
+        // the index isn't built for backtests, copied wallets are tracked here
+        let copied = watched.contains(&wallet_key(&update.owner));
+        if copied {
+            queue_trade(
+                &mut engine
+                    .wallet_trades
+                    .entry(update.owner.clone())
+                    .or_default(),
+                update.into(),
+            );
+        }
+
+        // conditions fail on missing prices, wait until every asset traded
+        if !all_priced || !(time_based || copied || watched.contains(&update.pubkey)) {
+            continue;
+        }
+
+        let done = engine.evaluate_pipeline(&mut pipeline).await?;
+        record_fills(&pipeline, &mut filled, &mut fills);
+        if done {
+            break;
+        }
+    }
+
+    Ok(Replay {
+        pipeline,
+        fills,
+        events: store.events(),
+        last_prices,
+        updates_replayed,
+    })
+}
+
+/// Picks up the fills of the last evaluation, recurring steps fill once per
+/// run and sliced orders once per slice
+fn record_fills(pipeline: &Pipeline, filled: &mut HashSet<String>, fills: &mut Vec<BacktestFill>) {
+    for step in pipeline.steps.values() {
+        let Some(order) = step.action.order() else {
+            continue;
+        };
+        let executions = step
+            .slices
+            .iter()
+            .map(|slice| (&slice.simulated_fill, &slice.transaction_hash))
+            .chain([(&step.simulated_fill, &step.transaction_hash)]);
+        for (fill, transaction_hash) in executions {
+            let (Some(fill), Some(transaction_hash)) = (fill, transaction_hash) else {
+                continue;
+            };
+            if filled.insert(transaction_hash.clone()) {
+                fills.push(BacktestFill {
+                    step_id: step.id,
+                    timestamp: fill.filled_at,
+                    input_token: order.input_token.clone(),
+                    output_token: order.output_token.clone(),
+                    amount: fill.amount.clone(),
+                    input_price: fill.input_price,
+                    output_price: fill.output_price,
+                    input_usd: None,
+                    output_amount: None,
+                });
+            }
+        }
+    }
+}
+
+fn report(replay: Replay, decimals: &HashMap<String, u8>, truncated: bool) -> BacktestReport {
+    let Replay {
+        pipeline,
+        mut fills,
+        events,
+        last_prices,
+        updates_replayed,
+    } = replay;
+
+    let mut balance_changes: HashMap<String, f64> = HashMap::new();
+    let mut complete = true;
+    fills.sort_by_key(|fill| fill.timestamp);
+    for fill in fills.iter_mut() {
+        let input_amount = match (fill.amount.parse::<f64>(), decimals.get(&fill.input_token)) {
+            (Ok(amount), Some(decimals)) => Some(amount / 10f64.powi(*decimals as i32)),
+            _ => None,
+        };
+        fill.input_usd = input_amount
+            .zip(fill.input_price)
+            .map(|(amount, price)| amount * price);
+        fill.output_amount = fill
+            .input_usd
+            .zip(fill.output_price.filter(|price| *price > 0.0))
+            .map(|(usd, price)| usd / price);
+
+        match (input_amount, fill.output_amount) {
+            (Some(input_amount), Some(output_amount)) => {
+                *balance_changes.entry(fill.input_token.clone()).or_default() -= input_amount;
+                *balance_changes
+                    .entry(fill.output_token.clone())
+                    .or_default() += output_amount;
+            }
+            _ => complete = false,
+        }
+    }
+
+    let pnl_usd = complete
+        .then(|| {
+            balance_changes
+                .iter()
+                .map(|(token, change)| last_prices.get(token).map(|price| change * price))
+                .sum::<Option<f64>>()
+        })
+        .flatten();
+
+    BacktestReport {
+        pipeline,
+        fills,
+        events,
+        balance_changes,
+        pnl_usd,
+        updates_replayed,
+        truncated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::events::PipelineEventKind;
+    use crate::engine::testing::price_update;
+    use serde_json::json;
+
+    const SOL: &str = "So11111111111111111111111111111111111111112";
+
+    fn update(asset: &str, price: f64, timestamp: u64) -> PriceUpdate {
+        PriceUpdate {
+            timestamp,
+            ..price_update(asset, price)
+        }
+    }
+
+    fn swap(
+        id: &str,
+        depends_on: &[&str],
+        input: &str,
+        output: &str,
+        amount: &str,
+        asset: &str,
+        value: f64,
+    ) -> serde_json::Value {
+        json!({
+            "id": id,
+            "depends_on": depends_on,
+            "action": {
+                "type": "SwapOrder",
+                "input_token": input,
+                "output_token": output,
+                "amount": amount
+            },
+            "conditions": [{ "type": "PriceAbove", "asset": asset, "value": value }]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_replay_buy_then_take_profit() {
+        let meme = Pubkey::new_unique().to_string();
+        let from = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        // 1 SOL into meme above $1, 66 meme back to SOL above $2
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [
+                swap("buy", &[], SOL, &meme, "1000000000", &meme, 1.0),
+                swap("sell", &["buy"], &meme, SOL, "66000000", &meme, 2.0)
+            ]
+        }))
+        .unwrap();
+        let pipeline = backtest_pipeline(wire, from).unwrap();
+
+        let start = from.timestamp() as u64;
+        let updates = [0.5, 1.5, 1.8, 2.5, 3.0]
+            .iter()
+            .enumerate()
+            .map(|(i, price)| update(&meme, *price, start + 60 * i as u64))
+            .collect::<Vec<_>>();
+        let fixed_prices = with_stablecoins(HashMap::from([(SOL.to_string(), 100.0)]));
+
+        let (engine, store) = backtest_engine(from).unwrap();
+        let replay = replay(&engine, &store, pipeline, &updates, &fixed_prices)
+            .await
+            .unwrap();
+        // the pipeline completes on the fourth update
+        assert_eq!(replay.updates_replayed, 4);
+        let decimals = HashMap::from([(SOL.to_string(), 9), (meme.clone(), 6)]);
+        let report = report(replay, &decimals, false);
+
+        assert!(matches!(report.pipeline.status, Status::Completed));
+        assert_eq!(report.fills.len(), 2);
+        let (buy, sell) = (&report.fills[0], &report.fills[1]);
+        assert_eq!(buy.timestamp.timestamp() as u64, start + 60);
+        assert_eq!(buy.input_usd, Some(100.0));
+        assert_eq!(sell.timestamp.timestamp() as u64, start + 180);
+        assert_eq!(sell.input_price, Some(2.5));
+
+        // +0.65 SOL and 2/3 meme left over, at $100 and $2.5
+        assert!((report.balance_changes[SOL] - 0.65).abs() < 1e-9);
+        assert!((report.balance_changes[&meme] - 2.0 / 3.0).abs() < 1e-9);
+        let pnl = report.pnl_usd.unwrap();
+        assert!((pnl - (65.0 + 2.5 * 2.0 / 3.0)).abs() < 1e-9);
+
+        let submitted = report
+            .events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    PipelineEventKind::TransactionSubmitted { dry_run: true, .. }
+                )
+            })
+            .count();
+        assert_eq!(submitted, 2);
+    }
+
+    #[test]
+    fn test_backtest_rejects_relative_amounts() {
+        let meme = Pubkey::new_unique().to_string();
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [swap("buy", &[], SOL, &meme, "50%", &meme, 1.0)]
+        }))
+        .unwrap();
+        assert!(matches!(
+            backtest_pipeline(wire, Utc::now()),
+            Err(BacktestError::Unsupported(_))
+        ));
+    }
+}
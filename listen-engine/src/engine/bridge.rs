@@ -1,26 +1,85 @@
 use std::collections::HashSet;
 
 use crate::engine::{
+    api::WireStepEdit,
+    confirm::CONFIRMING_KEY,
     pipeline::{PipelineStep, Status},
     Engine, EngineError, Pipeline,
 };
 use uuid::Uuid;
 
 impl Engine {
+    pub async fn add_pipeline(&self, pipeline: &Pipeline) -> Result<String, EngineError> {
+        self.store
+            .save_pipeline(pipeline)
+            .await
+            .map_err(EngineError::AddPipelineError)?;
+
+        self.index_pipeline(pipeline);
+        self.announce_index_update(&format!("{}:{}", pipeline.user_id, pipeline.id))
+            .await;
+
+        Ok(pipeline.id.to_string())
+    }
+
+    pub async fn get_pipeline(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+    ) -> Result<Pipeline, EngineError> {
+        match self
+            .store
+            .get_pipeline(user_id, &pipeline_id.to_string())
+            .await
+        {
+            Ok(Some(pipeline)) if pipeline.user_id == user_id => Ok(pipeline),
+            Ok(Some(_)) => Err(EngineError::Unauthorized),
+            Ok(None) => Err(EngineError::PipelineNotFound(pipeline_id.to_string())),
+            Err(e) => Err(EngineError::StoreError(e)),
+        }
+    }
+
     pub async fn delete_pipeline(
         &self,
         user_id: &str,
         pipeline_id: Uuid,
     ) -> Result<(), EngineError> {
-        if let Err(e) = self
-            .redis
+        let pipeline = self.get_pipeline(user_id, pipeline_id).await?;
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+
+        // Hold the processing lock while deleting, otherwise an in-flight
+        // evaluation could save the pipeline back after it has been deleted
+        if !self.lock_pipeline(&pipeline_key).await? {
+            return Err(EngineError::PipelineBusy(pipeline_id.to_string()));
+        }
+
+        self.remove_from_active_pipelines(&pipeline);
+
+        let result = self
+            .store
             .delete_pipeline(user_id, &pipeline_id.to_string())
             .await
-        {
-            return Err(EngineError::DeletePipelineError(e));
-        }
+            .map_err(EngineError::DeletePipelineError);
 
-        Ok(())
+        self.unlock_pipeline(&pipeline_key).await;
+        self.announce_index_update(&pipeline_key).await;
+
+        result
+    }
+
+    /// Removes the pipeline from the index of every asset it references, from
+    /// the confirmation index and from the pause and expiry tracking
+    pub fn remove_from_active_pipelines(&self, pipeline: &Pipeline) {
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+        let assets = self.extract_assets(pipeline);
+        for asset in assets.iter().map(String::as_str).chain([CONFIRMING_KEY]) {
+            if let Some(mut pipeline_ids) = self.active_pipelines.get_mut(asset) {
+                pipeline_ids.remove(&pipeline_key);
+            }
+        }
+        self.paused_pipelines.remove(&pipeline_key);
+        self.pipeline_expiries.remove(&pipeline_key);
+        self.prune_wallet_trades(&assets);
     }
 
     pub async fn get_all_pipelines_by_user(
@@ -28,10 +87,10 @@ impl Engine {
         user_id: &str,
     ) -> Result<Vec<Pipeline>, EngineError> {
         match self
-            .redis
+            .store
             .get_all_pipelines_for_user(user_id)
             .await
-            .map_err(EngineError::RedisClientError)
+            .map_err(EngineError::StoreError)
         {
             Ok(pipelines) => Ok(pipelines),
             Err(e) => {
@@ -46,47 +105,198 @@ impl Engine {
         user_id: &str,
         pipeline_id: Uuid,
     ) -> Result<(), EngineError> {
-        let mut pipeline = match self
-            .redis
-            .get_pipeline(user_id, &pipeline_id.to_string())
+        let pipeline_key = format!("{}:{}", user_id, pipeline_id);
+
+        // Same as deleting, an in-flight evaluation would overwrite the status
+        if !self.lock_pipeline(&pipeline_key).await? {
+            return Err(EngineError::PipelineBusy(pipeline_id.to_string()));
+        }
+
+        let result = self.apply_cancel(user_id, pipeline_id).await;
+
+        self.unlock_pipeline(&pipeline_key).await;
+        if result.is_ok() {
+            self.announce_index_update(&pipeline_key).await;
+        }
+
+        result
+    }
+
+    /// Cancelling expires the pipeline now: pending steps are cancelled right
+    /// away, transactions in flight are still confirmed and the pipeline is
+    /// only cancelled once none are left
+    async fn apply_cancel(&self, user_id: &str, pipeline_id: Uuid) -> Result<(), EngineError> {
+        // read under the lock, so that in-flight steps are up to date
+        let mut pipeline = self.get_pipeline(user_id, pipeline_id).await?;
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+
+        let now = self.clock.now();
+        pipeline.expires_at = Some(now);
+        pipeline.expire(now);
+
+        if matches!(pipeline.status, Status::Cancelled) {
+            self.remove_from_active_pipelines(&pipeline);
+        } else {
+            // the confirmations are followed even if the pipeline was paused
+            pipeline.status = Status::Pending;
+            self.paused_pipelines.remove(&pipeline_key);
+            self.index_expiry(&pipeline);
+        }
+
+        self.store
+            .save_pipeline(&pipeline)
             .await
-        {
-            Ok(Some(pipeline)) => pipeline,
-            Ok(None) => return Err(EngineError::PipelineNotFound(pipeline_id.to_string())),
-            Err(e) => return Err(EngineError::RedisClientError(e)),
-        };
+            .map_err(EngineError::StoreError)
+    }
 
-        if pipeline.user_id != user_id {
-            return Err(EngineError::Unauthorized);
+    /// Stops evaluating the pipeline until it is resumed, it stays indexed so
+    /// that resuming doesn't have to rebuild anything
+    pub async fn pause_pipeline(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+    ) -> Result<(), EngineError> {
+        self.set_paused(user_id, pipeline_id, true).await
+    }
+
+    pub async fn resume_pipeline(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+    ) -> Result<(), EngineError> {
+        self.set_paused(user_id, pipeline_id, false).await
+    }
+
+    async fn set_paused(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+        paused: bool,
+    ) -> Result<(), EngineError> {
+        let pipeline_key = format!("{}:{}", user_id, pipeline_id);
+
+        // Same as deleting, an in-flight evaluation would overwrite the status
+        if !self.lock_pipeline(&pipeline_key).await? {
+            return Err(EngineError::PipelineBusy(pipeline_id.to_string()));
         }
 
-        pipeline.status = Status::Cancelled;
+        let result = self.apply_paused(user_id, pipeline_id, paused).await;
 
-        for step in pipeline.steps.values_mut() {
-            if matches!(step.status, Status::Pending) {
-                step.status = Status::Cancelled;
-            }
+        self.unlock_pipeline(&pipeline_key).await;
+        if result.is_ok() {
+            self.announce_index_update(&pipeline_key).await;
         }
 
-        let assets_mentioned = self.extract_assets(&pipeline);
+        result
+    }
 
-        for asset in assets_mentioned {
-            match self.active_pipelines.get_mut(&asset) {
-                Some(mut pipeline_ids) => {
-                    pipeline_ids.remove(&pipeline.id.to_string());
-                    drop(pipeline_ids);
-                }
-                None => {
-                    tracing::error!("Asset {} not found in active pipelines", asset);
-                }
+    async fn apply_paused(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+        paused: bool,
+    ) -> Result<(), EngineError> {
+        // read under the lock, so that the status change applies to the latest state
+        let mut pipeline = self.get_pipeline(user_id, pipeline_id).await?;
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+
+        match (&pipeline.status, paused) {
+            (Status::Pending, true) => {
+                pipeline.status = Status::Paused;
+                self.paused_pipelines.insert(pipeline_key.clone());
+                Ok(())
+            }
+            (Status::Paused, false) => {
+                pipeline.status = Status::Pending;
+                self.paused_pipelines.remove(&pipeline_key);
+                Ok(())
             }
+            (status, _) => Err(EngineError::InvalidPipelineStatus(format!(
+                "cannot {} a {:?} pipeline",
+                if paused { "pause" } else { "resume" },
+                status
+            ))),
+        }?;
+
+        self.index_confirming(&pipeline);
+        self.store
+            .save_pipeline(&pipeline)
+            .await
+            .map_err(EngineError::SavePipelineError)
+    }
+
+    /// Applies the user's edit to a pending step. The processing lock keeps
+    /// evaluations out while the pipeline is read, edited and saved, and the
+    /// pipeline is re-indexed in case the edit changed the watched assets.
+    pub async fn edit_step(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+        step_id: Uuid,
+        edit: &WireStepEdit,
+    ) -> Result<PipelineStep, EngineError> {
+        let pipeline_key = format!("{}:{}", user_id, pipeline_id);
+        if !self.lock_pipeline(&pipeline_key).await? {
+            return Err(EngineError::PipelineBusy(pipeline_id.to_string()));
+        }
+
+        let result = self
+            .apply_step_edit(user_id, pipeline_id, step_id, edit)
+            .await;
+
+        self.unlock_pipeline(&pipeline_key).await;
+        if result.is_ok() {
+            self.announce_index_update(&pipeline_key).await;
+        }
+
+        result
+    }
+
+    async fn apply_step_edit(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+        step_id: Uuid,
+        edit: &WireStepEdit,
+    ) -> Result<PipelineStep, EngineError> {
+        // read under the lock, so that the edit applies to the latest state
+        let mut pipeline = self.get_pipeline(user_id, pipeline_id).await?;
+        if !matches!(pipeline.status, Status::Pending | Status::Paused) {
+            return Err(EngineError::InvalidPipelineStatus(format!(
+                "cannot edit a {:?} pipeline",
+                pipeline.status
+            )));
+        }
+        match pipeline.steps.get(&step_id) {
+            Some(step) if matches!(step.status, Status::Pending) => {}
+            Some(_) => return Err(EngineError::StepNotEditable),
+            None => return Err(EngineError::StepNotFound(step_id.to_string())),
         }
 
-        if let Err(e) = self.redis.save_pipeline(&pipeline).await {
-            return Err(EngineError::RedisClientError(e));
+        let mut pipeline_hash = pipeline.hash();
+        let old_assets: HashSet<String> = self.extract_assets(&pipeline).into_iter().collect();
+
+        edit.apply(&mut pipeline, step_id)
+            .map_err(EngineError::InvalidStepEdit)?;
+        self.save_pipeline(&pipeline, &mut pipeline_hash).await?;
+
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+        let new_assets: HashSet<String> = self.extract_assets(&pipeline).into_iter().collect();
+        for asset in old_assets.difference(&new_assets) {
+            if let Some(mut pipeline_ids) = self.active_pipelines.get_mut(asset) {
+                pipeline_ids.remove(&pipeline_key);
+            }
+        }
+        for asset in new_assets.difference(&old_assets) {
+            self.active_pipelines
+                .entry(asset.clone())
+                .or_default()
+                .insert(pipeline_key.clone());
         }
 
-        Ok(())
+        tracing::info!(%pipeline_id, %step_id, ?edit, "Edited pipeline step");
+
+        Ok(pipeline.steps[&step_id].clone())
     }
 
     pub async fn cancel_step(
@@ -96,44 +306,26 @@ impl Engine {
         step_id: Uuid,
     ) -> Result<(), EngineError> {
         let mut pipeline = match self
-            .redis
+            .store
             .get_pipeline(user_id, &pipeline_id.to_string())
             .await
         {
             Ok(Some(pipeline)) => pipeline,
             Ok(None) => return Err(EngineError::PipelineNotFound(pipeline_id.to_string())),
-            Err(e) => return Err(EngineError::RedisClientError(e)),
+            Err(e) => return Err(EngineError::StoreError(e)),
         };
 
         if pipeline.user_id != user_id {
             return Err(EngineError::Unauthorized);
         }
 
-        if let Some(step) = pipeline.steps.get_mut(&step_id) {
+        if let Some(step) = pipeline.steps.get(&step_id) {
             if matches!(step.status, Status::Pending) {
-                step.status = Status::Cancelled;
-
-                let mut to_cancel: Vec<PipelineStep> = vec![step.clone()];
-                let mut queue = vec![step_id];
-
-                while let Some(current_id) = queue.pop() {
-                    if let Some(current_step) = pipeline.steps.get(&current_id) {
-                        for next_id in &current_step.next_steps {
-                            if let Some(next_step) = pipeline.steps.get(next_id) {
-                                if matches!(next_step.status, Status::Pending) {
-                                    to_cancel.push(next_step.clone());
-                                    queue.push(*next_id);
-                                }
-                            }
-                        }
-                    }
-                }
-
-                for cancel_id in to_cancel.iter() {
-                    if let Some(step) = pipeline.steps.get_mut(&cancel_id.id) {
-                        step.status = Status::Cancelled;
-                    }
-                }
+                let to_cancel: Vec<PipelineStep> = pipeline
+                    .cancel_step_and_downstream(step_id)
+                    .iter()
+                    .filter_map(|id| pipeline.steps.get(id).cloned())
+                    .collect();
 
                 let mut assets_mentioned: HashSet<String> = HashSet::new();
                 for step in to_cancel.iter() {
@@ -152,16 +344,221 @@ impl Engine {
                     }
                 }
 
-                if let Err(e) = self.redis.save_pipeline(&pipeline).await {
-                    return Err(EngineError::RedisClientError(e));
+                if let Err(e) = self.store.save_pipeline(&pipeline).await {
+                    return Err(EngineError::StoreError(e));
                 }
+                self.announce_index_update(&format!("{}:{}", pipeline.user_id, pipeline.id))
+                    .await;
 
-                return Ok(());
+                Ok(())
             } else {
-                return Err(EngineError::StepNotCancellable);
+                Err(EngineError::StepNotCancellable)
             }
         } else {
-            return Err(EngineError::StepNotFound(step_id.to_string()));
+            Err(EngineError::StepNotFound(step_id.to_string()))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::api::WireStepEdit;
+    use crate::engine::pipeline::{Action, Status};
+    use crate::engine::testing::{engine, price_update, settle, stand_in, webhook_pipeline};
+    use crate::engine::EngineError;
+    use crate::store::PipelineStore;
+    use solana_sdk::pubkey::Pubkey;
+
+    #[tokio::test]
+    async fn test_add_get_delete_pipeline() {
+        let (engine, _) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        let key = format!("user:{}", pipeline.id);
+
+        engine.add_pipeline(&pipeline).await.unwrap();
+        assert!(engine.active_pipelines.get(&asset).unwrap().contains(&key));
+        assert_eq!(
+            engine.get_pipeline("user", pipeline.id).await.unwrap().id,
+            pipeline.id
+        );
+        assert!(matches!(
+            engine.get_pipeline("other", pipeline.id).await,
+            Err(EngineError::PipelineNotFound(_))
+        ));
+        assert_eq!(
+            engine
+                .get_all_pipelines_by_user("user")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        engine.delete_pipeline("user", pipeline.id).await.unwrap();
+        assert!(!engine.active_pipelines.get(&asset).unwrap().contains(&key));
+        assert!(matches!(
+            engine.get_pipeline("user", pipeline.id).await,
+            Err(EngineError::PipelineNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delete_busy_pipeline() {
+        let (engine, _) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        engine
+            .processing_pipelines
+            .lock()
+            .await
+            .insert(format!("user:{}", pipeline.id));
+
+        assert!(matches!(
+            engine.delete_pipeline("user", pipeline.id).await,
+            Err(EngineError::PipelineBusy(_))
+        ));
+        assert!(engine.get_pipeline("user", pipeline.id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pipeline() {
+        let (engine, _) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        let key = format!("user:{}", pipeline.id);
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        engine.processing_pipelines.lock().await.insert(key.clone());
+        assert!(matches!(
+            engine.cancel_pipeline("user", pipeline.id).await,
+            Err(EngineError::PipelineBusy(_))
+        ));
+        engine.processing_pipelines.lock().await.remove(&key);
+
+        engine.cancel_pipeline("user", pipeline.id).await.unwrap();
+
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Cancelled));
+        assert!(saved
+            .steps
+            .values()
+            .all(|step| matches!(step.status, Status::Cancelled)));
+        assert!(engine.active_pipelines.get(&asset).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pipeline_waits_for_confirmations() {
+        let (engine, store) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let mut pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        let first = pipeline.current_steps[0];
+        let mut second = pipeline.steps[&first].clone();
+        second.id = uuid::Uuid::new_v4();
+        pipeline.steps.insert(second.id, second.clone());
+        pipeline.steps.get_mut(&first).unwrap().status = Status::Confirming;
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        // the pending step is cancelled, the sent transaction is still confirmed
+        engine.cancel_pipeline("user", pipeline.id).await.unwrap();
+        let mut saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Pending));
+        assert!(matches!(saved.steps[&first].status, Status::Confirming));
+        assert!(matches!(saved.steps[&second.id].status, Status::Cancelled));
+
+        // cancelled by the expiry sweep once the transaction confirmed
+        saved.steps.get_mut(&first).unwrap().status = Status::Completed;
+        store.save_pipeline(&saved).await.unwrap();
+        engine.expire_pipelines().await.unwrap();
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume_pipeline() {
+        let (engine, store) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let (url, handle) = stand_in(200).await;
+        let pipeline = webhook_pipeline(&asset, 2.0, &url);
+        let key = format!("user:{}", pipeline.id);
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        engine.pause_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(
+            engine.pause_pipeline("user", pipeline.id).await,
+            Err(EngineError::InvalidPipelineStatus(_))
+        ));
+
+        // stays indexed, but price updates don't evaluate it
+        engine
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        settle(&engine).await;
+        assert!(engine.active_pipelines.get(&asset).unwrap().contains(&key));
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Paused));
+        assert!(store.events().is_empty());
+
+        engine.resume_pipeline("user", pipeline.id).await.unwrap();
+        engine
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        settle(&engine).await;
+        handle.await.unwrap();
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Completed));
+        assert!(matches!(
+            engine.resume_pipeline("user", pipeline.id).await,
+            Err(EngineError::InvalidPipelineStatus(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_edit_step() {
+        let (engine, _) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let other = Pubkey::new_unique().to_string();
+        let pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        let key = format!("user:{}", pipeline.id);
+        let step_id = pipeline.current_steps[0];
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        let edit: WireStepEdit = serde_json::from_value(serde_json::json!({
+            "message": "moved",
+            "conditions": [{ "type": "PriceAbove", "asset": other, "value": 5.0 }]
+        }))
+        .unwrap();
+
+        engine.processing_pipelines.lock().await.insert(key.clone());
+        assert!(matches!(
+            engine.edit_step("user", pipeline.id, step_id, &edit).await,
+            Err(EngineError::PipelineBusy(_))
+        ));
+        engine.processing_pipelines.lock().await.remove(&key);
+
+        let step = engine
+            .edit_step("user", pipeline.id, step_id, &edit)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &step.action,
+            Action::Notification(notification) if notification.message == "moved"
+        ));
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(saved.steps[&step_id].edited_at.is_some());
+
+        // the pipeline now only watches the new asset
+        assert!(!engine.active_pipelines.get(&asset).unwrap().contains(&key));
+        assert!(engine.active_pipelines.get(&other).unwrap().contains(&key));
+
+        engine.cancel_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(
+            engine.edit_step("user", pipeline.id, step_id, &edit).await,
+            Err(EngineError::InvalidPipelineStatus(_))
+        ));
+    }
+}
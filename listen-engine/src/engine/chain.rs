@@ -0,0 +1,438 @@
+//! Reads wallet balances and transactions from Solana and EVM nodes, Solana
+//! through `SOLANA_RPC_URL` and EVM chains through the same Alchemy
+//! endpoints as the approvals
+
+use serde_json::{json, Value};
+
+use crate::engine::order::{is_evm, is_solana};
+
+pub const SOLANA_NATIVE_MINTS: [&str; 2] = [
+    "So11111111111111111111111111111111111111112",
+    "11111111111111111111111111111111",
+];
+const EVM_NATIVE_TOKENS: [&str; 2] = [
+    "0x0000000000000000000000000000000000000000",
+    "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee",
+];
+const SOL_DECIMALS: u8 = 9;
+const EVM_NATIVE_DECIMALS: u8 = 18;
+
+/// keccak256("Transfer(address,address,uint256)")
+const ERC20_TRANSFER_TOPIC: &str =
+    "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChainError {
+    #[error("[Chain] RPC config error: {0}")]
+    RpcConfigError(String),
+
+    #[error("[Chain] RPC error: {0}")]
+    RpcError(String),
+
+    #[error("[Chain] Unsupported: {0}")]
+    Unsupported(String),
+}
+
+/// Whether `token` is the chain's native asset rather than a token contract
+pub fn is_native(caip2: &str, token: &str) -> bool {
+    if is_solana(caip2) {
+        SOLANA_NATIVE_MINTS.contains(&token)
+    } else {
+        EVM_NATIVE_TOKENS.contains(&token.to_lowercase().as_str())
+    }
+}
+
+fn rpc_url(caip2: &str) -> Result<String, ChainError> {
+    if is_solana(caip2) {
+        std::env::var("SOLANA_RPC_URL")
+            .map_err(|_| ChainError::RpcConfigError("SOLANA_RPC_URL is not set".to_string()))
+    } else if is_evm(caip2) {
+        evm_approvals::caip2_to_ethereum_rpc_url(caip2)
+            .map_err(|e| ChainError::RpcConfigError(e.to_string()))
+    } else {
+        Err(ChainError::Unsupported(format!("chain {}", caip2)))
+    }
+}
+
+/// Returns the `result` of the call, `Value::Null` when there is none
+async fn rpc_call(url: &str, method: &str, params: Value) -> Result<Value, ChainError> {
+    let response: Value = reqwest::Client::new()
+        .post(url)
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params
+        }))
+        .send()
+        .await
+        .map_err(|e| ChainError::RpcError(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| ChainError::RpcError(e.to_string()))?;
+
+    if let Some(error) = response.get("error") {
+        return Err(ChainError::RpcError(format!("{}: {}", method, error)));
+    }
+
+    Ok(response["result"].clone())
+}
+
+fn parse_hex(value: &str) -> Result<u128, ChainError> {
+    let digits = value.trim_start_matches("0x").trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u128::from_str_radix(digits, 16)
+        .map_err(|e| ChainError::RpcError(format!("invalid quantity {}: {}", value, e)))
+}
+
+/// Decimals of `token`, from the mint on Solana and the token contract on EVM
+/// chains
+pub async fn token_decimals(caip2: &str, token: &str) -> Result<u8, ChainError> {
+    if is_native(caip2, token) {
+        return Ok(if is_solana(caip2) {
+            SOL_DECIMALS
+        } else {
+            EVM_NATIVE_DECIMALS
+        });
+    }
+    let url = rpc_url(caip2)?;
+
+    if is_solana(caip2) {
+        let result = rpc_call(&url, "getTokenSupply", json!([token])).await?;
+        return result["value"]["decimals"]
+            .as_u64()
+            .and_then(|decimals| u8::try_from(decimals).ok())
+            .ok_or_else(|| ChainError::RpcError("getTokenSupply: missing decimals".to_string()));
+    }
+
+    // decimals()
+    let result = rpc_call(
+        &url,
+        "eth_call",
+        json!([{ "to": token, "data": "0x313ce567" }, "latest"]),
+    )
+    .await?;
+    let decimals = parse_hex(result.as_str().unwrap_or("0x0"))?;
+    u8::try_from(decimals)
+        .map_err(|_| ChainError::RpcError(format!("invalid decimals: {}", decimals)))
+}
+
+/// Balance of `token` held by `owner`, in base units
+pub async fn wallet_balance(caip2: &str, owner: &str, token: &str) -> Result<u128, ChainError> {
+    let url = rpc_url(caip2)?;
+
+    if is_solana(caip2) {
+        if is_native(caip2, token) {
+            let result = rpc_call(
+                &url,
+                "getBalance",
+                json!([owner, { "commitment": "confirmed" }]),
+            )
+            .await?;
+            return result["value"]
+                .as_u64()
+                .map(u128::from)
+                .ok_or_else(|| ChainError::RpcError("getBalance: missing value".to_string()));
+        }
+
+        let result = rpc_call(
+            &url,
+            "getTokenAccountsByOwner",
+            json!([
+                owner,
+                { "mint": token },
+                { "encoding": "jsonParsed", "commitment": "confirmed" }
+            ]),
+        )
+        .await?;
+        return Ok(sum_token_accounts(&result));
+    }
+
+    let result = if is_native(caip2, token) {
+        rpc_call(&url, "eth_getBalance", json!([owner, "latest"])).await?
+    } else {
+        // balanceOf(address)
+        let data = format!("0x70a08231{:0>64}", owner.trim_start_matches("0x"));
+        rpc_call(
+            &url,
+            "eth_call",
+            json!([{ "to": token, "data": data }, "latest"]),
+        )
+        .await?
+    };
+    parse_hex(result.as_str().unwrap_or("0x0"))
+}
+
+fn sum_token_accounts(result: &Value) -> u128 {
+    result["value"]
+        .as_array()
+        .map(|accounts| {
+            accounts
+                .iter()
+                .filter_map(|account| {
+                    account["account"]["data"]["parsed"]["info"]["tokenAmount"]["amount"]
+                        .as_str()?
+                        .parse::<u128>()
+                        .ok()
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// A transaction that made it into a block, successful or not
+#[derive(Debug, Clone)]
+pub enum ChainTransaction {
+    /// `getTransaction` result with `jsonParsed` encoding
+    Solana(Value),
+    /// `eth_getTransactionReceipt` result
+    Evm(Value),
+}
+
+/// Fetches the transaction at `confirmed` commitment on Solana, `None` while
+/// it has not landed (or never will)
+pub async fn get_transaction(
+    caip2: &str,
+    transaction_hash: &str,
+) -> Result<Option<ChainTransaction>, ChainError> {
+    let url = rpc_url(caip2)?;
+
+    if is_solana(caip2) {
+        let result = rpc_call(
+            &url,
+            "getTransaction",
+            json!([
+                transaction_hash,
+                {
+                    "encoding": "jsonParsed",
+                    "commitment": "confirmed",
+                    "maxSupportedTransactionVersion": 0
+                }
+            ]),
+        )
+        .await?;
+        Ok((!result.is_null()).then_some(ChainTransaction::Solana(result)))
+    } else {
+        let result = rpc_call(&url, "eth_getTransactionReceipt", json!([transaction_hash])).await?;
+        Ok((!result.is_null()).then_some(ChainTransaction::Evm(result)))
+    }
+}
+
+impl ChainTransaction {
+    /// Why the transaction failed, `None` if it succeeded
+    pub fn error(&self) -> Option<String> {
+        match self {
+            ChainTransaction::Solana(transaction) => {
+                let err = &transaction["meta"]["err"];
+                (!err.is_null()).then(|| err.to_string())
+            }
+            ChainTransaction::Evm(receipt) => {
+                (receipt["status"].as_str() == Some("0x0")).then(|| "reverted".to_string())
+            }
+        }
+    }
+
+    /// Net change of `owner`'s balance of `token` in base units, positive when
+    /// received. For SOL the lamport change counts as well (Jupiter unwraps
+    /// SOL by default), with the fee added back when `owner` paid it. On EVM
+    /// only ERC20 transfers are visible in the receipt.
+    pub fn balance_change(&self, owner: &str, token: &str) -> Result<i128, ChainError> {
+        match self {
+            ChainTransaction::Solana(transaction) => {
+                Ok(solana_balance_change(transaction, owner, token))
+            }
+            ChainTransaction::Evm(receipt) => {
+                if EVM_NATIVE_TOKENS.contains(&token.to_lowercase().as_str()) {
+                    return Err(ChainError::Unsupported(
+                        "native EVM balance changes".to_string(),
+                    ));
+                }
+                evm_balance_change(receipt, owner, token)
+            }
+        }
+    }
+
+    /// Decimals of `token`, read from the transaction on Solana and from the
+    /// token contract on EVM chains
+    pub async fn decimals(&self, caip2: &str, token: &str) -> Option<u8> {
+        if is_native(caip2, token) {
+            return Some(match self {
+                ChainTransaction::Solana(_) => SOL_DECIMALS,
+                ChainTransaction::Evm(_) => EVM_NATIVE_DECIMALS,
+            });
+        }
+        match self {
+            ChainTransaction::Solana(transaction) => ["postTokenBalances", "preTokenBalances"]
+                .iter()
+                .filter_map(|key| transaction["meta"][key].as_array())
+                .flatten()
+                .find(|balance| balance["mint"] == token)
+                .and_then(|balance| balance["uiTokenAmount"]["decimals"].as_u64())
+                .map(|decimals| decimals as u8),
+            ChainTransaction::Evm(_) => token_decimals(caip2, token).await.ok(),
+        }
+    }
+}
+
+fn solana_balance_change(transaction: &Value, owner: &str, mint: &str) -> i128 {
+    let meta = &transaction["meta"];
+    let token_balance = |key: &str| -> i128 {
+        meta[key]
+            .as_array()
+            .map(|balances| {
+                balances
+                    .iter()
+                    .filter(|balance| balance["owner"] == owner && balance["mint"] == mint)
+                    .filter_map(|balance| {
+                        balance["uiTokenAmount"]["amount"]
+                            .as_str()?
+                            .parse::<i128>()
+                            .ok()
+                    })
+                    .sum()
+            })
+            .unwrap_or(0)
+    };
+    let mut change = token_balance("postTokenBalances") - token_balance("preTokenBalances");
+
+    if SOLANA_NATIVE_MINTS.contains(&mint) {
+        let owner_index = transaction["transaction"]["message"]["accountKeys"]
+            .as_array()
+            .and_then(|keys| keys.iter().position(|key| key["pubkey"] == owner));
+        if let Some(index) = owner_index {
+            let lamports = |key: &str| meta[key][index].as_i64().unwrap_or(0) as i128;
+            change += lamports("postBalances") - lamports("preBalances");
+            if index == 0 {
+                change += meta["fee"].as_i64().unwrap_or(0) as i128;
+            }
+        }
+    }
+
+    change
+}
+
+fn evm_balance_change(receipt: &Value, owner: &str, token: &str) -> Result<i128, ChainError> {
+    let owner_topic = format!("0x{:0>64}", owner.trim_start_matches("0x").to_lowercase());
+    let mut change: i128 = 0;
+
+    for log in receipt["logs"].as_array().into_iter().flatten() {
+        let is_transfer = log["address"]
+            .as_str()
+            .is_some_and(|address| address.eq_ignore_ascii_case(token))
+            && log["topics"][0] == ERC20_TRANSFER_TOPIC;
+        if !is_transfer {
+            continue;
+        }
+        let is_topic = |i: usize| {
+            log["topics"][i]
+                .as_str()
+                .is_some_and(|topic| topic.eq_ignore_ascii_case(&owner_topic))
+        };
+        let value = parse_hex(log["data"].as_str().unwrap_or("0x0"))? as i128;
+        if is_topic(2) {
+            change = change.saturating_add(value);
+        }
+        if is_topic(1) {
+            change = change.saturating_sub(value);
+        }
+    }
+
+    Ok(change)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+    const MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    /// SOL -> USDC swap paying 0.5 SOL (and a 5000 lamport fee) for 25 USDC
+    fn solana_swap() -> ChainTransaction {
+        ChainTransaction::Solana(json!({
+            "meta": {
+                "err": null,
+                "fee": 5000,
+                "preBalances": [2_000_000_000u64, 0],
+                "postBalances": [1_499_995_000u64, 0],
+                "preTokenBalances": [
+                    {
+                        "owner": OWNER,
+                        "mint": MINT,
+                        "uiTokenAmount": { "amount": "100", "decimals": 6 }
+                    }
+                ],
+                "postTokenBalances": [
+                    {
+                        "owner": OWNER,
+                        "mint": MINT,
+                        "uiTokenAmount": { "amount": "25000100", "decimals": 6 }
+                    },
+                    {
+                        "owner": "other",
+                        "mint": MINT,
+                        "uiTokenAmount": { "amount": "999", "decimals": 6 }
+                    }
+                ]
+            },
+            "transaction": {
+                "message": { "accountKeys": [{ "pubkey": OWNER }, { "pubkey": "other" }] }
+            }
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_solana_balance_change() {
+        let transaction = solana_swap();
+        let sol = SOLANA_NATIVE_MINTS[0];
+
+        assert!(transaction.error().is_none());
+        assert_eq!(transaction.balance_change(OWNER, MINT).unwrap(), 25_000_000);
+        assert_eq!(
+            transaction.balance_change(OWNER, sol).unwrap(),
+            -500_000_000
+        );
+        let caip2 = privy::caip2::Caip2::SOLANA;
+        assert_eq!(transaction.decimals(caip2, MINT).await, Some(6));
+        assert_eq!(transaction.decimals(caip2, sol).await, Some(9));
+    }
+
+    #[test]
+    fn test_solana_failed_transaction() {
+        let transaction = ChainTransaction::Solana(
+            json!({ "meta": { "err": { "InstructionError": [0, "Custom"] } } }),
+        );
+
+        assert!(transaction.error().is_some());
+    }
+
+    #[test]
+    fn test_evm_balance_change() {
+        let owner = "0xCCC48877a33a2C14e40c82da843Cf4c607ABF770";
+        let token = "0xaf88d065e77c8cC2239327C5EDb3A432268e5831";
+        let owner_topic = "0x000000000000000000000000ccc48877a33a2c14e40c82da843cf4c607abf770";
+        let receipt = ChainTransaction::Evm(json!({
+            "status": "0x1",
+            "logs": [
+                {
+                    "address": token.to_lowercase(),
+                    "topics": [ERC20_TRANSFER_TOPIC, "0x01", owner_topic],
+                    "data": "0x00000000000000000000000000000000000000000000000000000000000f4240"
+                },
+                {
+                    "address": token,
+                    "topics": [ERC20_TRANSFER_TOPIC, owner_topic, "0x02"],
+                    "data": "0x01"
+                }
+            ]
+        }));
+
+        assert!(receipt.error().is_none());
+        assert_eq!(receipt.balance_change(owner, token).unwrap(), 999_999);
+        assert!(ChainTransaction::Evm(json!({ "status": "0x0" }))
+            .error()
+            .is_some());
+    }
+}
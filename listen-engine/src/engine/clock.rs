@@ -0,0 +1,39 @@
+//! Source of the current time for pipeline evaluation. Live engines use the
+//! system clock, backtests advance a replay clock to the timestamp of each
+//! replayed price update so that time-based conditions, expiries and event
+//! timestamps line up with the history.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    /// unix timestamp in milliseconds
+    Replay(Arc<AtomicI64>),
+}
+
+impl Clock {
+    pub fn replay(start: DateTime<Utc>) -> Self {
+        Clock::Replay(Arc::new(AtomicI64::new(start.timestamp_millis())))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Replay(millis) => {
+                DateTime::from_timestamp_millis(millis.load(Ordering::SeqCst)).unwrap_or_default()
+            }
+        }
+    }
+
+    /// Moves a replay clock to `now`, the system clock can't be set
+    pub fn set(&self, now: DateTime<Utc>) {
+        if let Clock::Replay(millis) = self {
+            millis.store(now.timestamp_millis(), Ordering::SeqCst);
+        }
+    }
+}
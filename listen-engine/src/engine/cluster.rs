@@ -0,0 +1,612 @@
+//! Running several engine instances against the same Redis. Instances
+//! register with a heartbeat and every pipeline is owned by one live
+//! instance, picked with a consistent hash ring so that an instance joining
+//! or leaving only moves its own share of the pipelines. Evaluations and API
+//! mutations also hold a lease on the pipeline in the store, which keeps two
+//! instances from acting on the same pipeline while ownership moves.
+//!
+//! Every instance indexes every pipeline but only evaluates the ones it owns.
+//! API mutations are announced on `PIPELINE_INDEX_CHANNEL` so that the other
+//! instances can update their index, and the index is rebuilt from the store
+//! whenever the live instances change. An instance that dies stops
+//! heartbeating: after `INSTANCE_TTL` its pipelines move to the remaining
+//! instances, and the leases it held run out after `LEASE_TTL`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::engine::{
+    confirm::CONFIRMING_KEY,
+    pipeline::{Pipeline, Status},
+    Engine, EngineError,
+};
+
+pub const PIPELINE_INDEX_CHANNEL: &str = "pipeline_index";
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+pub const INSTANCE_TTL: Duration = Duration::from_secs(15);
+/// Renewed for as long as the evaluation runs, so it only bounds how long
+/// the pipelines of a dead instance stay locked
+pub const LEASE_TTL: Duration = Duration::from_secs(15);
+
+/// Points on the ring per instance, evens out the share of each instance
+const VIRTUAL_NODES: usize = 64;
+
+/// Lease held by the evaluation running in `Engine::with_lease`
+struct HeldLease {
+    pipeline_key: String,
+    /// set once an order went out, the evaluation then runs on to save it
+    sent: AtomicBool,
+    lost: tokio::sync::Notify,
+}
+
+tokio::task_local! {
+    static HELD_LEASE: Arc<HeldLease>;
+}
+
+/// Announced by the instance that changed the pipeline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexUpdate {
+    pub instance_id: String,
+    pub pipeline_key: String,
+}
+
+/// Consistent hash ring over the live instances
+#[derive(Debug, Default)]
+pub struct HashRing {
+    nodes: BTreeMap<u64, String>,
+}
+
+/// Has to agree between instances, so no `DefaultHasher`
+fn ring_hash(key: &str) -> u64 {
+    let digest = Sha256::digest(key.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+impl HashRing {
+    pub fn new(instances: &[String]) -> Self {
+        let nodes = instances
+            .iter()
+            .flat_map(|instance| {
+                (0..VIRTUAL_NODES)
+                    .map(move |i| (ring_hash(&format!("{}#{}", instance, i)), instance.clone()))
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    /// The first instance clockwise from the key
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        self.nodes
+            .range(ring_hash(key)..)
+            .chain(&self.nodes)
+            .next()
+            .map(|(_, instance)| instance.as_str())
+    }
+}
+
+/// This instance and the live instances it shares the pipelines with
+pub struct Cluster {
+    pub instance_id: String,
+    instances: RwLock<Vec<String>>,
+    ring: RwLock<HashRing>,
+}
+
+impl Cluster {
+    /// Starts out alone, owning every pipeline until the first heartbeat
+    pub fn new(instance_id: String) -> Self {
+        let instances = vec![instance_id.clone()];
+        Self {
+            ring: RwLock::new(HashRing::new(&instances)),
+            instances: RwLock::new(instances),
+            instance_id,
+        }
+    }
+
+    pub fn instances(&self) -> Vec<String> {
+        self.instances.read().clone()
+    }
+
+    pub fn owns(&self, pipeline_key: &str) -> bool {
+        self.ring
+            .read()
+            .owner(pipeline_key)
+            .is_none_or(|owner| owner == self.instance_id)
+    }
+
+    /// Returns true if the live instances changed
+    fn set_instances(&self, mut instances: Vec<String>) -> bool {
+        if !instances.contains(&self.instance_id) {
+            instances.push(self.instance_id.clone());
+        }
+        instances.sort();
+
+        let mut current = self.instances.write();
+        if *current == instances {
+            return false;
+        }
+        *self.ring.write() = HashRing::new(&instances);
+        *current = instances;
+        true
+    }
+}
+
+impl Engine {
+    pub fn owns_pipeline(&self, pipeline_key: &str) -> bool {
+        self.cluster.owns(pipeline_key)
+    }
+
+    /// Heartbeats and picks up instances that joined or died. Returns true if
+    /// the pipelines were re-sharded, the index then has to be reloaded.
+    pub async fn refresh_instances(&self) -> Result<bool, EngineError> {
+        let instances = self
+            .store
+            .heartbeat(&self.cluster.instance_id, INSTANCE_TTL)
+            .await
+            .map_err(EngineError::StoreError)?;
+        metrics::gauge!("engine_cluster_instances", instances.len() as f64);
+
+        if !self.cluster.set_instances(instances) {
+            return Ok(false);
+        }
+        tracing::info!(
+            instance_id = %self.cluster.instance_id,
+            instances = ?self.cluster.instances(),
+            "Live engine instances changed"
+        );
+        metrics::counter!("engine_cluster_rebalances", 1);
+        Ok(true)
+    }
+
+    /// Hands the pipelines of this instance over right away, instead of
+    /// after the heartbeat runs out
+    pub async fn leave_cluster(&self) {
+        if let Err(e) = self.store.leave(&self.cluster.instance_id).await {
+            tracing::error!("Failed to leave the engine cluster: {}", e);
+        }
+    }
+
+    /// Takes the processing lock of the pipeline, in this instance and across
+    /// instances. Returns false if the pipeline is already being processed.
+    pub async fn lock_pipeline(&self, pipeline_key: &str) -> Result<bool, EngineError> {
+        if !self
+            .processing_pipelines
+            .lock()
+            .await
+            .insert(pipeline_key.to_string())
+        {
+            return Ok(false);
+        }
+
+        match self.acquire_lease(pipeline_key).await {
+            Ok(true) => Ok(true),
+            result => {
+                self.processing_pipelines.lock().await.remove(pipeline_key);
+                result
+            }
+        }
+    }
+
+    pub async fn unlock_pipeline(&self, pipeline_key: &str) {
+        self.release_lease(pipeline_key).await;
+        self.processing_pipelines.lock().await.remove(pipeline_key);
+    }
+
+    pub(crate) async fn acquire_lease(&self, pipeline_key: &str) -> Result<bool, EngineError> {
+        let acquired = self
+            .store
+            .acquire_lease(pipeline_key, &self.cluster.instance_id, LEASE_TTL)
+            .await
+            .map_err(EngineError::StoreError)?;
+        if !acquired {
+            metrics::counter!("pipeline_lease_conflicts", 1);
+        }
+        Ok(acquired)
+    }
+
+    pub(crate) async fn release_lease(&self, pipeline_key: &str) {
+        if let Err(e) = self
+            .store
+            .release_lease(pipeline_key, &self.cluster.instance_id)
+            .await
+        {
+            // runs out on its own after LEASE_TTL
+            tracing::warn!(%pipeline_key, error = %e, "Failed to release pipeline lease");
+        }
+    }
+
+    /// Runs `task` while renewing the lease on the pipeline. If another
+    /// instance took the lease over, the task is dropped at its next await
+    /// point and `None` returned, unless it already sent an order: it then
+    /// runs on to save the transaction, see `hold_lease_for_send`.
+    pub(crate) async fn with_lease<F: Future>(
+        &self,
+        pipeline_key: &str,
+        task: F,
+    ) -> Option<F::Output> {
+        let lease = Arc::new(HeldLease {
+            pipeline_key: pipeline_key.to_string(),
+            sent: AtomicBool::new(false),
+            lost: tokio::sync::Notify::new(),
+        });
+        let mut renew = tokio::time::interval(LEASE_TTL / 3);
+        renew.tick().await;
+        let task = HELD_LEASE.scope(lease.clone(), task);
+        tokio::pin!(task);
+
+        loop {
+            tokio::select! {
+                output = &mut task => return Some(output),
+                _ = lease.lost.notified() => {
+                    tracing::error!(%pipeline_key, "Pipeline lease was lost before sending an order, aborting the evaluation");
+                    metrics::counter!("pipeline_leases_lost", 1);
+                    return None;
+                }
+                _ = renew.tick() => match self.acquire_lease(pipeline_key).await {
+                    Ok(true) => {}
+                    Ok(false) if lease.sent.load(Ordering::SeqCst) => {
+                        tracing::error!(%pipeline_key, "Pipeline lease was taken over after an order was sent, finishing the evaluation to save it");
+                        metrics::counter!("pipeline_leases_lost", 1);
+                        return Some(task.await);
+                    }
+                    Ok(false) => {
+                        tracing::error!(%pipeline_key, "Pipeline lease was taken over during evaluation, aborting it");
+                        metrics::counter!("pipeline_leases_lost", 1);
+                        return None;
+                    }
+                    Err(e) => {
+                        tracing::warn!(%pipeline_key, error = %e, "Failed to renew pipeline lease");
+                    }
+                },
+            }
+        }
+    }
+
+    /// Renews the lease of the running evaluation right before an order is
+    /// sent. If it can't be, the evaluation is dropped here, before anything
+    /// goes out. Otherwise the evaluation keeps running until the sent order
+    /// is saved, even if the lease is lost in the meantime. Nothing to do
+    /// outside of `with_lease`, e.g. in backtests.
+    pub(crate) async fn hold_lease_for_send(&self) {
+        let Ok(lease) = HELD_LEASE.try_with(Arc::clone) else {
+            return;
+        };
+        match self.acquire_lease(&lease.pipeline_key).await {
+            Ok(true) => lease.sent.store(true, Ordering::SeqCst),
+            result => {
+                if let Err(e) = result {
+                    tracing::warn!(pipeline_key = %lease.pipeline_key, error = %e, "Failed to renew pipeline lease before sending");
+                }
+                lease.lost.notify_one();
+                // `with_lease` drops the evaluation
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Adds a pipeline that can still run to every index it belongs in
+    pub fn index_pipeline(&self, pipeline: &Pipeline) {
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+        for asset_id in self.extract_assets(pipeline) {
+            self.active_pipelines
+                .entry(asset_id)
+                .or_default()
+                .insert(pipeline_key.clone());
+        }
+        if matches!(pipeline.status, Status::Paused) {
+            self.paused_pipelines.insert(pipeline_key);
+        } else {
+            self.paused_pipelines.remove(&pipeline_key);
+        }
+        self.index_confirming(pipeline);
+        self.index_expiry(pipeline);
+    }
+
+    /// Rebuilds the index from the store, finished pipelines stay in the store
+    /// for history but are never evaluated again. Returns the number of
+    /// indexed pipelines.
+    pub async fn load_pipelines(&self) -> Result<usize, EngineError> {
+        let pipelines = self
+            .store
+            .get_all_pipelines()
+            .await
+            .map_err(EngineError::StoreError)?;
+        tracing::info!("{} pipelines from the store", pipelines.len());
+
+        let runnable: Vec<Pipeline> = pipelines
+            .into_iter()
+            .filter(|pipeline| matches!(pipeline.status, Status::Pending | Status::Paused))
+            .collect();
+        let assets: HashMap<String, HashSet<String>> = runnable
+            .iter()
+            .map(|pipeline| {
+                (
+                    format!("{}:{}", pipeline.user_id, pipeline.id),
+                    self.extract_assets(pipeline).into_iter().collect(),
+                )
+            })
+            .collect();
+
+        // drop what changed since the index was built, then fill in the rest
+        for mut entry in self.active_pipelines.iter_mut() {
+            let (asset, pipeline_keys) = entry.pair_mut();
+            pipeline_keys.retain(|pipeline_key| {
+                assets.get(pipeline_key).is_some_and(|pipeline_assets| {
+                    asset == CONFIRMING_KEY || pipeline_assets.contains(asset)
+                })
+            });
+        }
+        self.paused_pipelines
+            .retain(|pipeline_key| assets.contains_key(pipeline_key));
+        self.pipeline_expiries
+            .retain(|pipeline_key, _| assets.contains_key(pipeline_key));
+
+        for pipeline in &runnable {
+            self.index_pipeline(pipeline);
+        }
+
+        Ok(runnable.len())
+    }
+
+    /// Re-reads a pipeline that another instance changed into the index
+    pub async fn reindex_pipeline(&self, pipeline_key: &str) -> Result<(), EngineError> {
+        let pipeline = self
+            .store
+            .get_pipelines(&[pipeline_key.to_string()])
+            .await
+            .map_err(EngineError::StoreError)?
+            .pop()
+            .flatten();
+
+        for mut entry in self.active_pipelines.iter_mut() {
+            entry.value_mut().remove(pipeline_key);
+        }
+        self.paused_pipelines.remove(pipeline_key);
+        self.pipeline_expiries.remove(pipeline_key);
+
+        if let Some(pipeline) =
+            pipeline.filter(|pipeline| matches!(pipeline.status, Status::Pending | Status::Paused))
+        {
+            self.index_pipeline(&pipeline);
+        }
+
+        Ok(())
+    }
+
+    /// Lets the other instances know the pipeline was changed through the API
+    pub async fn announce_index_update(&self, pipeline_key: &str) {
+        let update = IndexUpdate {
+            instance_id: self.cluster.instance_id.clone(),
+            pipeline_key: pipeline_key.to_string(),
+        };
+        if let Err(e) = self.store.publish_index_update(&update).await {
+            // the other instances catch up on the next re-shard
+            tracing::error!(%pipeline_key, error = %e, "Failed to announce index update");
+            metrics::counter!("pipeline_index_announce_errors", 1);
+        }
+    }
+}
+
+/// Applies the index updates announced by the other instances. Updates can
+/// be missed while reconnecting, so the index is reloaded after a reconnect.
+pub fn spawn_index_relay(engine: Arc<Engine>) {
+    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+
+    tokio::spawn(async move {
+        let mut reconnecting = false;
+        loop {
+            if let Err(e) = relay_index_updates(&redis_url, &engine, reconnecting).await {
+                tracing::error!("Pipeline index relay error: {}", e);
+                metrics::counter!("pipeline_index_relay_errors", 1);
+            }
+            reconnecting = true;
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+async fn relay_index_updates(
+    redis_url: &str,
+    engine: &Engine,
+    reload: bool,
+) -> Result<(), redis::RedisError> {
+    let client = redis::Client::open(redis_url)?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(PIPELINE_INDEX_CHANNEL).await?;
+
+    if reload {
+        if let Err(e) = engine.load_pipelines().await {
+            tracing::error!("Failed to reload pipelines: {}", e);
+        }
+    }
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let payload: String = msg.get_payload()?;
+        match serde_json::from_str::<IndexUpdate>(&payload) {
+            Ok(update) if update.instance_id == engine.cluster.instance_id => {}
+            Ok(update) => {
+                if let Err(e) = engine.reindex_pipeline(&update.pipeline_key).await {
+                    tracing::error!(pipeline_key = %update.pipeline_key, error = %e, "Failed to reindex pipeline");
+                }
+            }
+            Err(e) => tracing::warn!("Failed to parse index update: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::engine::events::PipelineEventKind;
+    use crate::engine::testing::{engine_on, price_update, settle, stand_in, webhook_pipeline};
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_hash_ring() {
+        let instances: Vec<String> = ["a", "b", "c"].map(String::from).to_vec();
+        let ring = HashRing::new(&instances);
+        let keys: Vec<String> = (0..3000).map(|i| format!("user:{}", i)).collect();
+
+        let mut shares: HashMap<&str, usize> = HashMap::new();
+        for key in &keys {
+            *shares.entry(ring.owner(key).unwrap()).or_default() += 1;
+        }
+        assert!(shares.values().all(|share| *share > 500), "{:?}", shares);
+
+        // only the keys of the instance that left move
+        let smaller = HashRing::new(&instances[..2]);
+        for key in &keys {
+            let owner = ring.owner(key).unwrap();
+            if owner != "c" {
+                assert_eq!(smaller.owner(key), Some(owner));
+            }
+        }
+        assert_eq!(HashRing::default().owner("user:1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_lease_excludes_other_instances() {
+        let store = Arc::new(MemoryStore::new());
+        let first = engine_on(store.clone());
+        let second = engine_on(store);
+
+        assert!(first.lock_pipeline("user:1").await.unwrap());
+        assert!(!first.lock_pipeline("user:1").await.unwrap());
+        assert!(!second.lock_pipeline("user:1").await.unwrap());
+        // a failed lease doesn't leave the local lock behind
+        assert!(second.processing_pipelines.lock().await.is_empty());
+
+        first.unlock_pipeline("user:1").await;
+        assert!(second.lock_pipeline("user:1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lease_is_checked_before_sending() {
+        let store = Arc::new(MemoryStore::new());
+        let engine = engine_on(store.clone());
+        let other = engine_on(store);
+        assert!(engine.acquire_lease("user:1").await.unwrap());
+
+        let sent = engine
+            .with_lease("user:1", async {
+                engine.hold_lease_for_send().await;
+                true
+            })
+            .await;
+        assert_eq!(sent, Some(true));
+
+        // taken over while the evaluation was running
+        let sent = engine
+            .with_lease("user:1", async {
+                engine.release_lease("user:1").await;
+                assert!(other.acquire_lease("user:1").await.unwrap());
+                engine.hold_lease_for_send().await;
+                true
+            })
+            .await;
+        assert_eq!(sent, None);
+
+        // no lease to check outside of an evaluation
+        engine.hold_lease_for_send().await;
+    }
+
+    #[tokio::test]
+    async fn test_evaluation_waits_for_a_held_lease() {
+        let store = Arc::new(MemoryStore::new());
+        let engine = engine_on(store.clone());
+        let other = engine_on(store);
+        let asset = Pubkey::new_unique().to_string();
+        let (url, handle) = stand_in(200).await;
+        let pipeline = webhook_pipeline(&asset, 2.0, &url);
+        let key = format!("user:{}", pipeline.id);
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        // e.g. a pause on the other instance
+        assert!(other.acquire_lease(&key).await.unwrap());
+        let mut evaluations = engine
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        let evaluation = evaluations.pop().unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!evaluation.is_finished());
+
+        other.release_lease(&key).await;
+        // evaluated once the lease is free, the update can be acked
+        assert!(evaluation.await.unwrap());
+        handle.await.unwrap();
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_sharded_evaluation_and_handoff() {
+        let store = Arc::new(MemoryStore::new());
+        let first = engine_on(store.clone());
+        let second = engine_on(store.clone());
+        first.refresh_instances().await.unwrap();
+        assert!(second.refresh_instances().await.unwrap());
+        assert!(first.refresh_instances().await.unwrap());
+        assert_eq!(first.cluster.instances(), second.cluster.instances());
+
+        let asset = Pubkey::new_unique().to_string();
+        let (url, handle) = stand_in(200).await;
+        let pipeline = webhook_pipeline(&asset, 2.0, &url);
+        let key = format!("user:{}", pipeline.id);
+        first.add_pipeline(&pipeline).await.unwrap();
+        // stands in for the index relay
+        second.reindex_pipeline(&key).await.unwrap();
+        assert!(second.active_pipelines.get(&asset).unwrap().contains(&key));
+        assert_ne!(first.owns_pipeline(&key), second.owns_pipeline(&key));
+
+        let (owner, other) = if first.owns_pipeline(&key) {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        // instances skip the pipelines they don't own
+        other
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        settle(&other).await;
+        assert!(store.events().is_empty());
+
+        // the owner dies, the other instance takes over its pipelines
+        owner.leave_cluster().await;
+        assert!(other.refresh_instances().await.unwrap());
+        assert!(other.owns_pipeline(&key));
+        assert_eq!(other.load_pipelines().await.unwrap(), 1);
+
+        other
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        settle(&other).await;
+        handle.await.unwrap();
+
+        let saved = other.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Completed));
+        let kinds: Vec<_> = store.events().into_iter().map(|e| e.kind).collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [PipelineEventKind::Triggered, PipelineEventKind::Completed]
+        ));
+    }
+}
@@ -1,13 +1,19 @@
+use crate::engine::copy::wallet_key;
 use crate::engine::pipeline::{Condition, ConditionType};
 use crate::engine::{Engine, Pipeline};
 use std::collections::HashSet;
 
 impl Engine {
-    /// Extract all unique assets mentioned in pipeline conditions
+    /// Extract all unique assets mentioned in pipeline conditions, time-based
+    /// conditions and sliced orders are indexed under "TIME" and picked up by
+    /// the scheduler tick, watched wallets under `wallet_key`
     pub fn extract_assets(&self, pipeline: &Pipeline) -> Vec<String> {
         let mut assets = HashSet::new();
         for step in pipeline.steps.values() {
             self.collect_assets_from_condition(&step.conditions, &mut assets);
+            if step.action.slicing().is_some() {
+                assets.insert("TIME".to_string());
+            }
         }
         assets.into_iter().collect()
     }
@@ -28,12 +34,27 @@ impl Engine {
                 ConditionType::PriceBelow { asset, .. } => {
                     assets.insert(asset.clone());
                 }
+                ConditionType::TrailingStop { asset, .. }
+                | ConditionType::MarketCapAbove { asset, .. }
+                | ConditionType::MarketCapBelow { asset, .. }
+                | ConditionType::RollingVolumeAbove { asset, .. }
+                | ConditionType::LargeBuy { asset, .. } => {
+                    assets.insert(asset.clone());
+                }
+                ConditionType::WalletTraded { wallet, .. } => {
+                    assets.insert(wallet_key(wallet));
+                }
                 ConditionType::And(sub_conditions) | ConditionType::Or(sub_conditions) => {
                     stack.extend(sub_conditions.iter());
                 }
                 ConditionType::Now { .. } => {
                     assets.insert("NOW".to_string());
                 }
+                ConditionType::At { .. }
+                | ConditionType::After { .. }
+                | ConditionType::Cron { .. } => {
+                    assets.insert("TIME".to_string());
+                }
             }
         }
     }
@@ -0,0 +1,233 @@
+//! Confirmation of sent order transactions. An executed order waits in
+//! `Status::Confirming` until its transaction confirms, only then does it
+//! complete and unlock its downstream steps, with the amounts it actually
+//! swapped recorded as its `Fill`.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::engine::{
+    chain::{self, ChainTransaction},
+    order::SwapOrder,
+    pipeline::{Action, Fill, Pipeline, PipelineStep, Status},
+    Engine, EngineError,
+};
+
+/// Index key of pipelines with steps waiting for confirmation, polled on the
+/// scheduler tick
+pub const CONFIRMING_KEY: &str = "CONFIRMING";
+
+/// Solana blockhashes expire after ~60-90s, a transaction that hasn't landed
+/// by then never will
+const SOLANA_CONFIRMATION_TIMEOUT_SECS: i64 = 120;
+const EVM_CONFIRMATION_TIMEOUT_SECS: i64 = 600;
+
+#[derive(Debug)]
+pub enum Confirmation {
+    Pending,
+    Confirmed(Fill),
+    Failed(String),
+}
+
+/// Polls the transaction of a step in `Status::Confirming`
+pub async fn check_confirmation(
+    step: &PipelineStep,
+    wallet_address: Option<&str>,
+    pubkey: Option<&str>,
+) -> Result<Confirmation, EngineError> {
+    let (Action::Order(order), Some(transaction_hash)) = (&step.action, &step.transaction_hash)
+    else {
+        return Ok(Confirmation::Failed(
+            "no transaction to confirm".to_string(),
+        ));
+    };
+    check_transaction(
+        order,
+        transaction_hash,
+        step.submitted_at,
+        wallet_address,
+        pubkey,
+    )
+    .await
+}
+
+/// Polls a sent order transaction, also used for the slices of DCA/TWAP orders
+pub async fn check_transaction(
+    order: &SwapOrder,
+    transaction_hash: &str,
+    submitted_at: Option<DateTime<Utc>>,
+    wallet_address: Option<&str>,
+    pubkey: Option<&str>,
+) -> Result<Confirmation, EngineError> {
+    let owner = if order.is_evm() {
+        wallet_address.ok_or(EngineError::EVMWalletNotAvailable)?
+    } else {
+        pubkey.ok_or(EngineError::SolanaWalletNotAvailable)?
+    };
+
+    let transaction = chain::get_transaction(&order.from_chain_caip2, transaction_hash)
+        .await
+        .map_err(EngineError::ChainError)?;
+
+    Ok(match transaction {
+        Some(transaction) => match transaction.error() {
+            Some(error) => Confirmation::Failed(format!("Transaction failed: {}", error)),
+            None => Confirmation::Confirmed(record_fill(&transaction, order, owner).await),
+        },
+        None => match submitted_at {
+            Some(submitted_at) if timed_out(order, submitted_at, Utc::now()) => {
+                Confirmation::Failed(format!(
+                    "Transaction {} did not confirm in time",
+                    transaction_hash
+                ))
+            }
+            _ => Confirmation::Pending,
+        },
+    })
+}
+
+fn timed_out(order: &SwapOrder, submitted_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    let timeout = if order.is_solana() {
+        SOLANA_CONFIRMATION_TIMEOUT_SECS
+    } else {
+        EVM_CONFIRMATION_TIMEOUT_SECS
+    };
+    now - submitted_at > Duration::seconds(timeout)
+}
+
+/// Reads the swapped amounts off the confirmed transaction, amounts that are
+/// not visible in it (e.g. native EVM input) are left empty
+async fn record_fill(transaction: &ChainTransaction, order: &SwapOrder, owner: &str) -> Fill {
+    let caip2 = order.from_chain_caip2.as_str();
+    let input_amount = transaction
+        .balance_change(owner, &order.input_token)
+        .ok()
+        .filter(|change| *change < 0)
+        .map(i128::unsigned_abs);
+    // bridged output arrives on the destination chain in another transaction
+    let output_amount = (order.from_chain_caip2 == order.to_chain_caip2)
+        .then(|| transaction.balance_change(owner, &order.output_token).ok())
+        .flatten()
+        .filter(|change| *change > 0)
+        .map(i128::unsigned_abs);
+
+    let effective_price = match (input_amount, output_amount) {
+        (Some(input_amount), Some(output_amount)) => {
+            match (
+                transaction.decimals(caip2, &order.input_token).await,
+                transaction.decimals(caip2, &order.output_token).await,
+            ) {
+                (Some(input_decimals), Some(output_decimals)) => {
+                    effective_price(input_amount, input_decimals, output_amount, output_decimals)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+
+    Fill {
+        input_amount: input_amount.map(|amount| amount.to_string()),
+        output_amount: output_amount.map(|amount| amount.to_string()),
+        effective_price,
+        confirmed_at: Utc::now(),
+    }
+}
+
+fn effective_price(
+    input_amount: u128,
+    input_decimals: u8,
+    output_amount: u128,
+    output_decimals: u8,
+) -> Option<f64> {
+    let input = input_amount as f64 / 10f64.powi(input_decimals as i32);
+    let output = output_amount as f64 / 10f64.powi(output_decimals as i32);
+    (output > 0.0).then(|| input / output)
+}
+
+impl Engine {
+    /// Keeps the pipeline in the confirmation index for as long as it has
+    /// steps waiting for their transactions
+    pub fn index_confirming(&self, pipeline: &Pipeline) {
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+        let confirming = matches!(pipeline.status, Status::Pending)
+            && pipeline.steps.values().any(PipelineStep::in_flight);
+
+        if confirming {
+            self.active_pipelines
+                .entry(CONFIRMING_KEY.to_string())
+                .or_default()
+                .insert(pipeline_key);
+        } else if let Some(mut pipeline_ids) = self.active_pipelines.get_mut(CONFIRMING_KEY) {
+            pipeline_ids.remove(&pipeline_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use privy::caip2::Caip2;
+    use serde_json::json;
+
+    const OWNER: &str = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+    const SOL: &str = "So11111111111111111111111111111111111111112";
+    const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    fn order(input_token: &str, output_token: &str) -> SwapOrder {
+        SwapOrder {
+            input_token: input_token.to_string(),
+            output_token: output_token.to_string(),
+            amount: "50%".to_string(),
+            from_chain_caip2: Caip2::SOLANA.to_string(),
+            to_chain_caip2: Caip2::SOLANA.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_fill() {
+        // 0.5 SOL (plus the fee) for 75 USDC
+        let transaction = ChainTransaction::Solana(json!({
+            "meta": {
+                "err": null,
+                "fee": 5000,
+                "preBalances": [1_000_000_000u64],
+                "postBalances": [499_995_000u64],
+                "preTokenBalances": [],
+                "postTokenBalances": [
+                    {
+                        "owner": OWNER,
+                        "mint": USDC,
+                        "uiTokenAmount": { "amount": "75000000", "decimals": 6 }
+                    }
+                ]
+            },
+            "transaction": { "message": { "accountKeys": [{ "pubkey": OWNER }] } }
+        }));
+
+        let fill = record_fill(&transaction, &order(SOL, USDC), OWNER).await;
+        assert_eq!(fill.input_amount.as_deref(), Some("500000000"));
+        assert_eq!(fill.output_amount.as_deref(), Some("75000000"));
+        let price = fill.effective_price.unwrap();
+        assert!((price - 0.5 / 75.0).abs() < 1e-12);
+
+        // nothing of the output token arrived
+        let fill = record_fill(&transaction, &order(USDC, SOL), OWNER).await;
+        assert!(fill.input_amount.is_none());
+        assert!(fill.effective_price.is_none());
+    }
+
+    #[test]
+    fn test_timed_out() {
+        let now = Utc::now();
+        let solana = order(SOL, USDC);
+        let evm = SwapOrder {
+            from_chain_caip2: Caip2::ARBITRUM.to_string(),
+            ..solana.clone()
+        };
+
+        assert!(!timed_out(&solana, now - Duration::seconds(60), now));
+        assert!(timed_out(&solana, now - Duration::seconds(180), now));
+        assert!(!timed_out(&evm, now - Duration::seconds(180), now));
+    }
+}
@@ -0,0 +1,401 @@
+//! Copy trading. `ConditionType::WalletTraded` fires on the swaps of a watched
+//! wallet: pipelines are indexed under `wallet_key(wallet)` next to their
+//! assets in `Engine::active_pipelines`, and the recent swaps of every watched
+//! wallet are queued in `Engine::wallet_trades`. The matched swap is stored on
+//! the condition, the step's order mirrors it with [`LEADER_MINT`] in place of
+//! the traded token and optionally a multiple of the leader's size as the
+//! amount (e.g. "0.5x").
+
+use std::collections::VecDeque;
+
+use crate::engine::{
+    amount::OrderAmount,
+    chain::{self, ChainError},
+    order::SwapOrder,
+    pipeline::{Condition, ConditionType, LeaderTrade},
+    Engine, EngineError,
+};
+use crate::redis::subscriber::PriceUpdate;
+
+/// Stands in for the token traded by the leader, as `input_token` to mirror
+/// sells and as `output_token` to mirror buys
+pub const LEADER_MINT: &str = "leader_mint";
+
+/// Prefix of the index keys of watched wallets
+pub const WALLET_KEY_PREFIX: &str = "wallet:";
+
+/// Swaps kept per watched wallet, enough for the ones arriving while its
+/// pipelines are being evaluated
+pub const MAX_QUEUED_TRADES: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CopyTradeError {
+    #[error("[CopyTrade] No copied trade, the step needs a WalletTraded condition")]
+    NoTrade,
+
+    #[error("[CopyTrade] No price for {0}")]
+    MissingPrice(String),
+
+    #[error("[CopyTrade] {0}")]
+    ChainError(#[from] ChainError),
+
+    #[error("[CopyTrade] Leader sized amount is zero")]
+    ZeroAmount,
+}
+
+pub fn wallet_key(wallet: &str) -> String {
+    format!("{}{}", WALLET_KEY_PREFIX, wallet)
+}
+
+/// Index keys that are asset prices, as opposed to the time keys and wallets
+pub fn is_priced_key(key: &str) -> bool {
+    key != "NOW" && key != "TIME" && !key.starts_with(WALLET_KEY_PREFIX)
+}
+
+impl From<&PriceUpdate> for LeaderTrade {
+    fn from(update: &PriceUpdate) -> Self {
+        LeaderTrade {
+            mint: update.pubkey.clone(),
+            is_buy: update.is_buy,
+            usd: update.swap_amount,
+            price: update.price,
+            signature: update.signature.clone(),
+            timestamp: update.timestamp,
+        }
+    }
+}
+
+/// Queues the swap unless it is already queued, replayed updates repeat
+/// signatures. The oldest swaps are dropped past [`MAX_QUEUED_TRADES`].
+pub fn queue_trade(trades: &mut VecDeque<LeaderTrade>, trade: LeaderTrade) {
+    if trades
+        .iter()
+        .any(|queued| queued.signature == trade.signature)
+    {
+        return;
+    }
+    if trades.len() >= MAX_QUEUED_TRADES {
+        trades.pop_front();
+    }
+    trades.push_back(trade);
+}
+
+/// The last trade matched by the conditions, nested ones included
+pub fn leader_trade(conditions: &[Condition]) -> Option<&LeaderTrade> {
+    let mut stack: Vec<&Condition> = conditions.iter().collect();
+    let mut latest: Option<&LeaderTrade> = None;
+    while let Some(condition) = stack.pop() {
+        match &condition.condition_type {
+            ConditionType::WalletTraded {
+                trade: Some(trade), ..
+            } if latest.is_none_or(|latest| trade.timestamp > latest.timestamp) => {
+                latest = Some(trade);
+            }
+            ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub.iter()),
+            _ => {}
+        }
+    }
+    latest
+}
+
+pub fn watches_wallet(conditions: &[Condition]) -> bool {
+    let mut stack: Vec<&Condition> = conditions.iter().collect();
+    while let Some(condition) = stack.pop() {
+        match &condition.condition_type {
+            ConditionType::WalletTraded { .. } => return true,
+            ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub.iter()),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether the order needs a copied trade to execute
+pub fn mirrors_leader(order: &SwapOrder) -> bool {
+    order.input_token == LEADER_MINT
+        || order.output_token == LEADER_MINT
+        || matches!(order.amount.parse(), Ok(OrderAmount::LeaderSize(_)))
+}
+
+pub(crate) fn usd_to_base_units(usd: f64, price: f64, decimals: u8) -> u128 {
+    if price <= 0.0 {
+        return 0;
+    }
+    (usd / price * 10f64.powi(decimals as i32)).floor() as u128
+}
+
+impl Engine {
+    /// Queues the swap if its wallet is watched, returns the wallet's index key
+    pub fn record_wallet_trade(&self, update: &PriceUpdate) -> Option<String> {
+        if update.owner.is_empty() {
+            return None;
+        }
+        let key = wallet_key(&update.owner);
+        let watched = self
+            .active_pipelines
+            .get(&key)
+            .is_some_and(|pipeline_ids| !pipeline_ids.is_empty());
+        if !watched {
+            return None;
+        }
+        queue_trade(
+            &mut self.wallet_trades.entry(update.owner.clone()).or_default(),
+            LeaderTrade::from(update),
+        );
+        Some(key)
+    }
+
+    /// Drops the queued swaps of wallets no pipeline watches anymore
+    pub(crate) fn prune_wallet_trades(&self, keys: &[String]) {
+        for key in keys {
+            let Some(wallet) = key.strip_prefix(WALLET_KEY_PREFIX) else {
+                continue;
+            };
+            let watched = self
+                .active_pipelines
+                .get(key)
+                .is_some_and(|pipeline_ids| !pipeline_ids.is_empty());
+            if !watched {
+                self.wallet_trades.remove(wallet);
+            }
+        }
+    }
+
+    /// Fills in the trade copied by the conditions, [`LEADER_MINT`] becomes
+    /// the traded token and leader sized amounts are converted to base units
+    /// of the input token. Other orders are returned as they are.
+    pub async fn mirror_order(
+        &self,
+        order: &SwapOrder,
+        conditions: &[Condition],
+    ) -> Result<SwapOrder, EngineError> {
+        if !mirrors_leader(order) {
+            return Ok(order.clone());
+        }
+        let trade =
+            leader_trade(conditions).ok_or(EngineError::CopyTradeError(CopyTradeError::NoTrade))?;
+
+        let mut mirrored = order.clone();
+        for token in [&mut mirrored.input_token, &mut mirrored.output_token] {
+            if token == LEADER_MINT {
+                *token = trade.mint.clone();
+            }
+        }
+
+        if let Ok(OrderAmount::LeaderSize(ratio)) = order.amount.parse() {
+            let input_token = mirrored.input_token.as_str();
+            let price = if input_token == trade.mint {
+                Some(trade.price)
+            } else {
+                self.token_price(input_token).await
+            }
+            .ok_or_else(|| {
+                EngineError::CopyTradeError(CopyTradeError::MissingPrice(input_token.to_string()))
+            })?;
+            let decimals = chain::token_decimals(&mirrored.from_chain_caip2, input_token)
+                .await
+                .map_err(|e| EngineError::CopyTradeError(e.into()))?;
+
+            let amount = usd_to_base_units(trade.usd * ratio, price, decimals);
+            if amount == 0 {
+                return Err(EngineError::CopyTradeError(CopyTradeError::ZeroAmount));
+            }
+            mirrored.amount = amount.to_string();
+        }
+
+        tracing::info!(leader_signature = %trade.signature, ?mirrored, "Mirrored leader trade");
+        Ok(mirrored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use privy::caip2::Caip2;
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use std::collections::VecDeque;
+
+    use crate::engine::{
+        api::{PipelineParams, WirePipeline},
+        chain::SOLANA_NATIVE_MINTS,
+        constants::TEST_ADDRESS_SOL,
+        market::MarketSnapshot,
+        pipeline::{Action, Pipeline, PriceGuard, Status},
+        testing::{engine, price_update, settle},
+    };
+
+    #[test]
+    fn test_usd_to_base_units() {
+        // $75 of SOL at $150
+        assert_eq!(usd_to_base_units(75.0, 150.0, 9), 500_000_000);
+        assert_eq!(usd_to_base_units(10.0, 1.0, 6), 10_000_000);
+        assert_eq!(usd_to_base_units(10.0, 0.0, 6), 0);
+    }
+
+    #[test]
+    fn test_queue_trade() {
+        let trade = |signature: &str| LeaderTrade {
+            mint: "A".to_string(),
+            is_buy: true,
+            usd: 1.0,
+            price: 1.0,
+            signature: signature.to_string(),
+            timestamp: 1,
+        };
+        let mut trades = VecDeque::new();
+        queue_trade(&mut trades, trade("s0"));
+        queue_trade(&mut trades, trade("s0"));
+        assert_eq!(trades.len(), 1);
+
+        for i in 1..=MAX_QUEUED_TRADES {
+            queue_trade(&mut trades, trade(&format!("s{}", i)));
+        }
+        assert_eq!(trades.len(), MAX_QUEUED_TRADES);
+        assert_eq!(trades.front().unwrap().signature, "s1");
+    }
+
+    #[tokio::test]
+    async fn test_mirror_order() {
+        let (engine, _) = engine();
+        let mint = Pubkey::new_unique().to_string();
+        let order = SwapOrder {
+            input_token: SOLANA_NATIVE_MINTS[0].to_string(),
+            output_token: LEADER_MINT.to_string(),
+            amount: "0.5x".to_string(),
+            from_chain_caip2: Caip2::SOLANA.to_string(),
+            to_chain_caip2: Caip2::SOLANA.to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            engine.mirror_order(&order, &[]).await,
+            Err(EngineError::CopyTradeError(CopyTradeError::NoTrade))
+        ));
+
+        engine.price_cache.write().await.insert(
+            SOLANA_NATIVE_MINTS[0].to_string(),
+            MarketSnapshot {
+                price: 150.0,
+                ..Default::default()
+            },
+        );
+        let conditions = vec![Condition {
+            condition_type: ConditionType::WalletTraded {
+                wallet: TEST_ADDRESS_SOL.to_string(),
+                side: Default::default(),
+                min_usd: 0.0,
+                trade: Some(LeaderTrade {
+                    mint: mint.clone(),
+                    is_buy: true,
+                    usd: 300.0,
+                    price: 0.01,
+                    signature: "sig".to_string(),
+                    timestamp: 1,
+                }),
+            },
+            triggered: false,
+            last_evaluated: None,
+            guard: PriceGuard::default(),
+            confirmations: 0,
+            observed: None,
+        }];
+
+        let mirrored = engine.mirror_order(&order, &conditions).await.unwrap();
+        assert_eq!(mirrored.output_token, mint);
+        // half of the leader's $300, in lamports at $150
+        assert_eq!(mirrored.amount, "1000000000");
+    }
+
+    #[tokio::test]
+    async fn test_wallet_trade_triggers_copy() {
+        let (mut engine, _) = engine();
+        // fills at the cached prices without building transactions
+        engine.backtest = true;
+        let engine = Arc::new(engine);
+        let leader = Pubkey::new_unique().to_string();
+        let mint = Pubkey::new_unique().to_string();
+
+        let wire: WirePipeline = serde_json::from_value(serde_json::json!({
+            "steps": [{
+                "action": {
+                    "type": "SwapOrder",
+                    "input_token": SOLANA_NATIVE_MINTS[0],
+                    "output_token": LEADER_MINT,
+                    "amount": "1000000"
+                },
+                "conditions": [{
+                    "type": "WalletTraded",
+                    "wallet": leader,
+                    "side": "Buy",
+                    "value": 1000.0
+                }]
+            }]
+        }))
+        .unwrap();
+        let pipeline = Pipeline::try_from((
+            wire,
+            PipelineParams {
+                user_id: "user".to_string(),
+                wallet_address: None,
+                pubkey: Some(TEST_ADDRESS_SOL.to_string()),
+            },
+        ))
+        .unwrap();
+        engine.add_pipeline(&pipeline).await.unwrap();
+        let key = format!("user:{}", pipeline.id);
+        assert!(engine
+            .active_pipelines
+            .get(&wallet_key(&leader))
+            .unwrap()
+            .contains(&key));
+
+        let leader_update = |swap_amount: f64, is_buy: bool| {
+            let mut update = price_update(&mint, 0.01);
+            update.owner = leader.clone();
+            update.swap_amount = swap_amount;
+            update.is_buy = is_buy;
+            update.signature = format!("{}-{}", swap_amount, is_buy);
+            update
+        };
+
+        // too small, the wrong side, and someone else's swap
+        engine
+            .handle_price_update(&leader_update(500.0, true))
+            .await
+            .unwrap();
+        engine
+            .handle_price_update(&leader_update(5000.0, false))
+            .await
+            .unwrap();
+        let mut other = leader_update(5000.0, true);
+        other.owner = Pubkey::new_unique().to_string();
+        engine.handle_price_update(&other).await.unwrap();
+        settle(&engine).await;
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Pending));
+
+        engine
+            .handle_price_update(&leader_update(5000.0, true))
+            .await
+            .unwrap();
+        settle(&engine).await;
+
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Completed));
+        let step = saved.steps.values().next().unwrap();
+        assert!(step.simulated_fill.is_some());
+        let Action::Order(order) = &step.action else {
+            panic!("expected an order");
+        };
+        // the stored order keeps the placeholder, the fill mirrors the trade
+        assert_eq!(order.output_token, LEADER_MINT);
+        assert!(matches!(
+            leader_trade(&step.conditions),
+            Some(trade) if trade.mint == mint && trade.usd == 5000.0
+        ));
+        // no pipeline watches the leader anymore
+        assert!(!engine.wallet_trades.contains_key(&leader));
+    }
+}
@@ -1,23 +1,34 @@
+use crate::engine::amount::AmountError;
+use crate::engine::api::WirePipelineError;
+use crate::engine::chain::ChainError;
+use crate::engine::copy::CopyTradeError;
 use crate::engine::evaluator::EvaluatorError;
+use crate::engine::executor::ExecutorError;
 use crate::engine::order::SwapOrderError;
+use crate::engine::rebalance::RebalanceError;
+use crate::engine::risk::RiskLimitError;
 use crate::redis::client::RedisClientError;
 use crate::redis::subscriber::RedisSubscriberError;
+use crate::store::StoreError;
 use privy::config::PrivyConfigError;
 use privy::PrivyError;
 
 #[derive(Debug, thiserror::Error)]
 pub enum EngineError {
     #[error("[Engine] Failed to add pipeline: {0}")]
-    AddPipelineError(RedisClientError),
+    AddPipelineError(StoreError),
 
     #[error("[Engine] Pipeline not found: {0}")]
     PipelineNotFound(String),
 
+    #[error("[Engine] Pipeline is being processed, try again: {0}")]
+    PipelineBusy(String),
+
     #[error("[Engine] Failed to save pipeline: {0}")]
-    SavePipelineError(RedisClientError),
+    SavePipelineError(StoreError),
 
     #[error("[Engine] Failed to delete pipeline: {0}")]
-    DeletePipelineError(RedisClientError),
+    DeletePipelineError(StoreError),
 
     #[error("[Engine] Failed to get pipeline: {0}")]
     GetPipelineError(String),
@@ -32,14 +43,32 @@ pub enum EngineError {
     HandlePriceUpdateError(anyhow::Error),
 
     #[error("[Engine] Transaction error: {0}")]
-    TransactionError(privy::tx::PrivyTransactionError),
+    TransactionError(ExecutorError),
 
     #[error("[Engine] Swap order error: {0}")]
     SwapOrderError(SwapOrderError),
 
+    #[error("[Engine] Order amount error: {0}")]
+    AmountError(AmountError),
+
+    #[error("[Engine] Copy trade error: {0}")]
+    CopyTradeError(CopyTradeError),
+
+    #[error("[Engine] Rebalance error: {0}")]
+    RebalanceError(RebalanceError),
+
+    #[error("[Engine] Risk limit: {0}")]
+    RiskLimitError(RiskLimitError),
+
+    #[error("[Engine] Chain error: {0}")]
+    ChainError(ChainError),
+
     #[error("[Engine] Redis client error: {0}")]
     RedisClientError(RedisClientError),
 
+    #[error("[Engine] Store error: {0}")]
+    StoreError(StoreError),
+
     #[error("[Engine] Redis subscriber error: {0}")]
     RedisSubscriberError(RedisSubscriberError),
 
@@ -49,6 +78,9 @@ pub enum EngineError {
     #[error("[Engine] Privy config error: {0}")]
     PrivyConfigError(PrivyConfigError),
 
+    #[error("[Engine] Order executor config error: {0}")]
+    ExecutorConfigError(String),
+
     #[error("[Engine] Blockhash cache error: {0}")]
     BlockhashCacheError(blockhash_cache::BlockhashCacheError),
 
@@ -70,6 +102,15 @@ pub enum EngineError {
     #[error("[Engine] Step not cancellable")]
     StepNotCancellable,
 
+    #[error("[Engine] Invalid pipeline status: {0}")]
+    InvalidPipelineStatus(String),
+
+    #[error("[Engine] Step not editable, only pending steps can be edited")]
+    StepNotEditable,
+
+    #[error("[Engine] Invalid step edit: {0}")]
+    InvalidStepEdit(WirePipelineError),
+
     #[error("[Engine] Unauthorized")]
     Unauthorized,
 }
@@ -4,7 +4,7 @@
 //! again
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     str::FromStr,
     time::Instant,
 };
@@ -15,9 +15,17 @@ use uuid::Uuid;
 
 use crate::{
     engine::{
+        amount::resolve_order_amount,
+        confirm::{check_confirmation, Confirmation},
+        copy::{is_priced_key, WALLET_KEY_PREFIX},
         error::EngineError,
-        evaluator::Evaluator,
-        pipeline::{Action, ConditionType, Pipeline, PipelineStep, Status},
+        evaluator::{EvaluationContext, Evaluator},
+        events::PipelineEventKind,
+        execute::ExecutionContext,
+        market::MarketSnapshot,
+        pipeline::{Action, ConditionType, LeaderTrade, Pipeline, PipelineStep, Status},
+        rebalance::RebalanceOutcome,
+        slices::SliceProgress,
     },
     Engine,
 };
@@ -33,21 +41,21 @@ impl Engine {
         &self,
         pipeline: &mut Pipeline,
     ) -> Result<bool, EngineError> {
-        // Get the initial price cache
-        let mut price_cache = self.price_cache.read().await.clone();
-
         // Extract all assets needed for this pipeline
         let needed_assets = self.extract_assets(pipeline);
 
         // Check for missing prices and try to fetch them from Redis
-        let missing_assets: Vec<_> = needed_assets
-            .iter()
-            .filter(|asset| !price_cache.contains_key(*asset) && *asset != "NOW")
-            .collect();
+        let missing_assets: Vec<_> = {
+            let price_cache = self.price_cache.read().await;
+            needed_assets
+                .iter()
+                .filter(|asset| !price_cache.contains_key(*asset) && is_priced_key(asset))
+                .collect()
+        };
 
         // Validate that all assets are valid Solana pubkeys
         for asset in &needed_assets {
-            if *asset != "NOW" && !self.is_valid_solana_asset(asset) {
+            if is_priced_key(asset) && !self.is_valid_solana_asset(asset) {
                 // First identify which steps use the invalid asset
                 let mut failed_steps = Vec::new();
                 let mut steps_to_cancel = Vec::new();
@@ -104,9 +112,8 @@ impl Engine {
                 missing_assets.len()
             );
             for asset in missing_assets {
-                if let Some(price) = self.fetch_price_from_redis(asset).await {
-                    tracing::debug!("Found price for {} in Redis: {}", asset, price);
-                    price_cache.insert(asset.clone(), price);
+                if let Some(snapshot) = self.fetch_snapshot_from_store(asset).await {
+                    tracing::debug!("Found price for {} in Redis: {}", asset, snapshot.price);
                 }
             }
         }
@@ -131,7 +138,7 @@ impl Engine {
             // Add entry steps that are still pending
             for step_id in entry_steps {
                 if let Some(step) = pipeline.steps.get(&step_id) {
-                    if matches!(step.status, Status::Pending) {
+                    if matches!(step.status, Status::Pending | Status::Confirming) {
                         pipeline.current_steps.push(step_id);
                     }
                 }
@@ -165,18 +172,41 @@ impl Engine {
     pub async fn process_all_steps(
         &self,
         pipeline: &mut Pipeline,
-        price_cache: &HashMap<String, f64>,
+        price_cache: &HashMap<String, MarketSnapshot>,
+        wallet_trades: &HashMap<String, VecDeque<LeaderTrade>>,
         pipeline_hash: &mut String,
     ) -> Result<(), EngineError> {
         // Collect indexes of steps to remove after processing
         let mut steps_to_remove = Vec::new();
         let mut steps_to_add = Vec::new();
 
+        let ctx = EvaluationContext {
+            market: price_cache,
+            wallet_trades,
+            now: self.clock.now(),
+            pipeline_created_at: pipeline.created_at,
+        };
+        let pipeline_id = pipeline.id;
+        let user_id = pipeline.user_id.clone();
+        let dry_run = self.dry_run || pipeline.dry_run;
+
         // Use index-based iteration to allow dropping the mutable borrow
         let mut i = 0;
         while i < pipeline.current_steps.len() {
             let current_step_id = pipeline.current_steps[i];
             let mut step_status_changed = false;
+            let mut step_executed = false;
+            // the step filled, the rest of its OCO group is cancelled
+            let mut oco_settled = false;
+            // whether the step's conditions were met and it acted on them
+            let mut fired = false;
+            let sibling_in_flight = pipeline.oco_sibling_in_flight(current_step_id);
+            // relative order amounts can depend on the parent step
+            let parents: Vec<PipelineStep> = pipeline
+                .parent_steps(current_step_id)
+                .into_iter()
+                .cloned()
+                .collect();
 
             if let Some(step) = pipeline.steps.get_mut(&current_step_id) {
                 match step.status {
@@ -188,25 +218,100 @@ impl Engine {
                         }
                     }
                     Status::Pending => {
-                        match Evaluator::evaluate_conditions(&step.conditions, price_cache) {
+                        // sliced orders that started and rebalances in flight are
+                        // past their conditions, OCO steps wait while a
+                        // sibling's transaction is in flight, it may still fail
+                        let triggered =
+                            match (sibling_in_flight, step.is_slicing() || step.in_flight()) {
+                                (true, _) => Ok(false),
+                                (false, true) => Ok(true),
+                                (false, false) => {
+                                    let met =
+                                        Evaluator::evaluate_conditions(&mut step.conditions, &ctx);
+                                    fired = matches!(met, Ok(true));
+                                    met
+                                }
+                            };
+                        match triggered {
                             Ok(true) => match &step.action {
                                 Action::Order(order) => {
-                                    let order = order.clone();
-                                    match self
-                                        .execute_order(
-                                            &order,
-                                            &pipeline.user_id,
-                                            pipeline.wallet_address.clone(),
-                                            pipeline.pubkey.clone(),
-                                        )
-                                        .await
-                                    {
-                                        Ok(transaction_hash) => {
-                                            step.status = Status::Completed;
-                                            step.transaction_hash = Some(transaction_hash);
-                                            step_status_changed = true;
+                                    let resolved =
+                                        match self.mirror_order(order, &step.conditions).await {
+                                            Ok(order) => {
+                                                resolve_order_amount(
+                                                    &order,
+                                                    pipeline.wallet_address.as_deref(),
+                                                    pipeline.pubkey.as_deref(),
+                                                    &parents,
+                                                )
+                                                .await
+                                            }
+                                            Err(e) => Err(e),
+                                        };
+                                    let result = match resolved {
+                                        // the parent's transaction has not confirmed
+                                        // yet, retry on the next evaluation
+                                        Ok(None) => None,
+                                        Ok(Some(order)) => {
+                                            self.publish_event(
+                                                &user_id,
+                                                pipeline_id,
+                                                current_step_id,
+                                                PipelineEventKind::Triggered,
+                                            )
+                                            .await;
+                                            self.publish_event(
+                                                &user_id,
+                                                pipeline_id,
+                                                current_step_id,
+                                                PipelineEventKind::Executing,
+                                            )
+                                            .await;
+                                            Some(
+                                                self.execute_order(
+                                                    &order,
+                                                    &pipeline.user_id,
+                                                    pipeline.wallet_address.clone(),
+                                                    pipeline.pubkey.clone(),
+                                                    dry_run,
+                                                )
+                                                .await,
+                                            )
                                         }
                                         Err(e) => {
+                                            self.publish_event(
+                                                &user_id,
+                                                pipeline_id,
+                                                current_step_id,
+                                                PipelineEventKind::Triggered,
+                                            )
+                                            .await;
+                                            Some(Err(e))
+                                        }
+                                    };
+                                    match result {
+                                        None => {
+                                            fired = false;
+                                            tracing::debug!(%current_step_id, "Waiting for the parent transaction to confirm");
+                                        }
+                                        Some(Ok(executed)) => {
+                                            oco_settled = executed.simulated_fill.is_some();
+                                            if executed.simulated_fill.is_none() {
+                                                // completes once the transaction confirms
+                                                step.status = Status::Confirming;
+                                                step.submitted_at = Some(self.clock.now());
+                                            } else if !step.is_recurring() {
+                                                // recurring steps stay pending for the next run
+                                                step.status = Status::Completed;
+                                            }
+                                            step.transaction_hash = Some(executed.transaction_hash);
+                                            step.simulated_fill = executed.simulated_fill;
+                                            step.reserved_usd = executed.reserved_usd;
+                                            step.fill = None;
+                                            step_status_changed = true;
+                                            step_executed = true;
+                                        }
+                                        Some(Err(e)) => {
                                             step.status = Status::Failed;
                                             step.transaction_hash = None;
                                             step.error = Some(e.to_string());
@@ -255,7 +360,14 @@ impl Engine {
                                     }
                                 }
                                 Action::Notification(notification) => {
-                                    tracing::info!(%current_step_id, ?notification, "Sending notification");
+                                    tracing::info!(%current_step_id, message = %notification.message, "Sending notification");
+                                    self.publish_event(
+                                        &user_id,
+                                        pipeline_id,
+                                        current_step_id,
+                                        PipelineEventKind::Triggered,
+                                    )
+                                    .await;
                                     match self
                                         .send_notification(&pipeline.user_id, notification)
                                         .await
@@ -267,8 +379,12 @@ impl Engine {
                                                 "Notification sent: {}",
                                                 res
                                             );
-                                            step.status = Status::Completed;
+                                            if !step.is_recurring() {
+                                                step.status = Status::Completed;
+                                            }
                                             step_status_changed = true;
+                                            step_executed = true;
+                                            oco_settled = true;
                                         }
                                         Err(e) => {
                                             tracing::error!(
@@ -282,6 +398,102 @@ impl Engine {
                                         }
                                     }
                                 }
+                                Action::Dca { .. } | Action::Twap { .. } => {
+                                    let slicing = ExecutionContext {
+                                        user_id: &user_id,
+                                        pipeline_id,
+                                        wallet_address: pipeline.wallet_address.as_deref(),
+                                        pubkey: pipeline.pubkey.as_deref(),
+                                        dry_run,
+                                    };
+                                    let progress =
+                                        self.advance_slices(step, &parents, &slicing).await;
+                                    // settled by the first slice that fills
+                                    oco_settled = step
+                                        .slices
+                                        .iter()
+                                        .any(|slice| matches!(slice.status, Status::Completed));
+                                    match progress {
+                                        Ok(SliceProgress::Waiting) => {}
+                                        Ok(SliceProgress::Progressed) => {
+                                            step_status_changed = true;
+                                        }
+                                        Ok(SliceProgress::Executed) => {
+                                            step_status_changed = true;
+                                            step_executed = true;
+                                        }
+                                        Ok(SliceProgress::Filled) => {
+                                            step_status_changed = true;
+                                            if step.is_recurring() {
+                                                // re-armed, the next run plans new slices
+                                                step.slices.clear();
+                                                step.remaining_amount = None;
+                                            } else {
+                                                step.status = Status::Completed;
+                                                steps_to_remove.push(i);
+                                                steps_to_add.extend(step.next_steps.clone());
+                                            }
+                                        }
+                                        Ok(SliceProgress::Failed(error)) => {
+                                            tracing::warn!(%current_step_id, %error, "Sliced order failed");
+                                            step.status = Status::Failed;
+                                            step.error = Some(error);
+                                            step_status_changed = true;
+
+                                            for next_step_id in step.next_steps.clone() {
+                                                pipeline.cancel_step_and_downstream(next_step_id);
+                                            }
+                                            steps_to_remove.push(i);
+                                        }
+                                        Err(e) => {
+                                            tracing::warn!(%current_step_id, error = %e, "Failed to advance sliced order, will retry");
+                                        }
+                                    }
+                                }
+                                Action::Rebalance(_) => {
+                                    let ctx = ExecutionContext {
+                                        user_id: &user_id,
+                                        pipeline_id,
+                                        wallet_address: pipeline.wallet_address.as_deref(),
+                                        pubkey: pipeline.pubkey.as_deref(),
+                                        dry_run,
+                                    };
+                                    match self.rebalance(step, &ctx).await {
+                                        Ok(RebalanceOutcome::Waiting) => {}
+                                        Ok(RebalanceOutcome::WithinDrift) => {
+                                            // persists the time of the check
+                                            step_status_changed = true;
+                                        }
+                                        Ok(RebalanceOutcome::InFlight) => {
+                                            // the swaps publish their own
+                                            // transaction events
+                                            step_status_changed = true;
+                                        }
+                                        Ok(RebalanceOutcome::Rebalanced) => {
+                                            step_status_changed = true;
+                                            oco_settled = true;
+                                            if !step.is_recurring() {
+                                                step.status = Status::Completed;
+                                                steps_to_remove.push(i);
+                                                steps_to_add.extend(step.next_steps.clone());
+                                            }
+                                        }
+                                        Ok(RebalanceOutcome::Failed(error)) => {
+                                            tracing::warn!(%current_step_id, %error, "Rebalance failed");
+                                            step.status = Status::Failed;
+                                            step.error = Some(error);
+                                            step_status_changed = true;
+
+                                            for next_step_id in step.next_steps.clone() {
+                                                pipeline.cancel_step_and_downstream(next_step_id);
+                                            }
+                                            steps_to_remove.push(i);
+                                        }
+                                        Err(e) => {
+                                            tracing::warn!(%current_step_id, error = %e, "Failed to rebalance, will retry");
+                                        }
+                                    }
+                                }
                             },
                             Ok(false) => {
                                 // Conditions not met yet, keep step in current_steps
@@ -325,16 +537,120 @@ impl Engine {
                             }
                         }
                     }
+                    Status::Confirming => {
+                        match check_confirmation(
+                            step,
+                            pipeline.wallet_address.as_deref(),
+                            pipeline.pubkey.as_deref(),
+                        )
+                        .await
+                        {
+                            Ok(Confirmation::Pending) => {}
+                            Ok(Confirmation::Confirmed(fill)) => {
+                                tracing::info!(%current_step_id, ?fill, "Transaction confirmed");
+                                step.fill = Some(fill);
+                                step.reserved_usd = 0;
+                                step_status_changed = true;
+                                oco_settled = true;
+                                if step.is_recurring() {
+                                    // re-armed for the next run
+                                    step.status = Status::Pending;
+                                } else {
+                                    step.status = Status::Completed;
+                                    steps_to_remove.push(i);
+                                    steps_to_add.extend(step.next_steps.clone());
+                                }
+                            }
+                            Ok(Confirmation::Failed(reason)) => {
+                                tracing::warn!(%current_step_id, %reason, "Transaction did not confirm");
+                                // nothing was swapped, the notional is free again
+                                self.release_notional(
+                                    &user_id,
+                                    std::mem::take(&mut step.reserved_usd),
+                                )
+                                .await;
+                                step.status = Status::Failed;
+                                step.error = Some(reason);
+                                step_status_changed = true;
+
+                                // the swap never landed, nothing downstream may act on it
+                                for next_step_id in step.next_steps.clone() {
+                                    pipeline.cancel_step_and_downstream(next_step_id);
+                                }
+                                steps_to_remove.push(i);
+                            }
+                            Err(e) => {
+                                tracing::warn!(%current_step_id, error = %e, "Failed to check transaction confirmation, will retry");
+                            }
+                        }
+                    }
                     Status::Failed | Status::Cancelled => {
                         // Remove failed or cancelled steps from current_steps
                         steps_to_remove.push(i);
                     }
+                    // Only pipelines are paused, never individual steps
+                    Status::Paused => {}
                 }
             } else {
                 // Step not found, mark for removal
                 steps_to_remove.push(i);
             }
 
+            // cron conditions move on to their next run only once the step
+            // fired, not whenever they are due
+            if fired {
+                if let Some(step) = pipeline.steps.get_mut(&current_step_id) {
+                    Evaluator::advance_schedules(&mut step.conditions, ctx.now);
+                    step_status_changed = true;
+                }
+            }
+
+            // Cancel the rest of the OCO group once the step filled, before
+            // anything else gets evaluated, so that only one of the steps can
+            // ever execute
+            if oco_settled {
+                let cancelled = pipeline.cancel_oco_siblings(current_step_id);
+                if !cancelled.is_empty() {
+                    tracing::info!(%current_step_id, ?cancelled, "Cancelled OCO siblings");
+                }
+                for step_id in cancelled {
+                    self.publish_event(
+                        &user_id,
+                        pipeline_id,
+                        step_id,
+                        PipelineEventKind::Cancelled,
+                    )
+                    .await;
+                }
+            }
+
+            // Publish the outcome of the step
+            if step_status_changed {
+                if let Some(step) = pipeline.steps.get(&current_step_id) {
+                    if step_executed {
+                        if let Some(transaction_hash) = &step.transaction_hash {
+                            let kind = PipelineEventKind::TransactionSubmitted {
+                                transaction_hash: transaction_hash.clone(),
+                                dry_run: step.simulated_fill.is_some(),
+                            };
+                            self.publish_event(&user_id, pipeline_id, current_step_id, kind)
+                                .await;
+                        }
+                    }
+                    let kind = match step.status {
+                        Status::Completed => Some(PipelineEventKind::Completed),
+                        Status::Failed => Some(PipelineEventKind::Failed {
+                            error: step.error.clone().unwrap_or_default(),
+                        }),
+                        _ => None,
+                    };
+                    if let Some(kind) = kind {
+                        self.publish_event(&user_id, pipeline_id, current_step_id, kind)
+                            .await;
+                    }
+                }
+            }
+
             // Save the pipeline if the step's status changed
             if step_status_changed {
                 self.save_pipeline(pipeline, pipeline_hash).await?;
@@ -343,9 +659,15 @@ impl Engine {
             i += 1;
         }
 
-        // Add new steps to current_steps
+        // Add new steps to current_steps, steps with multiple dependencies
+        // are only added once all of them have completed
         for step_id in steps_to_add {
-            if !pipeline.current_steps.contains(&step_id) {
+            let dependencies_completed = pipeline
+                .steps
+                .values()
+                .filter(|s| s.next_steps.contains(&step_id))
+                .all(|s| matches!(s.status, Status::Completed));
+            if dependencies_completed && !pipeline.current_steps.contains(&step_id) {
                 pipeline.current_steps.push(step_id);
             }
         }
@@ -369,12 +691,12 @@ impl Engine {
         let all_steps_have_final_status = pipeline
             .steps
             .values()
-            .all(|step| !matches!(step.status, Status::Pending));
+            .all(|step| !matches!(step.status, Status::Pending | Status::Confirming));
 
         let has_pending_steps = pipeline
             .steps
             .values()
-            .any(|step| matches!(step.status, Status::Pending));
+            .any(|step| matches!(step.status, Status::Pending | Status::Confirming));
 
         // Pipeline is done if all steps have a final status or if there are no current steps
         // and no pending steps that could be activated
@@ -406,9 +728,21 @@ impl Engine {
         let start = Instant::now();
         counter!("pipeline_evaluations", 1);
 
+        // Expired steps must not execute, even if their conditions are met
+        if self.apply_expiry(pipeline, self.clock.now()).await
+            && matches!(pipeline.status, Status::Cancelled)
+        {
+            self.store
+                .save_pipeline(pipeline)
+                .await
+                .map_err(EngineError::SavePipelineError)?;
+            histogram!("pipeline_evaluation_duration", start.elapsed());
+            return Ok(true);
+        }
+
         match self.ensure_prices_available(pipeline).await {
             Ok(true) => {
-                self.redis
+                self.store
                     .save_pipeline(pipeline)
                     .await
                     .map_err(EngineError::SavePipelineError)?;
@@ -424,8 +758,24 @@ impl Engine {
             Ok(false) => {} // false means keep going
         }
 
-        // Clone the price cache after ensuring prices are available
-        let price_cache = self.price_cache.read().await.clone();
+        // Snapshot the market state of the pipeline's assets after ensuring prices are available
+        let assets = self.extract_assets(pipeline);
+        let price_cache: HashMap<String, MarketSnapshot> = {
+            let cache = self.price_cache.read().await;
+            assets
+                .iter()
+                .filter_map(|asset| cache.get(asset).map(|s| (asset.clone(), s.clone())))
+                .collect()
+        };
+        let wallet_trades: HashMap<String, VecDeque<LeaderTrade>> = assets
+            .iter()
+            .filter_map(|key| key.strip_prefix(WALLET_KEY_PREFIX))
+            .filter_map(|wallet| {
+                self.wallet_trades
+                    .get(wallet)
+                    .map(|trades| (wallet.to_string(), trades.clone()))
+            })
+            .collect();
 
         let mut pipeline_hash = pipeline.hash();
 
@@ -435,7 +785,7 @@ impl Engine {
         // If still empty after populating, check if we have any pending steps that aren't in current_steps
         if pipeline.current_steps.is_empty() {
             for (step_id, step) in &pipeline.steps {
-                if matches!(step.status, Status::Pending) {
+                if matches!(step.status, Status::Pending | Status::Confirming) {
                     pipeline.current_steps.push(*step_id);
                 }
             }
@@ -444,7 +794,7 @@ impl Engine {
         self.save_pipeline(pipeline, &mut pipeline_hash).await?;
 
         if !pipeline.current_steps.is_empty() {
-            self.process_all_steps(pipeline, &price_cache, &mut pipeline_hash)
+            self.process_all_steps(pipeline, &price_cache, &wallet_trades, &mut pipeline_hash)
                 .await?;
             self.save_pipeline(pipeline, &mut pipeline_hash).await?;
         }
@@ -458,17 +808,20 @@ impl Engine {
         Ok(pipeline_done)
     }
 
-    async fn fetch_price_from_redis(&self, asset: &str) -> Option<f64> {
-        if let Ok(price) = self.redis.get_price(asset).await {
+    async fn fetch_snapshot_from_store(&self, asset: &str) -> Option<MarketSnapshot> {
+        if let Ok(Some(update)) = self.store.get_price_update(asset).await {
             metrics::counter!("redis_price_fallback_hits", 1);
 
             // Update the shared in-memory cache for future lookups
+            let snapshot = MarketSnapshot::from_price_update(&update);
             {
                 let mut cache = self.price_cache.write().await;
-                cache.insert(asset.to_string(), price);
+                cache
+                    .entry(asset.to_string())
+                    .or_insert_with(|| snapshot.clone());
             }
 
-            return Some(price);
+            return Some(snapshot);
         }
 
         metrics::counter!("redis_price_fallback_misses", 1);
@@ -495,7 +848,7 @@ impl Engine {
         if pipeline.hash() != *pipeline_hash {
             tracing::info!("Saving pipeline: {}", pipeline.id);
             *pipeline_hash = pipeline.hash();
-            self.redis
+            self.store
                 .save_pipeline(pipeline)
                 .await
                 .map_err(EngineError::SavePipelineError)
@@ -504,3 +857,110 @@ impl Engine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::engine::{
+        api::{PipelineParams, WirePipeline},
+        constants::{TEST_ADDRESS_SOL, USDC_MINT},
+        risk::RiskLimitsUpdate,
+        testing::engine,
+    };
+
+    #[tokio::test]
+    async fn test_oco_sibling_survives_failed_confirmation() {
+        let (mut engine, _) = engine();
+        engine.backtest = true;
+        let asset = Pubkey::new_unique().to_string();
+        let exit = |id: &str, condition: &str, value: f64| {
+            json!({
+                "id": id,
+                "oco_group": "exit",
+                "action": {
+                    "type": "SwapOrder",
+                    "input_token": asset,
+                    "output_token": USDC_MINT,
+                    "amount": "1000"
+                },
+                "conditions": [{ "type": condition, "asset": asset, "value": value }]
+            })
+        };
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [exit("take_profit", "PriceAbove", 2.0), exit("stop_loss", "PriceBelow", 1.0)]
+        }))
+        .unwrap();
+        let mut pipeline = Pipeline::try_from((
+            wire,
+            PipelineParams {
+                user_id: "user".to_string(),
+                wallet_address: None,
+                pubkey: Some(TEST_ADDRESS_SOL.to_string()),
+            },
+        ))
+        .unwrap();
+        let step_id = |condition: fn(&ConditionType) -> bool| {
+            *pipeline
+                .steps
+                .iter()
+                .find(|(_, step)| condition(&step.conditions[0].condition_type))
+                .unwrap()
+                .0
+        };
+        let take_profit = step_id(|c| matches!(c, ConditionType::PriceAbove { .. }));
+        let stop_loss = step_id(|c| matches!(c, ConditionType::PriceBelow { .. }));
+
+        // the take profit was sent, its transaction never landed
+        engine
+            .set_risk_limits(
+                "user",
+                &RiskLimitsUpdate {
+                    max_daily_usd: Some(150),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let sent = pipeline.steps.get_mut(&take_profit).unwrap();
+        sent.status = Status::Confirming;
+        sent.submitted_at = Some(engine.clock.now());
+        sent.reserved_usd = engine.reserve_notional("user", 90.0).await.unwrap();
+        pipeline.current_steps = vec![stop_loss, take_profit];
+
+        let prices = HashMap::from([(
+            asset.clone(),
+            MarketSnapshot {
+                price: 0.5,
+                ..Default::default()
+            },
+        )]);
+        let mut hash = pipeline.hash();
+
+        // the stop loss holds off while the take profit is confirming
+        engine
+            .process_all_steps(&mut pipeline, &prices, &HashMap::new(), &mut hash)
+            .await
+            .unwrap();
+        assert!(matches!(
+            pipeline.steps[&take_profit].status,
+            Status::Failed
+        ));
+        assert!(matches!(pipeline.steps[&stop_loss].status, Status::Pending));
+        assert!(pipeline.steps[&stop_loss].transaction_hash.is_none());
+        // nothing was swapped, the reservation is released
+        let limits = engine.get_risk_limits("user").await.unwrap();
+        assert_eq!(limits.daily_notional_usd.unwrap().remaining, 150);
+
+        // and executes once it failed
+        engine
+            .process_all_steps(&mut pipeline, &prices, &HashMap::new(), &mut hash)
+            .await
+            .unwrap();
+        assert!(matches!(
+            pipeline.steps[&stop_loss].status,
+            Status::Completed
+        ));
+    }
+}
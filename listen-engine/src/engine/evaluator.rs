@@ -1,9 +1,24 @@
-use super::pipeline::{Condition, ConditionType};
+use super::market::{MarketSnapshot, MAX_VOLUME_WINDOW_SECS};
+use super::pipeline::{
+    Condition, ConditionType, LeaderTrade, ObservedPrice, PriceGuard, PriceSource,
+};
 use crate::engine::EngineError;
-use std::collections::HashMap;
+use chrono::{DateTime, TimeDelta, Utc};
+use cron::Schedule;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
 
 pub struct Evaluator;
 
+/// Everything conditions are evaluated against
+pub struct EvaluationContext<'a> {
+    pub market: &'a HashMap<String, MarketSnapshot>,
+    /// recent swaps of each watched wallet, oldest first
+    pub wallet_trades: &'a HashMap<String, VecDeque<LeaderTrade>>,
+    pub now: DateTime<Utc>,
+    pub pipeline_created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum EvaluatorError {
     #[error("[Evaluator] Failed to evaluate conditions: {0}")]
@@ -26,39 +41,829 @@ impl From<EvaluatorError> for EngineError {
 }
 
 impl Evaluator {
+    /// Conditions are taken mutably since some of them (e.g. trailing stops)
+    /// carry state that is updated on every evaluation. All conditions are
+    /// evaluated, without short-circuiting, so that this state stays current.
     pub fn evaluate_conditions(
-        conditions: &[Condition],
-        prices: &HashMap<String, f64>,
+        conditions: &mut [Condition],
+        ctx: &EvaluationContext,
     ) -> Result<bool, EvaluatorError> {
-        conditions.iter().try_fold(true, |acc, c| {
-            Ok(acc && Self::evaluate_condition(c, prices)?)
+        conditions.iter_mut().try_fold(true, |acc, c| {
+            let result = Self::evaluate_condition(c, ctx)?;
+            Ok(acc && result)
         })
     }
 
     fn evaluate_condition(
-        condition: &Condition,
-        prices: &HashMap<String, f64>,
+        condition: &mut Condition,
+        ctx: &EvaluationContext,
+    ) -> Result<bool, EvaluatorError> {
+        let result = Self::evaluate_condition_type(condition, ctx)?;
+        condition.triggered = result;
+        condition.last_evaluated = Some(ctx.now);
+        Ok(result)
+    }
+
+    fn evaluate_condition_type(
+        condition: &mut Condition,
+        ctx: &EvaluationContext,
     ) -> Result<bool, EvaluatorError> {
-        match &condition.condition_type {
+        let Condition {
+            condition_type,
+            guard,
+            confirmations,
+            observed,
+            ..
+        } = condition;
+        match condition_type {
             ConditionType::PriceAbove { asset, value } => {
-                let price = prices
-                    .get(asset)
-                    .ok_or_else(|| EvaluatorError::MissingPriceData(asset.clone()))?;
-                Ok(price >= value)
+                let Some(price) = Self::guarded_price(ctx, asset, guard)? else {
+                    return Ok(false);
+                };
+                let held = price.price >= *value;
+                Ok(Self::confirm(held, price, guard, confirmations, observed))
             }
             ConditionType::PriceBelow { asset, value } => {
-                let price = prices
-                    .get(asset)
-                    .ok_or_else(|| EvaluatorError::MissingPriceData(asset.clone()))?;
-                Ok(price <= value)
+                let Some(price) = Self::guarded_price(ctx, asset, guard)? else {
+                    return Ok(false);
+                };
+                let held = price.price <= *value;
+                Ok(Self::confirm(held, price, guard, confirmations, observed))
+            }
+            ConditionType::MarketCapAbove { asset, value } => {
+                let Some(price) = Self::guarded_price(ctx, asset, guard)? else {
+                    return Ok(false);
+                };
+                let held = price.market_cap >= *value;
+                Ok(Self::confirm(held, price, guard, confirmations, observed))
+            }
+            ConditionType::MarketCapBelow { asset, value } => {
+                let Some(price) = Self::guarded_price(ctx, asset, guard)? else {
+                    return Ok(false);
+                };
+                let held = price.market_cap <= *value;
+                Ok(Self::confirm(held, price, guard, confirmations, observed))
+            }
+            ConditionType::RollingVolumeAbove {
+                asset,
+                window,
+                value,
+            } => {
+                if *window == 0 || *window > MAX_VOLUME_WINDOW_SECS {
+                    return Err(EvaluatorError::InvalidConditionType(format!(
+                        "volume window must be between 1 and {} seconds, got {}",
+                        MAX_VOLUME_WINDOW_SECS, window
+                    )));
+                }
+                let snapshot = Self::snapshot(ctx, asset)?;
+                let volume = snapshot.rolling_volume(*window, ctx.now.timestamp() as u64);
+                Ok(volume >= *value)
             }
-            ConditionType::And(sub) => sub.iter().try_fold(true, |acc, c| {
-                Ok(acc && Self::evaluate_condition(c, prices)?)
+            ConditionType::LargeBuy { asset, min_usd } => {
+                let snapshot = Self::snapshot(ctx, asset)?;
+                let created_at = ctx.pipeline_created_at.timestamp() as u64;
+                Ok(snapshot.last_trade.as_ref().is_some_and(|trade| {
+                    trade.is_buy && trade.usd >= *min_usd && trade.timestamp >= created_at
+                }))
+            }
+            ConditionType::WalletTraded {
+                wallet,
+                side,
+                min_usd,
+                trade,
+            } => {
+                let Some(trades) = ctx.wallet_trades.get(wallet.as_str()) else {
+                    return Ok(false);
+                };
+                // every swap is copied once, in the order they were made: the
+                // ones queued after the copied swap, or made since if it was
+                // dropped from the queue
+                let unseen = match trade.as_ref() {
+                    Some(copied) => match trades
+                        .iter()
+                        .position(|queued| queued.signature == copied.signature)
+                    {
+                        Some(index) => index + 1,
+                        None => trades
+                            .iter()
+                            .position(|queued| queued.timestamp >= copied.timestamp)
+                            .unwrap_or(trades.len()),
+                    },
+                    None => 0,
+                };
+                let created_at = ctx.pipeline_created_at.timestamp() as u64;
+                let next = trades.iter().skip(unseen).find(|queued| {
+                    queued.timestamp >= created_at
+                        && side.matches(queued.is_buy)
+                        && queued.usd >= *min_usd
+                });
+                match next {
+                    Some(next) => {
+                        *trade = Some(next.clone());
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                }
+            }
+            ConditionType::TrailingStop {
+                asset,
+                trail_pct,
+                peak,
+            } => {
+                if !(*trail_pct > 0.0 && *trail_pct < 100.0) {
+                    return Err(EvaluatorError::InvalidConditionType(format!(
+                        "trailing stop percentage must be between 0 and 100, got {}",
+                        trail_pct
+                    )));
+                }
+                let Some(price) = Self::guarded_price(ctx, asset, guard)? else {
+                    return Ok(false);
+                };
+                let high = peak.map_or(price.price, |p| p.max(price.price));
+                *peak = Some(high);
+                let held = price.price <= high * (1.0 - *trail_pct / 100.0);
+                Ok(Self::confirm(held, price, guard, confirmations, observed))
+            }
+            ConditionType::And(sub) => sub.iter_mut().try_fold(true, |acc, c| {
+                let result = Self::evaluate_condition(c, ctx)?;
+                Ok(acc && result)
             }),
-            ConditionType::Or(sub) => sub.iter().try_fold(false, |acc, c| {
-                Ok(acc || Self::evaluate_condition(c, prices)?)
+            ConditionType::Or(sub) => sub.iter_mut().try_fold(false, |acc, c| {
+                let result = Self::evaluate_condition(c, ctx)?;
+                Ok(acc || result)
             }),
             ConditionType::Now { .. } => Ok(true),
+            ConditionType::At { timestamp } => Ok(ctx.now >= *timestamp),
+            ConditionType::After {
+                duration_since_created,
+            } => {
+                let due = i64::try_from(*duration_since_created)
+                    .ok()
+                    .and_then(TimeDelta::try_seconds)
+                    .and_then(|delay| ctx.pipeline_created_at.checked_add_signed(delay))
+                    .ok_or_else(|| {
+                        EvaluatorError::InvalidConditionType(format!(
+                            "delay of {} seconds is out of range",
+                            duration_since_created
+                        ))
+                    })?;
+                Ok(ctx.now >= due)
+            }
+            ConditionType::Cron { expr, next_run } => {
+                let schedule = Schedule::from_str(expr).map_err(|e| {
+                    EvaluatorError::InvalidConditionType(format!(
+                        "invalid cron expression {}: {}",
+                        expr, e
+                    ))
+                })?;
+                let scheduled = match next_run {
+                    Some(next_run) => *next_run,
+                    None => match schedule.after(&ctx.pipeline_created_at).next() {
+                        Some(next_run) => next_run,
+                        None => return Ok(false),
+                    },
+                };
+                // stays due until the step fires, see `advance_schedules`
+                *next_run = Some(scheduled);
+                Ok(ctx.now >= scheduled)
+            }
+        }
+    }
+
+    /// Re-arms the due cron conditions for their first run after now,
+    /// skipping any missed runs. Called once the step fires, so that a due
+    /// cron condition waits for the rest of the step's conditions
+    pub fn advance_schedules(conditions: &mut [Condition], now: DateTime<Utc>) {
+        for condition in conditions {
+            match &mut condition.condition_type {
+                ConditionType::Cron { expr, next_run }
+                    if next_run.is_some_and(|scheduled| now >= scheduled) =>
+                {
+                    *next_run = Schedule::from_str(expr)
+                        .ok()
+                        .and_then(|schedule| schedule.after(&now).next());
+                }
+                ConditionType::And(sub) | ConditionType::Or(sub) => {
+                    Self::advance_schedules(sub, now)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Price of the asset per the guard's source, `None` if it is stale or
+    /// there were no swaps within the window
+    fn guarded_price(
+        ctx: &EvaluationContext,
+        asset: &str,
+        guard: &PriceGuard,
+    ) -> Result<Option<ObservedPrice>, EvaluatorError> {
+        let snapshot = Self::snapshot(ctx, asset)?;
+        let now = ctx.now.timestamp().max(0) as u64;
+        if guard
+            .max_age_secs
+            .is_some_and(|max_age| now.saturating_sub(snapshot.timestamp) > max_age)
+        {
+            return Ok(None);
+        }
+        let price = match guard.source {
+            PriceSource::Last => Some(snapshot.price),
+            PriceSource::Vwap { window } => snapshot.vwap(window, now),
+            PriceSource::Median { window } => snapshot.median_price(window, now),
+        };
+        Ok(price.map(|price| ObservedPrice {
+            price,
+            market_cap: match snapshot.price > 0.0 {
+                true => snapshot.market_cap * price / snapshot.price,
+                false => snapshot.market_cap,
+            },
+            slot: snapshot.slot,
+            timestamp: snapshot.timestamp,
+        }))
+    }
+
+    /// Counts the consecutive prints the condition held on, a print is only
+    /// counted once however often the condition is evaluated on it
+    fn confirm(
+        held: bool,
+        price: ObservedPrice,
+        guard: &PriceGuard,
+        confirmations: &mut u32,
+        observed: &mut Option<ObservedPrice>,
+    ) -> bool {
+        let new_print = observed.as_ref().is_none_or(|last| {
+            last.slot != price.slot
+                || last.timestamp != price.timestamp
+                || last.price != price.price
+        });
+        let required = guard.confirmations.unwrap_or(1);
+        // capped, so that a condition that keeps holding doesn't change state
+        if !held {
+            *confirmations = 0;
+        } else if new_print && *confirmations < required {
+            *confirmations += 1;
+        }
+        *observed = Some(price);
+        held && *confirmations >= required
+    }
+
+    fn snapshot<'a>(
+        ctx: &EvaluationContext<'a>,
+        asset: &str,
+    ) -> Result<&'a MarketSnapshot, EvaluatorError> {
+        ctx.market
+            .get(asset)
+            .ok_or_else(|| EvaluatorError::MissingPriceData(asset.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::LazyLock;
+
+    use super::*;
+    use crate::engine::pipeline::TradeSide;
+    use crate::redis::subscriber::PriceUpdate;
+    use chrono::Duration;
+
+    static NO_TRADES: LazyLock<HashMap<String, VecDeque<LeaderTrade>>> =
+        LazyLock::new(HashMap::new);
+
+    fn trailing_stop(asset: &str, trail_pct: f64) -> Condition {
+        Condition {
+            condition_type: ConditionType::TrailingStop {
+                asset: asset.to_string(),
+                trail_pct,
+                peak: None,
+            },
+            triggered: false,
+            last_evaluated: None,
+            guard: PriceGuard::default(),
+            confirmations: 0,
+            observed: None,
+        }
+    }
+
+    fn prices(asset: &str, price: f64) -> HashMap<String, MarketSnapshot> {
+        HashMap::from([(
+            asset.to_string(),
+            MarketSnapshot {
+                price,
+                ..Default::default()
+            },
+        )])
+    }
+
+    fn ctx(market: &HashMap<String, MarketSnapshot>) -> EvaluationContext<'_> {
+        EvaluationContext {
+            market,
+            wallet_trades: &NO_TRADES,
+            now: Utc::now(),
+            pipeline_created_at: Utc::now(),
         }
     }
+
+    fn condition(condition_type: ConditionType) -> Condition {
+        Condition {
+            condition_type,
+            triggered: false,
+            last_evaluated: None,
+            guard: PriceGuard::default(),
+            confirmations: 0,
+            observed: None,
+        }
+    }
+
+    #[test]
+    fn test_trailing_stop_follows_peak() {
+        let mut conditions = vec![trailing_stop("A", 10.0)];
+
+        assert!(
+            !Evaluator::evaluate_conditions(&mut conditions, &ctx(&prices("A", 100.0))).unwrap()
+        );
+        assert!(
+            !Evaluator::evaluate_conditions(&mut conditions, &ctx(&prices("A", 150.0))).unwrap()
+        );
+        // 10% below the initial price, but not below the new peak
+        assert!(
+            !Evaluator::evaluate_conditions(&mut conditions, &ctx(&prices("A", 140.0))).unwrap()
+        );
+        assert!(
+            Evaluator::evaluate_conditions(&mut conditions, &ctx(&prices("A", 135.0))).unwrap()
+        );
+
+        match &conditions[0].condition_type {
+            ConditionType::TrailingStop { peak, .. } => assert_eq!(*peak, Some(150.0)),
+            _ => panic!("Expected TrailingStop condition type"),
+        }
+    }
+
+    #[test]
+    fn test_trailing_stop_updates_peak_inside_and() {
+        let mut conditions = vec![Condition {
+            condition_type: ConditionType::And(vec![
+                Condition {
+                    condition_type: ConditionType::PriceAbove {
+                        asset: "A".to_string(),
+                        value: 1000.0,
+                    },
+                    triggered: false,
+                    last_evaluated: None,
+                    guard: PriceGuard::default(),
+                    confirmations: 0,
+                    observed: None,
+                },
+                trailing_stop("A", 10.0),
+            ]),
+            triggered: false,
+            last_evaluated: None,
+            guard: PriceGuard::default(),
+            confirmations: 0,
+            observed: None,
+        }];
+
+        assert!(
+            !Evaluator::evaluate_conditions(&mut conditions, &ctx(&prices("A", 200.0))).unwrap()
+        );
+
+        match &conditions[0].condition_type {
+            ConditionType::And(sub) => match &sub[1].condition_type {
+                ConditionType::TrailingStop { peak, .. } => assert_eq!(*peak, Some(200.0)),
+                _ => panic!("Expected TrailingStop condition type"),
+            },
+            _ => panic!("Expected And condition type"),
+        }
+    }
+
+    #[test]
+    fn test_trailing_stop_invalid_percentage() {
+        let mut conditions = vec![trailing_stop("A", 120.0)];
+        assert!(Evaluator::evaluate_conditions(&mut conditions, &ctx(&prices("A", 1.0))).is_err());
+    }
+
+    #[test]
+    fn test_at_and_after() {
+        let prices = HashMap::new();
+        let created_at = Utc::now();
+        let mut conditions = vec![
+            condition(ConditionType::At {
+                timestamp: created_at + Duration::seconds(30),
+            }),
+            condition(ConditionType::After {
+                duration_since_created: 60,
+            }),
+        ];
+
+        let mut at = |now| {
+            let ctx = EvaluationContext {
+                market: &prices,
+                wallet_trades: &NO_TRADES,
+                now,
+                pipeline_created_at: created_at,
+            };
+            Evaluator::evaluate_conditions(&mut conditions, &ctx).unwrap()
+        };
+
+        assert!(!at(created_at));
+        assert!(!at(created_at + Duration::seconds(45)));
+        assert!(at(created_at + Duration::seconds(60)));
+
+        // out of range delays fail instead of panicking
+        let mut overflowing = vec![condition(ConditionType::After {
+            duration_since_created: u64::MAX / 2,
+        })];
+        let ctx = EvaluationContext {
+            market: &prices,
+            wallet_trades: &NO_TRADES,
+            now: created_at,
+            pipeline_created_at: created_at,
+        };
+        assert!(Evaluator::evaluate_conditions(&mut overflowing, &ctx).is_err());
+    }
+
+    #[test]
+    fn test_cron_rearms() {
+        let prices = HashMap::new();
+        let created_at = DateTime::parse_from_rfc3339("2025-01-01T00:00:10Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        // every minute, on the minute
+        let mut conditions = vec![condition(ConditionType::Cron {
+            expr: "0 * * * * *".to_string(),
+            next_run: None,
+        })];
+
+        // fires the step whenever the conditions are met
+        let mut at = |now| {
+            let ctx = EvaluationContext {
+                market: &prices,
+                wallet_trades: &NO_TRADES,
+                now,
+                pipeline_created_at: created_at,
+            };
+            let met = Evaluator::evaluate_conditions(&mut conditions, &ctx).unwrap();
+            if met {
+                Evaluator::advance_schedules(&mut conditions, now);
+            }
+            met
+        };
+
+        assert!(!at(created_at + Duration::seconds(20)));
+        assert!(at(created_at + Duration::seconds(50)));
+        assert!(!at(created_at + Duration::seconds(55)));
+        assert!(at(created_at + Duration::seconds(110)));
+    }
+
+    #[test]
+    fn test_cron_in_and_waits_for_its_sibling() {
+        let created_at = DateTime::parse_from_rfc3339("2025-01-01T00:00:10Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut conditions = vec![condition(ConditionType::And(vec![
+            condition(ConditionType::Cron {
+                expr: "0 * * * * *".to_string(),
+                next_run: None,
+            }),
+            condition(ConditionType::PriceAbove {
+                asset: "A".to_string(),
+                value: 100.0,
+            }),
+        ]))];
+
+        let mut at = |now, price| {
+            let market = prices("A", price);
+            let ctx = EvaluationContext {
+                market: &market,
+                wallet_trades: &NO_TRADES,
+                now,
+                pipeline_created_at: created_at,
+            };
+            let met = Evaluator::evaluate_conditions(&mut conditions, &ctx).unwrap();
+            if met {
+                Evaluator::advance_schedules(&mut conditions, now);
+            }
+            met
+        };
+
+        // the run is due but the price is not, it stays due
+        assert!(!at(created_at + Duration::seconds(50), 90.0));
+        assert!(at(created_at + Duration::seconds(55), 110.0));
+        // fired, waits for the next run
+        assert!(!at(created_at + Duration::seconds(56), 110.0));
+        assert!(at(created_at + Duration::seconds(110), 110.0));
+    }
+
+    #[test]
+    fn test_cron_in_or_rearms_once_fired() {
+        let created_at = DateTime::parse_from_rfc3339("2025-01-01T00:00:10Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut conditions = vec![condition(ConditionType::Or(vec![
+            condition(ConditionType::Cron {
+                expr: "0 * * * * *".to_string(),
+                next_run: None,
+            }),
+            condition(ConditionType::PriceAbove {
+                asset: "A".to_string(),
+                value: 100.0,
+            }),
+        ]))];
+
+        let mut at = |now, price| {
+            let market = prices("A", price);
+            let ctx = EvaluationContext {
+                market: &market,
+                wallet_trades: &NO_TRADES,
+                now,
+                pipeline_created_at: created_at,
+            };
+            let met = Evaluator::evaluate_conditions(&mut conditions, &ctx).unwrap();
+            if met {
+                Evaluator::advance_schedules(&mut conditions, now);
+            }
+            met
+        };
+
+        assert!(!at(created_at + Duration::seconds(20), 90.0));
+        // fired by the schedule, the leg does not keep reporting true
+        assert!(at(created_at + Duration::seconds(50), 90.0));
+        assert!(!at(created_at + Duration::seconds(55), 90.0));
+        // fired by the price, the schedule is not due yet and stays armed
+        assert!(at(created_at + Duration::seconds(60), 110.0));
+        assert!(at(created_at + Duration::seconds(110), 90.0));
+        assert!(!at(created_at + Duration::seconds(115), 90.0));
+    }
+
+    #[test]
+    fn test_cron_invalid_expression() {
+        let prices = HashMap::new();
+        let mut conditions = vec![condition(ConditionType::Cron {
+            expr: "not a cron".to_string(),
+            next_run: None,
+        })];
+        assert!(Evaluator::evaluate_conditions(&mut conditions, &ctx(&prices)).is_err());
+    }
+
+    fn update(timestamp: u64, swap_amount: f64, is_buy: bool) -> PriceUpdate {
+        PriceUpdate {
+            name: "A".to_string(),
+            pubkey: "A".to_string(),
+            price: 1.0,
+            market_cap: 2_000_000.0,
+            timestamp,
+            slot: 1,
+            swap_amount,
+            owner: String::new(),
+            signature: String::new(),
+            multi_hop: false,
+            is_buy,
+            is_pump: false,
+        }
+    }
+
+    #[test]
+    fn test_market_conditions() {
+        let created_at = DateTime::from_timestamp(1000, 0).unwrap();
+        let mut snapshot = MarketSnapshot::default();
+        snapshot.apply(&update(900, 50_000.0, true), true);
+        snapshot.apply(&update(1010, 3_000.0, false), true);
+        snapshot.apply(&update(1020, 12_000.0, true), true);
+        let market = HashMap::from([("A".to_string(), snapshot)]);
+
+        let ctx = EvaluationContext {
+            market: &market,
+            wallet_trades: &NO_TRADES,
+            now: DateTime::from_timestamp(1030, 0).unwrap(),
+            pipeline_created_at: created_at,
+        };
+
+        let evaluate = |condition_type| {
+            Evaluator::evaluate_conditions(&mut [condition(condition_type)], &ctx).unwrap()
+        };
+
+        assert!(evaluate(ConditionType::MarketCapAbove {
+            asset: "A".to_string(),
+            value: 1_000_000.0,
+        }));
+        assert!(!evaluate(ConditionType::MarketCapBelow {
+            asset: "A".to_string(),
+            value: 1_000_000.0,
+        }));
+        // the 900 trade is outside of the window
+        assert!(evaluate(ConditionType::RollingVolumeAbove {
+            asset: "A".to_string(),
+            window: 60,
+            value: 15_000.0,
+        }));
+        assert!(!evaluate(ConditionType::RollingVolumeAbove {
+            asset: "A".to_string(),
+            window: 60,
+            value: 16_000.0,
+        }));
+        assert!(evaluate(ConditionType::LargeBuy {
+            asset: "A".to_string(),
+            min_usd: 10_000.0,
+        }));
+        assert!(!evaluate(ConditionType::LargeBuy {
+            asset: "A".to_string(),
+            min_usd: 20_000.0,
+        }));
+    }
+
+    fn print(timestamp: u64, slot: u64, price: f64, swap_amount: f64) -> PriceUpdate {
+        PriceUpdate {
+            price,
+            slot,
+            ..update(timestamp, swap_amount, true)
+        }
+    }
+
+    fn guarded(value: f64, guard: PriceGuard) -> Condition {
+        Condition {
+            guard,
+            ..condition(ConditionType::PriceBelow {
+                asset: "A".to_string(),
+                value,
+            })
+        }
+    }
+
+    #[test]
+    fn test_price_guard_ignores_stale_and_outlier_prices() {
+        let mut snapshot = MarketSnapshot::default();
+        snapshot.apply(&print(1000, 1, 1.0, 1_000.0), true);
+        snapshot.apply(&print(1010, 2, 1.02, 1_000.0), true);
+        // a small multi-hop swap printing far off the market
+        snapshot.apply(&print(1020, 3, 0.2, 50.0), true);
+        let market = HashMap::from([("A".to_string(), snapshot)]);
+        let ctx = |now: i64| EvaluationContext {
+            market: &market,
+            wallet_trades: &NO_TRADES,
+            now: DateTime::from_timestamp(now, 0).unwrap(),
+            pipeline_created_at: DateTime::from_timestamp(900, 0).unwrap(),
+        };
+
+        let mut last = vec![guarded(0.5, PriceGuard::default())];
+        assert!(Evaluator::evaluate_conditions(&mut last, &ctx(1025)).unwrap());
+        assert!(last[0].triggered);
+        assert!(matches!(
+            last[0].observed,
+            Some(ObservedPrice { price, slot: 3, .. }) if price == 0.2
+        ));
+
+        for source in [
+            PriceSource::Median { window: 60 },
+            PriceSource::Vwap { window: 60 },
+        ] {
+            let mut smoothed = vec![guarded(
+                0.5,
+                PriceGuard {
+                    source,
+                    ..Default::default()
+                },
+            )];
+            assert!(!Evaluator::evaluate_conditions(&mut smoothed, &ctx(1025)).unwrap());
+            assert!(smoothed[0].observed.as_ref().unwrap().price > 0.9);
+        }
+
+        let mut fresh_only = vec![guarded(
+            0.5,
+            PriceGuard {
+                max_age_secs: Some(5),
+                ..Default::default()
+            },
+        )];
+        assert!(Evaluator::evaluate_conditions(&mut fresh_only, &ctx(1025)).unwrap());
+        assert!(!Evaluator::evaluate_conditions(&mut fresh_only, &ctx(1030)).unwrap());
+    }
+
+    #[test]
+    fn test_price_guard_confirmations() {
+        let mut conditions = vec![guarded(
+            0.5,
+            PriceGuard {
+                confirmations: Some(2),
+                ..Default::default()
+            },
+        )];
+        let mut evaluate = |update: PriceUpdate| {
+            let market =
+                HashMap::from([("A".to_string(), MarketSnapshot::from_price_update(&update))]);
+            let ctx = EvaluationContext {
+                market: &market,
+                wallet_trades: &NO_TRADES,
+                now: DateTime::from_timestamp(1100, 0).unwrap(),
+                pipeline_created_at: DateTime::from_timestamp(1000, 0).unwrap(),
+            };
+            let result = Evaluator::evaluate_conditions(&mut conditions, &ctx).unwrap();
+            (result, conditions[0].confirmations)
+        };
+
+        assert_eq!(evaluate(print(1050, 1, 0.4, 100.0)), (false, 1));
+        // evaluated again on the same print, e.g. for another asset's update
+        assert_eq!(evaluate(print(1050, 1, 0.4, 100.0)), (false, 1));
+        assert_eq!(evaluate(print(1051, 2, 0.45, 100.0)), (true, 2));
+        assert_eq!(evaluate(print(1052, 3, 0.6, 100.0)), (false, 0));
+    }
+
+    #[test]
+    fn test_large_buy_ignores_trades_before_creation() {
+        let snapshot = MarketSnapshot::from_price_update(&update(900, 50_000.0, true));
+        let market = HashMap::from([("A".to_string(), snapshot)]);
+        let ctx = EvaluationContext {
+            market: &market,
+            wallet_trades: &NO_TRADES,
+            now: DateTime::from_timestamp(1030, 0).unwrap(),
+            pipeline_created_at: DateTime::from_timestamp(1000, 0).unwrap(),
+        };
+
+        let mut conditions = vec![condition(ConditionType::LargeBuy {
+            asset: "A".to_string(),
+            min_usd: 10_000.0,
+        })];
+        assert!(!Evaluator::evaluate_conditions(&mut conditions, &ctx).unwrap());
+    }
+
+    #[test]
+    fn test_wallet_traded_copies_each_swap_once() {
+        let market = HashMap::new();
+        let mut conditions = vec![condition(ConditionType::WalletTraded {
+            wallet: "W".to_string(),
+            side: TradeSide::Buy,
+            min_usd: 1_000.0,
+            trade: None,
+        })];
+        let mut evaluate = |signature: &str, is_buy: bool, usd: f64, timestamp: u64| {
+            let wallet_trades = HashMap::from([(
+                "W".to_string(),
+                VecDeque::from([leader_trade(signature, is_buy, usd, timestamp)]),
+            )]);
+            let ctx = EvaluationContext {
+                market: &market,
+                wallet_trades: &wallet_trades,
+                now: DateTime::from_timestamp(1030, 0).unwrap(),
+                pipeline_created_at: DateTime::from_timestamp(1000, 0).unwrap(),
+            };
+            Evaluator::evaluate_conditions(&mut conditions, &ctx).unwrap()
+        };
+
+        // made before the pipeline existed
+        assert!(!evaluate("s0", true, 5_000.0, 900));
+        assert!(evaluate("s1", true, 5_000.0, 1010));
+        assert!(!evaluate("s1", true, 5_000.0, 1010));
+        assert!(!evaluate("s2", false, 5_000.0, 1015));
+        assert!(!evaluate("s3", true, 500.0, 1020));
+        assert!(evaluate("s4", true, 2_000.0, 1025));
+    }
+
+    fn leader_trade(signature: &str, is_buy: bool, usd: f64, timestamp: u64) -> LeaderTrade {
+        LeaderTrade {
+            mint: "A".to_string(),
+            is_buy,
+            usd,
+            price: 1.0,
+            signature: signature.to_string(),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn test_wallet_traded_copies_queued_swaps_in_order() {
+        let market = HashMap::new();
+        let mut conditions = vec![condition(ConditionType::WalletTraded {
+            wallet: "W".to_string(),
+            side: TradeSide::Buy,
+            min_usd: 1_000.0,
+            trade: None,
+        })];
+        // both swaps arrived while the pipeline was being evaluated
+        let wallet_trades = HashMap::from([(
+            "W".to_string(),
+            VecDeque::from([
+                leader_trade("s1", true, 5_000.0, 1010),
+                leader_trade("s2", false, 5_000.0, 1010),
+                leader_trade("s3", true, 2_000.0, 1010),
+            ]),
+        )]);
+        let ctx = EvaluationContext {
+            market: &market,
+            wallet_trades: &wallet_trades,
+            now: DateTime::from_timestamp(1030, 0).unwrap(),
+            pipeline_created_at: DateTime::from_timestamp(1000, 0).unwrap(),
+        };
+        let mut copied = || {
+            Evaluator::evaluate_conditions(&mut conditions, &ctx)
+                .unwrap()
+                .then(|| match &conditions[0].condition_type {
+                    ConditionType::WalletTraded {
+                        trade: Some(trade), ..
+                    } => trade.signature.clone(),
+                    _ => unreachable!(),
+                })
+        };
+
+        assert_eq!(copied().as_deref(), Some("s1"));
+        assert_eq!(copied().as_deref(), Some("s3"));
+        assert_eq!(copied(), None);
+    }
 }
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Engine;
+
+/// Redis pub/sub channel step transitions are published on, every server
+/// instance relays it to its connected clients
+pub const PIPELINE_EVENTS_CHANNEL: &str = "pipeline_events";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PipelineEventKind {
+    Triggered,
+    Executing,
+    TransactionSubmitted {
+        transaction_hash: String,
+        /// the fill was simulated, nothing was sent on-chain
+        #[serde(default)]
+        dry_run: bool,
+    },
+    Completed,
+    Failed {
+        error: String,
+    },
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineEvent {
+    pub user_id: String,
+    pub pipeline_id: Uuid,
+    pub step_id: Uuid,
+    #[serde(flatten)]
+    pub kind: PipelineEventKind,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Engine {
+    /// Publishes a step transition, failures are logged and never interrupt
+    /// pipeline evaluation
+    pub async fn publish_event(
+        &self,
+        user_id: &str,
+        pipeline_id: Uuid,
+        step_id: Uuid,
+        kind: PipelineEventKind,
+    ) {
+        let event = PipelineEvent {
+            user_id: user_id.to_string(),
+            pipeline_id,
+            step_id,
+            kind,
+            timestamp: self.clock.now(),
+        };
+
+        if let Err(e) = self.store.publish_event(&event).await {
+            tracing::warn!(?event, error = %e, "Failed to publish pipeline event");
+            metrics::counter!("pipeline_event_publish_errors", 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_event_roundtrip() {
+        let event = PipelineEvent {
+            user_id: "user".to_string(),
+            pipeline_id: Uuid::new_v4(),
+            step_id: Uuid::new_v4(),
+            kind: PipelineEventKind::TransactionSubmitted {
+                transaction_hash: "hash".to_string(),
+                dry_run: false,
+            },
+            timestamp: Utc::now(),
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "TransactionSubmitted");
+        assert_eq!(json["transaction_hash"], "hash");
+
+        let parsed: PipelineEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.step_id, event.step_id);
+        assert!(matches!(
+            parsed.kind,
+            PipelineEventKind::TransactionSubmitted { transaction_hash, .. } if transaction_hash == "hash"
+        ));
+    }
+}
@@ -1,33 +1,74 @@
 use std::sync::Arc;
 
 use crate::engine::{
-    order::{swap_order_to_transaction, SwapOrder, SwapOrderTransaction},
+    constants::{USDC_MINT, USDT_MINT},
+    executor::OrderExecutor,
+    order::{build_swap, SwapOrder, SwapOrderTransaction},
+    pipeline::SimulatedFill,
     retry::retry_with_backoff,
     Engine, EngineError,
 };
 use blockhash_cache::{inject_blockhash_into_encoded_tx, BLOCKHASH_CACHE};
 use evm_approvals::{caip2_to_chain_id, create_approval_transaction, get_allowance};
-use privy::{tx::PrivyTransaction, Privy};
+use privy::tx::PrivyTransaction;
+use uuid::Uuid;
+
+pub const DRY_RUN_TX_PREFIX: &str = "dry-run:";
+
+/// Who the orders of a step are executed for
+pub struct ExecutionContext<'a> {
+    pub user_id: &'a str,
+    pub pipeline_id: Uuid,
+    pub wallet_address: Option<&'a str>,
+    pub pubkey: Option<&'a str>,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecutedOrder {
+    /// Real transaction hash, or a synthetic `dry-run:` id for simulated fills
+    pub transaction_hash: String,
+    pub simulated_fill: Option<SimulatedFill>,
+    /// Daily notional reserved for the sent order, see `release_notional`
+    pub reserved_usd: u32,
+}
 
 impl Engine {
+    /// Builds the swap transaction for the order and sends it, with `dry_run`
+    /// the transaction is still built (so routing errors surface) but a fill
+    /// at the cached prices is recorded instead of sending it. Orders past the
+    /// user's risk limits fail up front, see `risk`.
     pub async fn execute_order(
         &self,
         order: &SwapOrder,
         user_id: &str,
         wallet_address: Option<String>,
         pubkey: Option<String>,
-    ) -> Result<String, EngineError> {
+        dry_run: bool,
+    ) -> Result<ExecutedOrder, EngineError> {
         if wallet_address.is_none() && order.is_evm() {
             return Err(EngineError::EVMWalletNotAvailable);
         }
         if pubkey.is_none() && order.is_solana() {
             return Err(EngineError::SolanaWalletNotAvailable);
         }
+        // the routes of the past can't be rebuilt, backtests fill at the
+        // replayed prices right away
+        if self.backtest {
+            return Ok(self.simulate_fill(order).await);
+        }
+        // nothing past the user's limits gets signed, Jupiter swaps are
+        // checked against the engine's prices before they are built, LiFi
+        // routes once their quote is in
+        let checked = match order.is_solana_swap() {
+            true => Some(self.check_risk_limits(order, user_id, None).await?),
+            false => None,
+        };
         let address = match order.is_evm() {
             true => wallet_address.clone().unwrap(),
             false => pubkey.clone().unwrap(),
         };
-        let mut privy_transaction = PrivyTransaction {
+        let privy_transaction = PrivyTransaction {
             user_id: user_id.to_string(),
             address,
             from_chain_caip2: order.from_chain_caip2.clone(),
@@ -35,32 +76,63 @@ impl Engine {
             evm_transaction: None,
             solana_transaction: None,
         };
-        let lifi_api_key: Option<String> = match std::env::var("LIFI_API_KEY") {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        };
+        let lifi_api_key: Option<String> = std::env::var("LIFI_API_KEY").ok();
 
-        match swap_order_to_transaction(
+        let built = build_swap(
             order,
             &lifi::LiFi::new(lifi_api_key),
             wallet_address.clone(),
             pubkey.clone(),
         )
         .await
-        .map_err(EngineError::SwapOrderError)?
-        {
+        .map_err(EngineError::SwapOrderError)?;
+        let notional = match checked {
+            Some(notional) => notional,
+            None => {
+                self.check_risk_limits(order, user_id, built.input_usd)
+                    .await?
+            }
+        };
+
+        if dry_run {
+            return Ok(self.simulate_fill(order).await);
+        }
+
+        self.hold_lease_for_send().await;
+        let reserved = self.reserve_notional(user_id, notional).await?;
+        let sent = self
+            .send_transaction(built.transaction, order, privy_transaction)
+            .await;
+        if sent.is_err() {
+            self.release_notional(user_id, reserved).await;
+        }
+
+        Ok(ExecutedOrder {
+            transaction_hash: sent?,
+            simulated_fill: None,
+            reserved_usd: reserved,
+        })
+    }
+
+    async fn send_transaction(
+        &self,
+        transaction: SwapOrderTransaction,
+        order: &SwapOrder,
+        mut privy_transaction: PrivyTransaction,
+    ) -> Result<String, EngineError> {
+        match transaction {
             SwapOrderTransaction::Evm(transaction) => {
                 let spender_address = transaction["to"].as_str().unwrap();
                 ensure_approvals(
                     spender_address,
                     order,
                     &privy_transaction,
-                    self.privy.clone(),
+                    self.executor.as_ref(),
                 )
                 .await?;
                 privy_transaction.evm_transaction = Some(transaction);
                 match self
-                    .privy
+                    .executor
                     .execute_transaction(privy_transaction.clone())
                     .await
                 {
@@ -82,18 +154,66 @@ impl Engine {
                 privy_transaction.solana_transaction = Some(fresh_blockhash_tx);
 
                 // Execute Solana transaction with retry
-                execute_solana_transaction_with_retry(&privy_transaction, self.privy.clone(), order)
-                    .await
+                execute_solana_transaction_with_retry(
+                    &privy_transaction,
+                    self.executor.clone(),
+                    order,
+                )
+                .await
             }
         }
     }
+
+    async fn store_price(&self, asset: &str) -> Option<f64> {
+        match self.store.get_price_update(asset).await {
+            Ok(update) => update.map(|update| update.price),
+            Err(e) => {
+                tracing::warn!(%asset, error = %e, "Failed to fetch price");
+                None
+            }
+        }
+    }
+
+    /// Latest USD price of the token, tokens without active pipelines might
+    /// not be cached yet, stablecoins are taken at $1 then
+    pub(crate) async fn token_price(&self, token: &str) -> Option<f64> {
+        let cached = self.price_cache.read().await.get(token).map(|s| s.price);
+        match cached {
+            Some(price) => Some(price),
+            None => match self.store_price(token).await {
+                Some(price) => Some(price),
+                None if [USDC_MINT, USDT_MINT].contains(&token) => Some(1.0),
+                None => None,
+            },
+        }
+    }
+
+    async fn simulate_fill(&self, order: &SwapOrder) -> ExecutedOrder {
+        let input_price = self.token_price(&order.input_token).await;
+        let output_price = self.token_price(&order.output_token).await;
+
+        metrics::counter!("dry_run_fills", 1);
+        let transaction_hash = format!("{}{}", DRY_RUN_TX_PREFIX, Uuid::new_v4());
+        tracing::info!(?order, %transaction_hash, ?input_price, ?output_price, "Recorded dry-run fill");
+
+        ExecutedOrder {
+            transaction_hash,
+            simulated_fill: Some(SimulatedFill {
+                amount: order.amount.clone(),
+                input_price,
+                output_price,
+                filled_at: self.clock.now(),
+            }),
+            reserved_usd: 0,
+        }
+    }
 }
 
 pub async fn ensure_approvals(
     spender_address: &str,
     order: &SwapOrder,
     privy_transaction: &PrivyTransaction,
-    privy: Arc<Privy>,
+    executor: &dyn OrderExecutor,
 ) -> Result<(), EngineError> {
     let allowance = get_allowance(
         &order.input_token,
@@ -114,7 +234,7 @@ pub async fn ensure_approvals(
         .map_err(EngineError::ApprovalsError)?;
         let mut approval_privy_tx = privy_transaction.clone();
         approval_privy_tx.evm_transaction = Some(approval_transaction);
-        privy
+        executor
             .execute_transaction(approval_privy_tx)
             .await
             .map_err(EngineError::TransactionError)?;
@@ -124,15 +244,15 @@ pub async fn ensure_approvals(
 
 pub async fn execute_solana_transaction_with_retry(
     privy_transaction: &PrivyTransaction,
-    privy: Arc<Privy>,
+    executor: Arc<dyn OrderExecutor>,
     order: &SwapOrder,
 ) -> Result<String, EngineError> {
     retry_with_backoff("execute_solana_transaction", || {
         let privy_tx = privy_transaction.clone();
-        let privy_clone = privy.clone();
+        let executor = executor.clone();
 
         async move {
-            match privy_clone.execute_transaction(privy_tx).await {
+            match executor.execute_transaction(privy_tx).await {
                 Ok(transaction_hash) => Ok(transaction_hash),
                 Err(e) => {
                     tracing::warn!(
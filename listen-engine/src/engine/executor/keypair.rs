@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use privy::tx::PrivyTransaction;
+use serde::Deserialize;
+use serde_json::json;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::VersionedTransaction;
+
+use super::{ExecutorError, OrderExecutor};
+use crate::engine::EngineError;
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<String>,
+    error: Option<RpcResponseError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponseError {
+    message: String,
+}
+
+/// Signs Solana transactions with a single local keypair and sends them
+/// through a JSON-RPC node, meant for self-hosting and running against
+/// `solana-test-validator`. Pipelines have to use the keypair's pubkey and EVM
+/// orders are not supported
+pub struct LocalKeypairExecutor {
+    keypair: Keypair,
+    rpc_url: String,
+    client: reqwest::Client,
+}
+
+impl LocalKeypairExecutor {
+    pub fn new(keypair: Keypair, rpc_url: String) -> Self {
+        Self {
+            keypair,
+            rpc_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn from_env() -> Result<Self, EngineError> {
+        let private_key = std::env::var("SOLANA_PRIVATE_KEY").map_err(|_| {
+            EngineError::ExecutorConfigError("SOLANA_PRIVATE_KEY is not set".to_string())
+        })?;
+        let rpc_url = std::env::var("SOLANA_RPC_URL").map_err(|_| {
+            EngineError::ExecutorConfigError("SOLANA_RPC_URL is not set".to_string())
+        })?;
+        let keypair = solana_sdk::bs58::decode(private_key.trim())
+            .into_vec()
+            .ok()
+            .and_then(|bytes| Keypair::from_bytes(&bytes).ok())
+            .ok_or_else(|| {
+                EngineError::ExecutorConfigError("SOLANA_PRIVATE_KEY is not a valid keypair".into())
+            })?;
+
+        tracing::info!(pubkey = %keypair.pubkey(), "Using local keypair executor");
+
+        Ok(Self::new(keypair, rpc_url))
+    }
+
+    /// Decodes the base64 transaction and signs it as the fee payer
+    fn sign(&self, encoded: &str) -> Result<VersionedTransaction, ExecutorError> {
+        let bytes = STANDARD
+            .decode(encoded)
+            .map_err(|e| ExecutorError::InvalidTransaction(e.to_string()))?;
+        let mut transaction: VersionedTransaction = bincode::deserialize(&bytes)
+            .map_err(|e| ExecutorError::InvalidTransaction(e.to_string()))?;
+
+        let pubkey = self.keypair.pubkey();
+        if transaction.message.static_account_keys().first() != Some(&pubkey) {
+            return Err(ExecutorError::InvalidTransaction(format!(
+                "fee payer is not the executor keypair {}",
+                pubkey
+            )));
+        }
+        if transaction.message.header().num_required_signatures != 1 {
+            return Err(ExecutorError::Unsupported(
+                "transactions requiring additional signers".to_string(),
+            ));
+        }
+
+        let signature = self.keypair.sign_message(&transaction.message.serialize());
+        transaction.signatures = vec![signature];
+
+        Ok(transaction)
+    }
+}
+
+#[async_trait]
+impl OrderExecutor for LocalKeypairExecutor {
+    async fn execute_transaction(
+        &self,
+        transaction: PrivyTransaction,
+    ) -> Result<String, ExecutorError> {
+        if !transaction.is_solana() {
+            return Err(ExecutorError::Unsupported(
+                "EVM transactions with the local keypair executor".to_string(),
+            ));
+        }
+        let encoded = transaction.solana_transaction.ok_or_else(|| {
+            ExecutorError::InvalidTransaction("missing Solana transaction".to_string())
+        })?;
+
+        let signed = self.sign(&encoded)?;
+        let serialized = bincode::serialize(&signed)
+            .map_err(|e| ExecutorError::InvalidTransaction(e.to_string()))?;
+
+        let response: RpcResponse = self
+            .client
+            .post(&self.rpc_url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "sendTransaction",
+                "params": [
+                    STANDARD.encode(serialized),
+                    { "encoding": "base64", "preflightCommitment": "confirmed" }
+                ]
+            }))
+            .send()
+            .await
+            .map_err(|e| ExecutorError::RpcError(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| ExecutorError::RpcError(e.to_string()))?;
+
+        match response {
+            RpcResponse {
+                result: Some(signature),
+                ..
+            } => Ok(signature),
+            RpcResponse {
+                error: Some(error), ..
+            } => Err(ExecutorError::RpcError(error.message)),
+            _ => Err(ExecutorError::RpcError("empty response".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::hash::Hash;
+    use solana_sdk::message::{v0, VersionedMessage};
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::Signature;
+    use solana_sdk::system_instruction;
+
+    fn unsigned_transfer(payer: &Pubkey) -> String {
+        let instruction = system_instruction::transfer(payer, &Pubkey::new_unique(), 1);
+        let message =
+            v0::Message::try_compile(payer, &[instruction], &[], Hash::new_unique()).unwrap();
+        let transaction = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::V0(message),
+        };
+        STANDARD.encode(bincode::serialize(&transaction).unwrap())
+    }
+
+    #[test]
+    fn test_sign_as_fee_payer() {
+        let executor = LocalKeypairExecutor::new(Keypair::new(), String::new());
+        let pubkey = executor.keypair.pubkey();
+
+        let signed = executor.sign(&unsigned_transfer(&pubkey)).unwrap();
+
+        assert!(signed.signatures[0].verify(pubkey.as_ref(), &signed.message.serialize()));
+    }
+
+    #[test]
+    fn test_sign_rejects_foreign_fee_payer() {
+        let executor = LocalKeypairExecutor::new(Keypair::new(), String::new());
+
+        assert!(matches!(
+            executor.sign(&unsigned_transfer(&Pubkey::new_unique())),
+            Err(ExecutorError::InvalidTransaction(_))
+        ));
+    }
+}
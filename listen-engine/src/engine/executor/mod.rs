@@ -0,0 +1,66 @@
+pub mod keypair;
+pub mod privy;
+pub mod simulated;
+
+use std::sync::Arc;
+
+use ::privy::tx::{PrivyTransaction, PrivyTransactionError};
+use ::privy::{config::PrivyConfig, Privy};
+use async_trait::async_trait;
+
+use crate::engine::EngineError;
+
+pub use self::keypair::LocalKeypairExecutor;
+pub use self::privy::PrivyExecutor;
+pub use self::simulated::SimulatedExecutor;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExecutorError {
+    #[error("[Executor] Privy error: {0}")]
+    PrivyError(#[from] PrivyTransactionError),
+
+    #[error("[Executor] Unsupported transaction: {0}")]
+    Unsupported(String),
+
+    #[error("[Executor] Invalid transaction: {0}")]
+    InvalidTransaction(String),
+
+    #[error("[Executor] RPC error: {0}")]
+    RpcError(String),
+}
+
+/// Signs and sends the transactions built for swap orders
+#[async_trait]
+pub trait OrderExecutor: Send + Sync {
+    /// Returns the transaction hash (signature on Solana)
+    async fn execute_transaction(
+        &self,
+        transaction: PrivyTransaction,
+    ) -> Result<String, ExecutorError>;
+}
+
+/// Picks the executor from `ORDER_EXECUTOR`, either `privy` (default) or
+/// `keypair`, the latter signing with `SOLANA_PRIVATE_KEY` and sending through
+/// `SOLANA_RPC_URL`
+pub fn make_order_executor(
+    privy: Option<Arc<Privy>>,
+) -> Result<Arc<dyn OrderExecutor>, EngineError> {
+    let kind = std::env::var("ORDER_EXECUTOR").unwrap_or_else(|_| "privy".to_string());
+
+    match kind.as_str() {
+        "privy" => {
+            let privy = match privy {
+                Some(privy) => privy,
+                None => Arc::new(Privy::new(
+                    PrivyConfig::from_env().map_err(EngineError::PrivyConfigError)?,
+                )),
+            };
+            Ok(Arc::new(PrivyExecutor::new(privy)))
+        }
+        "keypair" => Ok(Arc::new(LocalKeypairExecutor::from_env()?)),
+        other => Err(EngineError::ExecutorConfigError(format!(
+            "unknown ORDER_EXECUTOR: {}",
+            other
+        ))),
+    }
+}
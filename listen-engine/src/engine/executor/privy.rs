@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use privy::{tx::PrivyTransaction, Privy};
+
+use super::{ExecutorError, OrderExecutor};
+
+/// Signs with the user's Privy server wallet
+pub struct PrivyExecutor {
+    privy: Arc<Privy>,
+}
+
+impl PrivyExecutor {
+    pub fn new(privy: Arc<Privy>) -> Self {
+        Self { privy }
+    }
+}
+
+#[async_trait]
+impl OrderExecutor for PrivyExecutor {
+    async fn execute_transaction(
+        &self,
+        transaction: PrivyTransaction,
+    ) -> Result<String, ExecutorError> {
+        Ok(self.privy.execute_transaction(transaction).await?)
+    }
+}
@@ -0,0 +1,23 @@
+use async_trait::async_trait;
+use privy::tx::PrivyTransaction;
+
+use super::{ExecutorError, OrderExecutor};
+
+/// Executor of backtest engines, orders are filled at replayed prices before
+/// a transaction is ever built, so nothing should reach this executor. It
+/// refuses to send anything in case something does.
+#[derive(Debug, Default)]
+pub struct SimulatedExecutor;
+
+#[async_trait]
+impl OrderExecutor for SimulatedExecutor {
+    async fn execute_transaction(
+        &self,
+        transaction: PrivyTransaction,
+    ) -> Result<String, ExecutorError> {
+        Err(ExecutorError::Unsupported(format!(
+            "simulated executor does not send transactions, got one for {}",
+            transaction.address
+        )))
+    }
+}
@@ -0,0 +1,213 @@
+//! Pipeline and step expiry. Pipelines with an `expires_at`, or with pending
+//! steps that have one, are tracked in `Engine::pipeline_expiries` and swept
+//! on a fixed interval, so that stale orders are cancelled even if their
+//! assets never move again.
+
+use chrono::{DateTime, Utc};
+
+use crate::engine::{
+    events::PipelineEventKind,
+    pipeline::{Pipeline, Status},
+    Engine, EngineError,
+};
+
+impl Engine {
+    /// Tracks the next expiry of the pipeline, for as long as it can still run
+    pub fn index_expiry(&self, pipeline: &Pipeline) {
+        let pipeline_key = format!("{}:{}", pipeline.user_id, pipeline.id);
+        match pipeline.next_expiry() {
+            Some(expires_at) if matches!(pipeline.status, Status::Pending | Status::Paused) => {
+                self.pipeline_expiries.insert(pipeline_key, expires_at);
+            }
+            _ => {
+                self.pipeline_expiries.remove(&pipeline_key);
+            }
+        }
+    }
+
+    /// Cancels whatever has expired in the pipeline and publishes the
+    /// cancellations. Returns true if anything was cancelled.
+    pub async fn apply_expiry(&self, pipeline: &mut Pipeline, now: DateTime<Utc>) -> bool {
+        let was_cancelled = matches!(pipeline.status, Status::Cancelled);
+        let hash = pipeline.hash();
+        let cancelled = pipeline.expire(now);
+        let pipeline_expired = !was_cancelled && matches!(pipeline.status, Status::Cancelled);
+        if cancelled.is_empty() && !pipeline_expired {
+            // the remaining slices of a sliced order in flight
+            return pipeline.hash() != hash;
+        }
+
+        tracing::info!(pipeline_id = %pipeline.id, ?cancelled, pipeline_expired, "Cancelled expired steps");
+        metrics::counter!("pipeline_expirations", cancelled.len() as u64);
+        for step_id in cancelled {
+            self.publish_event(
+                &pipeline.user_id,
+                pipeline.id,
+                step_id,
+                PipelineEventKind::Cancelled,
+            )
+            .await;
+        }
+        true
+    }
+
+    /// Cancels the pipelines of this instance that are due to expire,
+    /// pipelines that are being evaluated are left for the next sweep
+    pub async fn expire_pipelines(&self) -> Result<(), EngineError> {
+        let now = self.clock.now();
+        let due: Vec<String> = self
+            .pipeline_expiries
+            .iter()
+            .filter(|entry| *entry.value() <= now && self.owns_pipeline(entry.key()))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        for pipeline_key in due {
+            if !self.lock_pipeline(&pipeline_key).await? {
+                continue;
+            }
+
+            // read under the lock, so that the latest state is expired
+            let result = match self
+                .store
+                .get_pipelines(std::slice::from_ref(&pipeline_key))
+                .await
+            {
+                Ok(mut pipelines) => match pipelines.pop().flatten() {
+                    Some(mut pipeline) => self.expire_pipeline(&mut pipeline, now).await,
+                    None => {
+                        self.pipeline_expiries.remove(&pipeline_key);
+                        Ok(())
+                    }
+                },
+                Err(e) => Err(EngineError::StoreError(e)),
+            };
+
+            self.unlock_pipeline(&pipeline_key).await;
+
+            if let Err(e) = result {
+                tracing::error!(%pipeline_key, error = %e, "Failed to expire pipeline");
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn expire_pipeline(
+        &self,
+        pipeline: &mut Pipeline,
+        now: DateTime<Utc>,
+    ) -> Result<(), EngineError> {
+        if matches!(pipeline.status, Status::Pending | Status::Paused)
+            && self.apply_expiry(pipeline, now).await
+        {
+            let done =
+                matches!(pipeline.status, Status::Cancelled) || self.collect_step_results(pipeline);
+            self.store
+                .save_pipeline(pipeline)
+                .await
+                .map_err(EngineError::SavePipelineError)?;
+            if done {
+                self.remove_from_active_pipelines(pipeline);
+                return Ok(());
+            }
+        }
+
+        self.index_expiry(pipeline);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::engine::testing::{engine, webhook_pipeline};
+    use crate::store::PipelineStore;
+
+    #[tokio::test]
+    async fn test_expire_pipelines() {
+        let (engine, store) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let mut pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        pipeline.expires_at = Some(Utc::now() + Duration::hours(1));
+        let key = format!("user:{}", pipeline.id);
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        // not due yet
+        engine.expire_pipelines().await.unwrap();
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Pending));
+        assert!(engine.pipeline_expiries.contains_key(&key));
+
+        engine
+            .pipeline_expiries
+            .insert(key.clone(), Utc::now() - Duration::seconds(1));
+        let mut expired = saved.clone();
+        expired.expires_at = Some(Utc::now() - Duration::seconds(1));
+        store.save_pipeline(&expired).await.unwrap();
+
+        engine.expire_pipelines().await.unwrap();
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Cancelled));
+        assert!(saved
+            .steps
+            .values()
+            .all(|step| matches!(step.status, Status::Cancelled)));
+        assert!(!engine.active_pipelines.get(&asset).unwrap().contains(&key));
+        assert!(!engine.pipeline_expiries.contains_key(&key));
+        assert!(matches!(
+            store.events().last().map(|e| &e.kind),
+            Some(PipelineEventKind::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_pipeline_expiry_waits_for_confirmations() {
+        let asset = Pubkey::new_unique().to_string();
+        let mut pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        let now = Utc::now();
+        let first = pipeline.current_steps[0];
+        let mut second = pipeline.steps[&first].clone();
+        second.id = uuid::Uuid::new_v4();
+        pipeline.steps.insert(second.id, second.clone());
+        pipeline.steps.get_mut(&first).unwrap().status = Status::Confirming;
+        pipeline.expires_at = Some(now - Duration::seconds(1));
+
+        // the pending step is cancelled, the pipeline keeps polling the
+        // confirming one
+        assert_eq!(pipeline.expire(now), vec![second.id]);
+        assert!(matches!(pipeline.status, Status::Pending));
+        assert!(matches!(pipeline.steps[&first].status, Status::Confirming));
+
+        pipeline.steps.get_mut(&first).unwrap().status = Status::Completed;
+        assert!(pipeline.expire(now).is_empty());
+        assert!(matches!(pipeline.status, Status::Cancelled));
+    }
+
+    #[test]
+    fn test_step_expiry_cancels_downstream() {
+        let asset = Pubkey::new_unique().to_string();
+        let mut pipeline = webhook_pipeline(&asset, 2.0, "http://127.0.0.1/hook");
+        let now = Utc::now();
+        let first = pipeline.current_steps[0];
+        let mut second = pipeline.steps[&first].clone();
+        second.id = uuid::Uuid::new_v4();
+        pipeline.steps.get_mut(&first).unwrap().next_steps = vec![second.id];
+        pipeline.steps.get_mut(&first).unwrap().expires_at = Some(now + Duration::minutes(5));
+        pipeline.steps.insert(second.id, second.clone());
+
+        assert_eq!(pipeline.next_expiry(), Some(now + Duration::minutes(5)));
+        assert!(pipeline.expire(now).is_empty());
+
+        let mut cancelled = pipeline.expire(now + Duration::minutes(10));
+        cancelled.sort();
+        let mut expected = vec![first, second.id];
+        expected.sort();
+        assert_eq!(cancelled, expected);
+        assert!(matches!(pipeline.status, Status::Pending));
+        assert_eq!(pipeline.next_expiry(), None);
+    }
+}
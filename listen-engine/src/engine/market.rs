@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+
+use crate::redis::subscriber::PriceUpdate;
+
+/// Longest window supported by rolling volume conditions, trades older than
+/// this are dropped from the snapshot
+pub const MAX_VOLUME_WINDOW_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Trade {
+    pub timestamp: u64,
+    pub price: f64,
+    pub usd: f64,
+    pub is_buy: bool,
+}
+
+/// Latest known market state of an asset, built from the `PriceUpdate` stream
+#[derive(Debug, Clone, Default)]
+pub struct MarketSnapshot {
+    pub price: f64,
+    pub market_cap: f64,
+    pub slot: u64,
+    pub timestamp: u64,
+    pub last_trade: Option<Trade>,
+    /// Recent trades, only tracked for assets with active pipelines
+    pub trades: VecDeque<Trade>,
+}
+
+impl MarketSnapshot {
+    pub fn from_price_update(update: &PriceUpdate) -> Self {
+        let mut snapshot = Self::default();
+        snapshot.apply(update, false);
+        snapshot
+    }
+
+    /// Applies a new update to the snapshot, `track_volume` controls whether
+    /// the trade is kept for rolling volume calculations
+    pub fn apply(&mut self, update: &PriceUpdate, track_volume: bool) {
+        let trade = Trade {
+            timestamp: update.timestamp,
+            price: update.price,
+            usd: update.swap_amount,
+            is_buy: update.is_buy,
+        };
+
+        self.price = update.price;
+        self.market_cap = update.market_cap;
+        self.slot = update.slot;
+        self.timestamp = update.timestamp;
+
+        if track_volume {
+            self.trades.push_back(trade.clone());
+        }
+        let cutoff = update.timestamp.saturating_sub(MAX_VOLUME_WINDOW_SECS);
+        while self.trades.front().is_some_and(|t| t.timestamp < cutoff) {
+            self.trades.pop_front();
+        }
+
+        self.last_trade = Some(trade);
+    }
+
+    /// USD volume of the trades within `window_secs` before `now`
+    pub fn rolling_volume(&self, window_secs: u64, now: u64) -> f64 {
+        self.window(window_secs, now).map(|t| t.usd).sum()
+    }
+
+    /// Volume weighted average price of the trades within `window_secs`
+    /// before `now`, `None` without trades
+    pub fn vwap(&self, window_secs: u64, now: u64) -> Option<f64> {
+        let (usd, tokens) = self
+            .window(window_secs, now)
+            .filter(|t| t.price > 0.0)
+            .fold((0.0, 0.0), |(usd, tokens), t| {
+                (usd + t.usd, tokens + t.usd / t.price)
+            });
+        (tokens > 0.0).then(|| usd / tokens)
+    }
+
+    /// Median price of the trades within `window_secs` before `now`, `None`
+    /// without trades
+    pub fn median_price(&self, window_secs: u64, now: u64) -> Option<f64> {
+        let mut prices: Vec<f64> = self.window(window_secs, now).map(|t| t.price).collect();
+        if prices.is_empty() {
+            return None;
+        }
+        prices.sort_by(f64::total_cmp);
+        let mid = prices.len() / 2;
+        Some(match prices.len() % 2 {
+            0 => (prices[mid - 1] + prices[mid]) / 2.0,
+            _ => prices[mid],
+        })
+    }
+
+    fn window(&self, window_secs: u64, now: u64) -> impl Iterator<Item = &Trade> {
+        let cutoff = now.saturating_sub(window_secs);
+        self.trades
+            .iter()
+            .rev()
+            .take_while(move |t| t.timestamp >= cutoff)
+    }
+}
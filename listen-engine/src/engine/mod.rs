@@ -1,121 +1,201 @@
+pub mod amount;
 pub mod api;
+pub mod backtest;
 pub mod bridge;
+pub mod chain;
+pub mod clock;
+pub mod cluster;
 pub mod collect;
+pub mod confirm;
 pub mod constants;
+pub mod copy;
 pub mod error;
 pub mod evaluate;
 pub mod evaluator;
+pub mod events;
 pub mod execute;
+pub mod executor;
+pub mod expiry;
+pub mod market;
 pub mod notifications;
 pub mod order;
 pub mod pipeline;
+pub mod rebalance;
 pub mod retry;
+pub mod risk;
+pub mod slices;
+#[cfg(test)]
+pub(crate) mod testing;
 use crate::engine::error::EngineError;
-use crate::redis::client::{make_redis_client, RedisClient};
-use crate::redis::subscriber::{make_redis_subscriber, PriceUpdate, RedisSubscriber};
+use crate::redis::client::make_redis_client;
+use crate::redis::subscriber::{
+    make_redis_subscriber, PriceMessage, PriceTransport, PriceUpdate, RedisSubscriber,
+};
 use anyhow::Result;
-use dashmap::DashMap;
+use chrono::{DateTime, Utc};
+use dashmap::mapref::entry::Entry;
+use dashmap::{DashMap, DashSet};
 use metrics::{counter, histogram};
 use privy::config::PrivyConfig;
 use privy::Privy;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 use tokio::sync::mpsc;
+use tokio::sync::watch;
 use tokio::sync::Mutex;
 use tokio::sync::Notify;
 use tokio::sync::RwLock;
-
-use self::pipeline::{Pipeline, Status};
+use tokio::task::JoinHandle;
+
+use self::clock::Clock;
+use self::cluster::{Cluster, HEARTBEAT_INTERVAL, LEASE_TTL};
+use self::confirm::CONFIRMING_KEY;
+use self::executor::{make_order_executor, OrderExecutor};
+use self::market::MarketSnapshot;
+use self::pipeline::{LeaderTrade, Pipeline, Status};
 use crate::server::state::EngineMessage;
+use crate::store::PipelineStore;
 
 pub struct Engine {
-    pub redis: Arc<RedisClient>,
+    pub store: Arc<dyn PipelineStore>,
     pub redis_sub: Arc<RedisSubscriber>,
-    pub privy: Arc<Privy>,
+    /// Only required for the Privy executor and email notifications
+    pub privy: Option<Arc<Privy>>,
+    pub executor: Arc<dyn OrderExecutor>,
 
     // Current market state
-    price_cache: Arc<RwLock<HashMap<String, f64>>>,
+    price_cache: Arc<RwLock<HashMap<String, MarketSnapshot>>>,
     processing_pipelines: Arc<Mutex<HashSet<String>>>,
-    active_pipelines: Arc<DashMap<String, HashSet<String>>>, // asset -> pipeline ids
-    shutdown_signal: Arc<Notify>,                            // Used to signal shutdown
+    requeued_pipelines: Arc<DashMap<String, watch::Receiver<Option<bool>>>>, // see `requeue_evaluation`
+    active_pipelines: Arc<DashMap<String, HashSet<String>>>, // asset or wallet key -> pipeline ids
+    paused_pipelines: Arc<DashSet<String>>,                  // still indexed, but not evaluated
+    pipeline_expiries: Arc<DashMap<String, DateTime<Utc>>>,  // pipeline id -> next expiry
+    wallet_trades: Arc<DashMap<String, VecDeque<LeaderTrade>>>, // watched wallet -> recent swaps, see `copy`
+    shutdown_signal: Arc<Notify>,                               // Used to signal shutdown
     pending_tasks: Arc<AtomicUsize>, // Track number of running pipeline evaluations
+    dry_run: bool,                   // Simulate every order, regardless of the pipeline flag
+    backtest: bool, // Replaying history, see `backtest`: no transactions or notifications
+    clock: Clock,
+    cluster: Arc<Cluster>, // Instances sharing the pipelines, see `cluster`
 }
 
 impl Clone for Engine {
     fn clone(&self) -> Self {
         Self {
-            redis: self.redis.clone(),
+            store: self.store.clone(),
             redis_sub: self.redis_sub.clone(),
             privy: self.privy.clone(),
+            executor: self.executor.clone(),
             price_cache: self.price_cache.clone(),
             processing_pipelines: self.processing_pipelines.clone(),
+            requeued_pipelines: self.requeued_pipelines.clone(),
             active_pipelines: self.active_pipelines.clone(),
+            paused_pipelines: self.paused_pipelines.clone(),
+            pipeline_expiries: self.pipeline_expiries.clone(),
+            wallet_trades: self.wallet_trades.clone(),
             shutdown_signal: self.shutdown_signal.clone(),
             pending_tasks: self.pending_tasks.clone(),
+            dry_run: self.dry_run,
+            backtest: self.backtest,
+            clock: self.clock.clone(),
+            cluster: self.cluster.clone(),
         }
     }
 }
 
 impl Engine {
-    pub async fn from_env() -> Result<(Self, mpsc::Receiver<PriceUpdate>), EngineError> {
+    pub async fn from_env() -> Result<(Self, mpsc::Receiver<PriceMessage>), EngineError> {
         let (tx, rx) = mpsc::channel(1000);
 
-        Ok((
-            Self {
-                privy: Arc::new(Privy::new(
-                    PrivyConfig::from_env().map_err(EngineError::PrivyConfigError)?,
-                )),
-                redis: make_redis_client()
-                    .await
-                    .map_err(EngineError::RedisClientError)?,
-                redis_sub: make_redis_subscriber(tx).map_err(EngineError::RedisSubscriberError)?,
-                price_cache: Arc::new(RwLock::new(HashMap::new())),
-                processing_pipelines: Arc::new(Mutex::new(HashSet::new())),
-                active_pipelines: Arc::new(DashMap::new()),
-                shutdown_signal: Arc::new(Notify::new()),
-                pending_tasks: Arc::new(AtomicUsize::new(0)),
-            },
-            rx,
-        ))
+        let privy = PrivyConfig::from_env()
+            .ok()
+            .map(|config| Arc::new(Privy::new(config)));
+        let executor = make_order_executor(privy.clone())?;
+        let store = make_redis_client()
+            .await
+            .map_err(EngineError::RedisClientError)?;
+        let instance_id = std::env::var("ENGINE_INSTANCE_ID").ok();
+        let cluster = Cluster::new(
+            instance_id
+                .clone()
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        );
+        let redis_sub = make_redis_subscriber(tx, &cluster.instance_id)
+            .map_err(EngineError::RedisSubscriberError)?;
+        if redis_sub.transport() == PriceTransport::Stream && instance_id.is_none() {
+            tracing::warn!(
+                "ENGINE_INSTANCE_ID is not set, price updates missed while down won't be replayed"
+            );
+        }
+
+        let mut engine = Self::new(store, redis_sub, privy, executor);
+        engine.dry_run = std::env::var("ENGINE_DRY_RUN").is_ok_and(|v| v == "true" || v == "1");
+        engine.cluster = Arc::new(cluster);
+
+        Ok((engine, rx))
+    }
+
+    pub fn new(
+        store: Arc<dyn PipelineStore>,
+        redis_sub: Arc<RedisSubscriber>,
+        privy: Option<Arc<Privy>>,
+        executor: Arc<dyn OrderExecutor>,
+    ) -> Self {
+        Self {
+            store,
+            redis_sub,
+            privy,
+            executor,
+            price_cache: Arc::new(RwLock::new(HashMap::new())),
+            processing_pipelines: Arc::new(Mutex::new(HashSet::new())),
+            requeued_pipelines: Arc::new(DashMap::new()),
+            active_pipelines: Arc::new(DashMap::new()),
+            paused_pipelines: Arc::new(DashSet::new()),
+            pipeline_expiries: Arc::new(DashMap::new()),
+            wallet_trades: Arc::new(DashMap::new()),
+            shutdown_signal: Arc::new(Notify::new()),
+            pending_tasks: Arc::new(AtomicUsize::new(0)),
+            dry_run: false,
+            backtest: false,
+            clock: Clock::System,
+            cluster: Arc::new(Cluster::new(uuid::Uuid::new_v4().to_string())),
+        }
     }
 
     pub async fn run(
         engine: Arc<Self>,
-        mut receiver: mpsc::Receiver<PriceUpdate>,
+        mut receiver: mpsc::Receiver<PriceMessage>,
         mut command_rx: mpsc::Receiver<EngineMessage>,
     ) -> Result<()> {
         tracing::info!("Engine starting up");
 
         // Add health check interval
         let mut health_check_interval = tokio::time::interval(Duration::from_secs(60));
+        // Time-based pipelines are evaluated on a fixed tick instead of price updates
+        let mut scheduler_interval = tokio::time::interval(Duration::from_secs(1));
+        scheduler_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut expiry_interval = tokio::time::interval(Duration::from_secs(10));
+        expiry_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
         let mut last_price_update = Instant::now();
 
-        let existing_pipelines = match engine.redis.get_all_pipelines().await {
-            Ok(p) => {
-                tracing::info!("{} pipelines from Redis", p.len());
-                p
-            }
-            Err(e) => {
-                tracing::error!("Failed to load pipelines from Redis: {}", e);
-                return Err(e.into());
-            }
-        };
+        let mut heartbeat_interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        heartbeat_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
-        // load existing pipelines into active pipelines
-        for pipeline in existing_pipelines {
-            let asset_ids = engine.extract_assets(&pipeline);
-            for asset_id in asset_ids {
-                engine
-                    .active_pipelines
-                    .entry(asset_id.clone())
-                    .or_default()
-                    .insert(format!("{}:{}", pipeline.user_id, pipeline.id));
-            }
+        // join the other instances before loading, so that the pipelines are
+        // only sharded once
+        if let Err(e) = engine.refresh_instances().await {
+            tracing::error!("Failed to register engine instance: {}", e);
+        }
+        if let Err(e) = engine.load_pipelines().await {
+            tracing::error!("Failed to load pipelines from Redis: {}", e);
+            return Err(e.into());
         }
+        cluster::spawn_index_relay(engine.clone());
 
         engine.redis_sub.start_listening().await?;
 
@@ -145,25 +225,56 @@ impl Engine {
                             last_price_update.elapsed().as_secs());
                     }
                 }
+                _ = scheduler_interval.tick() => {
+                    if let Err(e) = engine.handle_scheduler_tick().await {
+                        tracing::error!("Error handling scheduler tick: {}", e);
+                        metrics::counter!("engine_scheduler_tick_errors", 1);
+                    }
+                }
+                _ = heartbeat_interval.tick() => {
+                    match engine.refresh_instances().await {
+                        Ok(true) => {
+                            if let Err(e) = engine.load_pipelines().await {
+                                tracing::error!("Failed to reload pipelines after re-sharding: {}", e);
+                            }
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            tracing::error!("Engine heartbeat failed: {}", e);
+                            metrics::counter!("engine_heartbeat_errors", 1);
+                        }
+                    }
+                }
+                _ = expiry_interval.tick() => {
+                    if let Err(e) = engine.expire_pipelines().await {
+                        tracing::error!("Error expiring pipelines: {}", e);
+                        metrics::counter!("engine_expiry_errors", 1);
+                    }
+                }
                 Some(msg) = command_rx.recv() => {
                     metrics::counter!("engine_commands_received", 1);
                     tracing::debug!("Received engine message: {:?}", msg);
                     match msg {
                         EngineMessage::AddPipeline { pipeline, response_tx } => {
-                            let asset_ids = engine.extract_assets(&pipeline);
-                            for asset_id in asset_ids {
-                                engine.active_pipelines.entry(asset_id.clone()).or_default().insert(format!("{}:{}", pipeline.user_id, pipeline.id));
-                                engine.redis.save_pipeline(&pipeline).await?;
+                            let result = engine.add_pipeline(&pipeline).await;
+                            if let Err(e) = &result {
+                                tracing::error!("Error adding pipeline: {}", e);
+                            }
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
                             }
-                            let _ = response_tx.send(Ok(pipeline.id.to_string()));
                         },
-                        EngineMessage::DeletePipeline { .. } => {
-                            panic!("DeletePipeline not implemented");
-                            // let result = self.delete_pipeline(&user_id, pipeline_id).await;
-                            // let _ = response_tx.send(result);
+                        EngineMessage::DeletePipeline { user_id, pipeline_id, response_tx } => {
+                            let result = engine.delete_pipeline(&user_id, pipeline_id).await;
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
+                            }
                         },
-                        EngineMessage::GetPipeline { .. } => {
-                            panic!("GetPipeline not implemented");
+                        EngineMessage::GetPipeline { user_id, pipeline_id, response_tx } => {
+                            let result = engine.get_pipeline(&user_id, pipeline_id).await;
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
+                            }
                         },
                         EngineMessage::GetAllPipelinesByUser { user_id, response_tx } => {
                             let result = engine.get_all_pipelines_by_user(&user_id).await;
@@ -187,14 +298,63 @@ impl Engine {
                                 tracing::error!("Failed to send response - channel closed");
                             }
                         },
+                        EngineMessage::EditStep { user_id, pipeline_id, step_id, edit, response_tx } => {
+                            let result = engine.edit_step(&user_id, pipeline_id, step_id, &edit).await;
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
+                            }
+                        },
+                        EngineMessage::PausePipeline { user_id, pipeline_id, response_tx } => {
+                            let result = engine.pause_pipeline(&user_id, pipeline_id).await;
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
+                            }
+                        },
+                        EngineMessage::ResumePipeline { user_id, pipeline_id, response_tx } => {
+                            let result = engine.resume_pipeline(&user_id, pipeline_id).await;
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
+                            }
+                        },
+                        EngineMessage::GetRiskLimits { user_id, response_tx } => {
+                            let result = engine.get_risk_limits(&user_id).await;
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
+                            }
+                        },
+                        EngineMessage::SetRiskLimits { user_id, update, response_tx } => {
+                            let result = engine.set_risk_limits(&user_id, &update).await;
+                            if response_tx.send(result).is_err() {
+                                tracing::error!("Failed to send response - channel closed");
+                            }
+                        },
                     }
                 }
-                Some(price_update) = receiver.recv() => {
+                Some(message) = receiver.recv() => {
                     last_price_update = Instant::now();
                     metrics::counter!("engine_price_updates_received", 1);
-                    if let Err(e) = engine.handle_price_update(&price_update.pubkey, price_update.price, price_update.slot).await {
-                        tracing::error!("Error handling price update: {}", e);
-                        metrics::counter!("engine_price_update_errors", 1);
+                    match engine.handle_price_update(&message.update).await {
+                        // streamed updates are acknowledged once the pipelines
+                        // they triggered have been evaluated, until then they
+                        // are replayed when the engine restarts
+                        Ok(evaluations) => {
+                            if let Some(stream_id) = message.stream_id {
+                                let redis_sub = engine.redis_sub.clone();
+                                tokio::spawn(async move {
+                                    let mut finished = true;
+                                    for evaluation in evaluations {
+                                        finished &= evaluation.await.unwrap_or(false);
+                                    }
+                                    if finished {
+                                        redis_sub.ack(stream_id);
+                                    }
+                                });
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!("Error handling price update: {}", e);
+                            metrics::counter!("engine_price_update_errors", 1);
+                        }
                     }
                 }
                 else => break
@@ -204,7 +364,10 @@ impl Engine {
         Ok(())
     }
 
-    pub async fn handle_price_update(&self, asset: &str, price: f64, slot: u64) -> Result<()> {
+    /// Evaluates the pipelines watching the asset or the wallet that swapped
+    /// it, returns the spawned evaluations, see `evaluate_pipelines`
+    pub async fn handle_price_update(&self, update: &PriceUpdate) -> Result<Vec<JoinHandle<bool>>> {
+        let asset = update.pubkey.as_str();
         let start = Instant::now();
         counter!("price_updates_processed", 1);
 
@@ -216,102 +379,286 @@ impl Engine {
             if let Some(active_pipelines) = self.active_pipelines.get(&asset.to_string()) {
                 res.extend(active_pipelines.iter().cloned());
             }
+            // copy trades of the wallet that made the swap
+            if let Some(wallet_key) = self.record_wallet_trade(update) {
+                if let Some(wallet_pipelines) = self.active_pipelines.get(&wallet_key) {
+                    res.extend(wallet_pipelines.iter().cloned());
+                }
+            }
+            res.sort();
+            res.dedup();
             res
         };
 
-        // Update price cache after getting pipeline IDs
+        // Update price cache after getting pipeline IDs, trades are only kept
+        // for volume tracking if there are pipelines watching the asset
         {
+            let track_volume = self
+                .active_pipelines
+                .get(asset)
+                .is_some_and(|pipeline_ids| !pipeline_ids.is_empty());
             let mut cache = self.price_cache.write().await;
-            cache.insert(asset.to_string(), price);
+            cache
+                .entry(asset.to_string())
+                .or_default()
+                .apply(update, track_volume);
         }
 
-        // Process in chunks to limit concurrent Redis connections
-        for chunk in pipeline_ids.chunks(10) {
-            let asset = asset.to_string();
+        let evaluations = self.evaluate_pipelines(&pipeline_ids, asset).await?;
+
+        histogram!("price_update_duration", start.elapsed());
+        tracing::debug!(
+            "{}: {} {} took {:?}",
+            asset,
+            update.price,
+            update.slot,
+            start.elapsed()
+        );
+        Ok(evaluations)
+    }
 
-            // Batch fetch pipelines from Redis
-            let mut pipe = bb8_redis::redis::pipe();
-            for id in chunk {
-                pipe.get(format!("pipeline:{}", id));
+    /// Evaluates pipelines that don't depend on price updates, i.e. the ones
+    /// indexed under "NOW" and "TIME", so they run even on quiet assets, and
+    /// the ones with transactions waiting for confirmation
+    pub async fn handle_scheduler_tick(&self) -> Result<()> {
+        let pipeline_ids = {
+            // sliced orders can be under both "TIME" and CONFIRMING_KEY
+            let mut res = HashSet::new();
+            for key in ["NOW", "TIME", CONFIRMING_KEY] {
+                if let Some(pipeline_ids) = self.active_pipelines.get(key) {
+                    res.extend(pipeline_ids.iter().cloned());
+                }
             }
+            res.into_iter().collect::<Vec<_>>()
+        };
 
-            let pipelines: Vec<Option<Pipeline>> = self.redis.execute_redis_pipe(pipe).await?;
-            tracing::debug!("Fetched {} from redis", pipelines.len());
+        if !pipeline_ids.is_empty() {
+            counter!("scheduler_ticks_processed", 1);
+            self.evaluate_pipelines(&pipeline_ids, "TIME").await?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetches the given pipelines from the store and spawns evaluation for the
+    /// ones that are pending and owned by this instance, the ones already being
+    /// processed are queued behind that evaluation. The spawned evaluations
+    /// resolve to false if they were cut short, by a shutdown or a store error.
+    async fn evaluate_pipelines(
+        &self,
+        pipeline_ids: &[String],
+        trigger: &str,
+    ) -> Result<Vec<JoinHandle<bool>>> {
+        // Paused pipelines and the ones of other instances are skipped before
+        // hitting the store
+        let pipeline_ids: Vec<String> = pipeline_ids
+            .iter()
+            .filter(|pipeline_id| {
+                !self.paused_pipelines.contains(*pipeline_id) && self.owns_pipeline(pipeline_id)
+            })
+            .cloned()
+            .collect();
+
+        let mut evaluations = Vec::new();
+
+        // Process in chunks to limit the size of store lookups
+        for chunk in pipeline_ids.chunks(10) {
+            // Batch fetch pipelines from the store
+            let pipelines: Vec<Option<Pipeline>> = self.store.get_pipelines(chunk).await?;
+            tracing::debug!("Fetched {} from store", pipelines.len());
 
             // Process the fetched pipelines concurrently
             for (pipeline_id, maybe_pipeline) in chunk.iter().zip(pipelines) {
-                if let Some(mut pipeline) = maybe_pipeline {
-                    let self_clone = self.clone();
-                    let pipeline_id = pipeline_id.clone();
-                    let asset = asset.clone();
+                if !maybe_pipeline
+                    .is_some_and(|pipeline| matches!(pipeline.status, Status::Pending))
+                {
+                    continue;
+                }
 
-                    if !matches!(pipeline.status, Status::Pending) {
-                        continue;
-                    }
+                let can_process = self
+                    .processing_pipelines
+                    .lock()
+                    .await
+                    .insert(pipeline_id.clone());
 
-                    let can_process = {
-                        let mut processing = self_clone.processing_pipelines.lock().await;
-                        if processing.contains(&pipeline_id) {
-                            false
-                        } else {
-                            processing.insert(pipeline_id.clone());
-                            true
-                        }
+                if can_process {
+                    evaluations.push(self.spawn_evaluation(pipeline_id.clone(), trigger));
+                } else {
+                    evaluations.push(self.requeue_evaluation(pipeline_id.clone(), trigger));
+                }
+            }
+        }
+
+        Ok(evaluations)
+    }
+
+    /// Spawns the evaluation of a pipeline whose processing lock is held
+    fn spawn_evaluation(&self, pipeline_id: String, trigger: &str) -> JoinHandle<bool> {
+        // Increment pending tasks counter, the guard releases the processing
+        // lock and the counter even if the task panics
+        self.pending_tasks.fetch_add(1, Ordering::SeqCst);
+        let guard = ProcessingGuard {
+            processing_pipelines: self.processing_pipelines.clone(),
+            pending_tasks: self.pending_tasks.clone(),
+            pipeline_id: pipeline_id.clone(),
+        };
+        let engine = self.clone();
+        let trigger = trigger.to_string();
+
+        // Spawn a detached task for pipeline evaluation, the lease is taken in
+        // the task to keep the store round trip out of the price update loop
+        tokio::spawn(async move {
+            let finished = match engine.wait_for_lease(&pipeline_id).await {
+                Ok(true) => {
+                    let finished = engine
+                        .with_lease(&pipeline_id, engine.evaluate_leased(&pipeline_id, &trigger))
+                        .await
+                        .unwrap_or(false);
+                    engine.release_lease(&pipeline_id).await;
+                    finished
+                }
+                // not evaluated, so the update isn't acked and is replayed
+                Ok(false) => {
+                    tracing::warn!(
+                        "{}: leased by another instance, update left unacked",
+                        pipeline_id
+                    );
+                    false
+                }
+                Err(e) => {
+                    tracing::error!("{}: failed to lease pipeline: {}", pipeline_id, e);
+                    false
+                }
+            };
+
+            drop(guard);
+            finished
+        })
+    }
+
+    /// The pipeline is already being evaluated and could miss the update, so
+    /// it is evaluated again once that evaluation is done. Updates arriving
+    /// until then share the queued evaluation and are acked with it.
+    fn requeue_evaluation(&self, pipeline_id: String, trigger: &str) -> JoinHandle<bool> {
+        let mut done = match self.requeued_pipelines.entry(pipeline_id.clone()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let (sender, done) = watch::channel(None);
+                entry.insert(done.clone());
+
+                let engine = self.clone();
+                let trigger = trigger.to_string();
+                engine.pending_tasks.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let locked = engine.wait_for_processing_lock(&pipeline_id).await;
+                    // later updates need an evaluation of their own
+                    engine.requeued_pipelines.remove(&pipeline_id);
+                    let finished = if locked {
+                        engine
+                            .spawn_evaluation(pipeline_id, &trigger)
+                            .await
+                            .unwrap_or(false)
+                    } else {
+                        tracing::warn!("{}: still evaluating, update left unacked", pipeline_id);
+                        false
                     };
+                    let _ = sender.send(Some(finished));
+                    engine.pending_tasks.fetch_sub(1, Ordering::SeqCst);
+                });
+                done
+            }
+        };
 
-                    if can_process && !matches!(pipeline.status, Status::Failed | Status::Cancelled)
-                    {
-                        // Increment pending tasks counter
-                        self_clone.pending_tasks.fetch_add(1, Ordering::SeqCst);
-
-                        // Spawn a detached task for pipeline evaluation
-                        let shutdown = self_clone.shutdown_signal.clone();
-                        tokio::spawn(async move {
-                            let result = async {
-                                tokio::select! {
-                                    r = self_clone.evaluate_pipeline(&mut pipeline) => r,
-                                    _ = shutdown.notified() => {
-                                        tracing::info!("Gracefully stopping pipeline evaluation for {}", pipeline_id);
-                                        Ok(false) // Don't mark as complete if interrupted
-                                    }
-                                }
-                            }.await;
-
-                            match result {
-                                Ok(is_complete) => {
-                                    if is_complete {
-                                        if let Some(mut pipelines) =
-                                            self_clone.active_pipelines.get_mut(&asset)
-                                        {
-                                            pipelines.remove(&pipeline_id);
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    tracing::error!(
-                                        "{}: {} Pipeline evaluation error: {}",
-                                        pipeline_id,
-                                        asset,
-                                        e
-                                    );
-                                }
-                            }
+        tokio::spawn(async move {
+            matches!(
+                done.wait_for(Option::is_some).await.as_deref(),
+                Ok(Some(true))
+            )
+        })
+    }
 
-                            // Always release the processing lock
-                            let mut processing = self_clone.processing_pipelines.lock().await;
-                            processing.remove(&pipeline_id);
+    /// Takes the lease once an API mutation or another instance holding it is
+    /// done, false if it is still held after a lease's lifetime
+    async fn wait_for_lease(&self, pipeline_id: &str) -> Result<bool, EngineError> {
+        let deadline = Instant::now() + LEASE_TTL;
+        loop {
+            if self.acquire_lease(pipeline_id).await? {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
 
-                            // Decrement pending tasks counter
-                            self_clone.pending_tasks.fetch_sub(1, Ordering::SeqCst);
-                        });
-                    }
+    /// Takes the processing lock once the evaluation holding it is done, false
+    /// if it is still held after a lease's lifetime
+    async fn wait_for_processing_lock(&self, pipeline_id: &str) -> bool {
+        let deadline = Instant::now() + LEASE_TTL;
+        while Instant::now() < deadline {
+            if self
+                .processing_pipelines
+                .lock()
+                .await
+                .insert(pipeline_id.to_string())
+            {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        false
+    }
+
+    /// Evaluates the pipeline while holding its lease. It is read again first,
+    /// another instance could have evaluated it since it was fetched. Returns
+    /// false if the evaluation was cut short.
+    async fn evaluate_leased(&self, pipeline_id: &str, trigger: &str) -> bool {
+        let mut pipeline = match self.store.get_pipelines(&[pipeline_id.to_string()]).await {
+            Ok(mut pipelines) => match pipelines.pop().flatten() {
+                Some(pipeline) if matches!(pipeline.status, Status::Pending) => pipeline,
+                _ => return true,
+            },
+            Err(e) => {
+                tracing::error!(
+                    "{}: {} Failed to fetch pipeline: {}",
+                    pipeline_id,
+                    trigger,
+                    e
+                );
+                return false;
+            }
+        };
+
+        let result = tokio::select! {
+            r = self.evaluate_pipeline(&mut pipeline) => r,
+            _ = self.shutdown_signal.notified() => {
+                tracing::info!("Gracefully stopping pipeline evaluation for {}", pipeline_id);
+                return false;
+            }
+        };
+
+        match result {
+            Ok(is_complete) => {
+                self.index_confirming(&pipeline);
+                self.index_expiry(&pipeline);
+                if is_complete {
+                    // drop the pipeline from every index it is in, not
+                    // just the one that triggered this evaluation
+                    self.remove_from_active_pipelines(&pipeline);
                 }
             }
+            Err(e) => {
+                tracing::error!(
+                    "{}: {} Pipeline evaluation error: {}",
+                    pipeline_id,
+                    trigger,
+                    e
+                );
+            }
         }
 
-        histogram!("price_update_duration", start.elapsed());
-        tracing::debug!("{}: {} {} took {:?}", asset, price, slot, start.elapsed());
-        Ok(())
+        true
     }
 
     pub async fn shutdown(&self) {
@@ -328,5 +675,180 @@ impl Engine {
         }
 
         tracing::info!("All pipeline evaluations completed");
+
+        self.leave_cluster().await;
+    }
+}
+
+/// Held by a pipeline evaluation task, releases the pipeline's processing
+/// lock and the pending task it counts for when dropped, including when the
+/// evaluation panics
+struct ProcessingGuard {
+    processing_pipelines: Arc<Mutex<HashSet<String>>>,
+    pending_tasks: Arc<AtomicUsize>,
+    pipeline_id: String,
+}
+
+impl Drop for ProcessingGuard {
+    fn drop(&mut self) {
+        let pipeline_id = std::mem::take(&mut self.pipeline_id);
+        match self.processing_pipelines.try_lock() {
+            Ok(mut processing) => {
+                processing.remove(&pipeline_id);
+            }
+            // drop can't wait for the lock
+            Err(_) => {
+                let processing_pipelines = self.processing_pipelines.clone();
+                tokio::spawn(async move {
+                    processing_pipelines.lock().await.remove(&pipeline_id);
+                });
+            }
+        }
+        self.pending_tasks.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{engine, price_update, settle, stand_in, webhook_pipeline};
+    use super::*;
+    use crate::engine::events::PipelineEventKind;
+    use solana_sdk::pubkey::Pubkey;
+
+    #[tokio::test]
+    async fn test_price_update_triggers_pipeline() {
+        let (engine, store) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let other = Pubkey::new_unique().to_string();
+        let (url, handle) = stand_in(200).await;
+        let pipeline = webhook_pipeline(&asset, 2.0, &url);
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        engine
+            .handle_price_update(&price_update(&asset, 1.0))
+            .await
+            .unwrap();
+        settle(&engine).await;
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Pending));
+        assert!(store.events().is_empty());
+
+        // other assets don't touch the pipeline
+        engine
+            .handle_price_update(&price_update(&other, 3.0))
+            .await
+            .unwrap();
+        settle(&engine).await;
+        assert!(store.events().is_empty());
+
+        // the evaluation finished, a streamed update would be acknowledged
+        let evaluations = engine
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        assert_eq!(evaluations.len(), 1);
+        for evaluation in evaluations {
+            assert!(evaluation.await.unwrap());
+        }
+
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Completed));
+        handle.await.unwrap();
+        assert!(saved
+            .steps
+            .values()
+            .all(|step| matches!(step.status, Status::Completed)));
+        assert!(engine.active_pipelines.get(&asset).unwrap().is_empty());
+
+        let kinds: Vec<_> = store.events().into_iter().map(|e| e.kind).collect();
+        assert!(matches!(
+            kinds.as_slice(),
+            [PipelineEventKind::Triggered, PipelineEventKind::Completed]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_fails_pipeline() {
+        let (engine, store) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let (url, handle) = stand_in(500).await;
+        let pipeline = webhook_pipeline(&asset, 2.0, &url);
+        engine.add_pipeline(&pipeline).await.unwrap();
+
+        engine
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        settle(&engine).await;
+
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Failed));
+        handle.await.unwrap();
+        assert!(matches!(
+            store.events().last().map(|e| &e.kind),
+            Some(PipelineEventKind::Failed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_price_falls_back_to_store() {
+        let (engine, store) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let (url, handle) = stand_in(200).await;
+        // the price was never streamed to the engine, it has to come from the store
+        let mut pipeline = webhook_pipeline(&asset, 2.0, &url);
+        pipeline.steps.values_mut().for_each(|step| {
+            step.conditions[0].condition_type = pipeline::ConditionType::PriceBelow {
+                asset: asset.clone(),
+                value: 2.0,
+            }
+        });
+        engine.add_pipeline(&pipeline).await.unwrap();
+        store.set_price_update(price_update(&asset, 1.0));
+
+        engine
+            .evaluate_pipelines(&[format!("user:{}", pipeline.id)], &asset)
+            .await
+            .unwrap();
+        settle(&engine).await;
+
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Completed));
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_busy_pipeline_is_evaluated_after_the_running_evaluation() {
+        let (engine, _store) = engine();
+        let asset = Pubkey::new_unique().to_string();
+        let (url, handle) = stand_in(200).await;
+        let pipeline = webhook_pipeline(&asset, 2.0, &url);
+        engine.add_pipeline(&pipeline).await.unwrap();
+        let pipeline_id = format!("user:{}", pipeline.id);
+
+        // an evaluation from an earlier update is still running
+        engine
+            .processing_pipelines
+            .lock()
+            .await
+            .insert(pipeline_id.clone());
+        let mut evaluations = engine
+            .handle_price_update(&price_update(&asset, 3.0))
+            .await
+            .unwrap();
+        assert_eq!(evaluations.len(), 1);
+        let evaluation = evaluations.pop().unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!evaluation.is_finished());
+
+        engine
+            .processing_pipelines
+            .lock()
+            .await
+            .remove(&pipeline_id);
+        assert!(evaluation.await.unwrap());
+        let saved = engine.get_pipeline("user", pipeline.id).await.unwrap();
+        assert!(matches!(saved.status, Status::Completed));
+        handle.await.unwrap();
     }
 }
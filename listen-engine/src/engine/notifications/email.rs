@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use privy::Privy;
+use resend_rs::types::CreateEmailBaseOptions;
+use resend_rs::Resend;
+
+use super::NotificationChannel;
+use crate::redis::rate_limits::RateLimitType;
+
+const FROM: &str = "listen@app.listen-rs.com";
+
+/// Emails the user's Privy account address through Resend, unavailable when
+/// the engine runs without Privy
+pub struct EmailChannel {
+    privy: Option<Arc<Privy>>,
+}
+
+impl EmailChannel {
+    pub fn new(privy: Option<Arc<Privy>>) -> Self {
+        Self { privy }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for EmailChannel {
+    fn rate_limit_type(&self) -> RateLimitType {
+        RateLimitType::EmailNotifications
+    }
+
+    /// Emails were never counted against their limit, enforcing it now would
+    /// cut existing users off at the default without notice
+    fn counts_deliveries(&self) -> bool {
+        false
+    }
+
+    async fn send(&self, user_id: &str, message: &str) -> Result<String> {
+        let privy = self
+            .privy
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Email notifications require Privy"))?;
+        let recipient_email = privy.get_email_by_user_id(user_id).await?;
+        let api_key = std::env::var("RESEND_API_KEY")?;
+        let resend = Resend::new(&api_key);
+        let to = [recipient_email.as_str()];
+
+        let email = CreateEmailBaseOptions::new(FROM, to, message).with_text(message);
+
+        let result = resend
+            .emails
+            .send(email)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send email: {}", e))?;
+
+        tracing::info!("Email sent with ID: {:?}", result.id);
+
+        Ok(result.id.to_string())
+    }
+}
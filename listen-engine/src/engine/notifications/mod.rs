@@ -0,0 +1,115 @@
+pub mod email;
+pub mod telegram;
+pub mod webhook;
+
+use crate::engine::pipeline::{Notification, NotificationTarget};
+use crate::redis::rate_limits::RateLimitType;
+use crate::Engine;
+use anyhow::Result;
+use async_trait::async_trait;
+
+pub use email::EmailChannel;
+pub use telegram::TelegramChannel;
+pub use webhook::WebhookChannel;
+
+/// A destination notifications can be delivered to
+#[async_trait]
+pub trait NotificationChannel: Send + Sync {
+    /// Rate limit bucket the deliveries count against
+    fn rate_limit_type(&self) -> RateLimitType;
+
+    /// Whether deliveries are counted, or the limit is only checked
+    fn counts_deliveries(&self) -> bool {
+        true
+    }
+
+    /// Delivers the message, returns a channel specific delivery id
+    async fn send(&self, user_id: &str, message: &str) -> Result<String>;
+}
+
+impl Engine {
+    fn notification_channel(
+        &self,
+        target: &NotificationTarget,
+    ) -> Result<Box<dyn NotificationChannel>> {
+        Ok(match target {
+            NotificationTarget::Email => Box::new(EmailChannel::new(self.privy.clone())),
+            NotificationTarget::Webhook { url, secret } => {
+                Box::new(WebhookChannel::new(url.clone(), secret.clone()))
+            }
+            NotificationTarget::Telegram { chat_id } => {
+                Box::new(TelegramChannel::from_env(chat_id.clone())?)
+            }
+        })
+    }
+
+    pub async fn send_notification(
+        &self,
+        user_id: &str,
+        notification: &Notification,
+    ) -> Result<String> {
+        if self.backtest {
+            tracing::debug!(%user_id, message = %notification.message, "Skipping notification in backtest");
+            return Ok("backtest".to_string());
+        }
+        let channel = self.notification_channel(&notification.channel)?;
+        let limit_type = channel.rate_limit_type();
+
+        let rate_limit = self.store.get_rate_limit(user_id, &limit_type).await?;
+        if rate_limit.remaining == 0 {
+            return Err(anyhow::anyhow!(
+                "Rate limit exceeded for {}",
+                limit_type.key()
+            ));
+        }
+
+        let id = channel.send(user_id, &notification.message).await?;
+
+        // the message is already out, a failed counter update must not fail the step
+        if channel.counts_deliveries() {
+            if let Err(e) = self.store.increment_rate_limit(user_id, &limit_type).await {
+                tracing::warn!(%user_id, "Failed to record notification rate limit: {}", e);
+            }
+        }
+
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::{engine, stand_in};
+
+    #[tokio::test]
+    async fn test_deliveries_count_against_the_limit() {
+        let (engine, _) = engine();
+        let (url, handle) = stand_in(200).await;
+        let notification = Notification {
+            message: "price hit".to_string(),
+            channel: NotificationTarget::Webhook {
+                url,
+                secret: "s3cret".to_string(),
+            },
+        };
+
+        engine
+            .send_notification("user", &notification)
+            .await
+            .unwrap();
+        handle.await.unwrap();
+        let limit_type = RateLimitType::WebhookNotifications;
+        let rate_limit = engine
+            .store
+            .get_rate_limit("user", &limit_type)
+            .await
+            .unwrap();
+        assert_eq!(rate_limit.remaining, rate_limit.limit - 1);
+
+        // the email limit is only checked
+        let email = engine
+            .notification_channel(&NotificationTarget::Email)
+            .unwrap();
+        assert!(!email.counts_deliveries());
+    }
+}
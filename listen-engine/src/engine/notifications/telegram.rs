@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::NotificationChannel;
+use crate::redis::rate_limits::RateLimitType;
+
+const DEFAULT_API_URL: &str = "https://api.telegram.org";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+struct TelegramResponse {
+    ok: bool,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    result: Option<TelegramMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramMessage {
+    message_id: i64,
+}
+
+/// Sends the message to a chat through the bot configured with
+/// `TELEGRAM_BOT_TOKEN`, the user has to start a chat with the bot first
+pub struct TelegramChannel {
+    client: reqwest::Client,
+    api_url: String,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl TelegramChannel {
+    pub fn new(api_url: String, bot_token: String, chat_id: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_url,
+            bot_token,
+            chat_id,
+        }
+    }
+
+    pub fn from_env(chat_id: String) -> Result<Self> {
+        let bot_token = std::env::var("TELEGRAM_BOT_TOKEN")
+            .map_err(|_| anyhow::anyhow!("TELEGRAM_BOT_TOKEN is not set"))?;
+        let api_url =
+            std::env::var("TELEGRAM_API_URL").unwrap_or_else(|_| DEFAULT_API_URL.to_string());
+        Ok(Self::new(api_url, bot_token, chat_id))
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for TelegramChannel {
+    fn rate_limit_type(&self) -> RateLimitType {
+        RateLimitType::TelegramNotifications
+    }
+
+    async fn send(&self, _user_id: &str, message: &str) -> Result<String> {
+        let url = format!("{}/bot{}/sendMessage", self.api_url, self.bot_token);
+
+        let response: TelegramResponse = self
+            .client
+            .post(&url)
+            .timeout(TIMEOUT)
+            .json(&json!({
+                "chat_id": self.chat_id,
+                "text": message,
+            }))
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send telegram message: {}", e.without_url()))?
+            .json()
+            .await?;
+
+        match response {
+            TelegramResponse {
+                ok: true,
+                result: Some(message),
+                ..
+            } => {
+                tracing::info!(message_id = message.message_id, "Telegram message sent");
+                Ok(message.message_id.to_string())
+            }
+            TelegramResponse { description, .. } => Err(anyhow::anyhow!(
+                "Telegram rejected message: {}",
+                description.unwrap_or_default()
+            )),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{redirect, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use super::NotificationChannel;
+use crate::redis::rate_limits::RateLimitType;
+
+pub const SIGNATURE_HEADER: &str = "X-Listen-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Listen-Timestamp";
+pub const DELIVERY_HEADER: &str = "X-Listen-Delivery";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Tests deliver to stand-ins on localhost
+const ALLOW_PRIVATE_HOSTS: bool = cfg!(test);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub delivery_id: String,
+    pub user_id: String,
+    pub message: String,
+    pub timestamp: i64,
+}
+
+/// POSTs a JSON payload to a user supplied URL, signed with HMAC-SHA256 over
+/// `"{timestamp}.{body}"` so receivers can verify origin and reject replays
+pub struct WebhookChannel {
+    url: String,
+    secret: String,
+}
+
+impl WebhookChannel {
+    pub fn new(url: String, secret: String) -> Self {
+        Self { url, secret }
+    }
+
+    /// The engine runs next to internal services, so the host is resolved
+    /// and rejected if any of its addresses isn't public. The client is
+    /// pinned to the checked addresses and doesn't follow redirects.
+    async fn client(&self) -> Result<(reqwest::Client, Url)> {
+        let url = Url::parse(&self.url)?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("Webhook url has no host"))?
+            .to_string();
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("Webhook url has no port"))?;
+
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+            .await?
+            .collect();
+        if addrs.is_empty() {
+            return Err(anyhow!("Webhook host {} did not resolve", host));
+        }
+        if !ALLOW_PRIVATE_HOSTS && addrs.iter().any(|addr| !is_public(&addr.ip())) {
+            return Err(anyhow!("Webhook host {} is not public", host));
+        }
+
+        let client = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .resolve_to_addrs(&host, &addrs)
+            .build()?;
+        Ok((client, url))
+    }
+}
+
+/// Whether the address is reachable from the internet, i.e. not loopback,
+/// private, link-local, shared or otherwise reserved
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64) // shared address space
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(&IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00 // unique local
+                    || (first & 0xffc0) == 0xfe80) // link-local
+            }
+        },
+    }
+}
+
+/// Hex encoded signature sent in the `X-Listen-Signature` header
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[async_trait]
+impl NotificationChannel for WebhookChannel {
+    fn rate_limit_type(&self) -> RateLimitType {
+        RateLimitType::WebhookNotifications
+    }
+
+    async fn send(&self, user_id: &str, message: &str) -> Result<String> {
+        let payload = WebhookPayload {
+            delivery_id: Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            message: message.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+        };
+        let body = serde_json::to_vec(&payload)?;
+        let signature = sign(&self.secret, payload.timestamp, &body);
+
+        let (client, url) = self.client().await?;
+        let response = client
+            .post(url)
+            .timeout(TIMEOUT)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+            .header(TIMESTAMP_HEADER, payload.timestamp.to_string())
+            .header(DELIVERY_HEADER, &payload.delivery_id)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to deliver webhook: {}", e))?;
+        // redirects aren't followed, they don't count as deliveries
+        if !response.status().is_success() {
+            return Err(anyhow!("Webhook rejected delivery: {}", response.status()));
+        }
+
+        tracing::info!(delivery_id = %payload.delivery_id, "Webhook delivered");
+
+        Ok(payload.delivery_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::stand_in;
+
+    #[tokio::test]
+    async fn test_webhook_is_signed() {
+        let (url, handle) = stand_in(200).await;
+        let channel = WebhookChannel::new(url, "s3cret".to_string());
+
+        let delivery_id = channel.send("user-1", "price hit").await.unwrap();
+        let request = handle.await.unwrap();
+
+        let timestamp: i64 = request.headers[&TIMESTAMP_HEADER.to_lowercase()]
+            .parse()
+            .unwrap();
+        assert_eq!(
+            request.headers[&SIGNATURE_HEADER.to_lowercase()],
+            format!("sha256={}", sign("s3cret", timestamp, &request.body))
+        );
+        assert_eq!(
+            request.headers[&DELIVERY_HEADER.to_lowercase()],
+            delivery_id
+        );
+
+        let payload: WebhookPayload = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(payload.user_id, "user-1");
+        assert_eq!(payload.message, "price hit");
+        assert_eq!(payload.timestamp, timestamp);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_error_status_fails() {
+        let (url, handle) = stand_in(500).await;
+        let channel = WebhookChannel::new(url, "s3cret".to_string());
+
+        assert!(channel.send("user-1", "price hit").await.is_err());
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhook_redirect_is_not_followed() {
+        let (url, handle) = stand_in(307).await;
+        let channel = WebhookChannel::new(url, "s3cret".to_string());
+
+        assert!(channel.send("user-1", "price hit").await.is_err());
+        handle.await.unwrap();
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use solana_sdk::pubkey::Pubkey;
@@ -6,16 +7,30 @@ use std::{collections::HashMap, str::FromStr};
 use privy::caip2::Caip2;
 
 use super::retry::retry_with_backoff;
-use crate::jup::Jupiter;
+use crate::jup::{Jupiter, PriorityFee, SwapParams};
 use privy::util::base64encode;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SwapOrder {
     pub input_token: String,
     pub output_token: String,
     pub amount: String,
     pub from_chain_caip2: String,
     pub to_chain_caip2: String,
+    /// Max slippage in basis points, Jupiter's dynamic slippage is used when unset
+    #[serde(default)]
+    pub slippage_bps: Option<u16>,
+    /// Solana priority fee level, capped by `max_priority_fee_lamports`
+    #[serde(default)]
+    pub priority_fee: Option<PriorityFee>,
+    #[serde(default)]
+    pub max_priority_fee_lamports: Option<u64>,
+    /// Quotes with a higher price impact (in percent) are rejected
+    #[serde(default)]
+    pub max_price_impact_pct: Option<f64>,
+    /// The order fails instead of executing after this time
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -49,6 +64,15 @@ pub enum SwapOrderError {
 
     #[error("No wallet address")]
     NoWalletAddress,
+
+    #[error("Order deadline {0} has passed")]
+    DeadlineExceeded(DateTime<Utc>),
+
+    #[error("Price impact {impact_pct}% exceeds the maximum of {max_pct}%")]
+    PriceImpactTooHigh { impact_pct: f64, max_pct: f64 },
+
+    #[error("Invalid price impact in quote: {0}")]
+    InvalidPriceImpact(String),
 }
 
 pub fn is_solana(caip2: &str) -> bool {
@@ -67,6 +91,34 @@ impl SwapOrder {
     pub fn is_solana(&self) -> bool {
         is_solana(&self.from_chain_caip2)
     }
+
+    /// Solana to Solana swaps are routed through Jupiter, everything else
+    /// through LiFi
+    pub fn is_solana_swap(&self) -> bool {
+        self.is_solana() && self.from_chain_caip2 == self.to_chain_caip2
+    }
+
+    fn check_price_impact(&self, impact_pct: f64) -> Result<(), SwapOrderError> {
+        match self.max_price_impact_pct {
+            Some(max_pct) if impact_pct > max_pct => Err(SwapOrderError::PriceImpactTooHigh {
+                impact_pct,
+                max_pct,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the impact of a quote that reports it as a fraction, a quote
+    /// without a readable impact is rejected if the order sets a maximum
+    fn check_quoted_price_impact(&self, fraction: &str) -> Result<(), SwapOrderError> {
+        if self.max_price_impact_pct.is_none() {
+            return Ok(());
+        }
+        match fraction.parse::<f64>() {
+            Ok(fraction) if fraction.is_finite() => self.check_price_impact(fraction * 100.0),
+            _ => Err(SwapOrderError::InvalidPriceImpact(fraction.to_string())),
+        }
+    }
 }
 
 // Map of CAIP2 identifiers to LiFi chain IDs
@@ -117,12 +169,30 @@ pub enum SwapOrderTransaction {
     Solana(String),
 }
 
+/// Swap transaction along with what its quote says about it
+pub struct BuiltSwap {
+    pub transaction: SwapOrderTransaction,
+    /// USD value of the input amount per the quote, only LiFi quotes have one
+    pub input_usd: Option<f64>,
+}
+
 pub async fn swap_order_to_transaction(
     order: &SwapOrder,
     lifi: &lifi::LiFi,
     wallet_address: Option<String>, // evm output
     pubkey: Option<String>,         // solana output
 ) -> Result<SwapOrderTransaction, SwapOrderError> {
+    build_swap(order, lifi, wallet_address, pubkey)
+        .await
+        .map(|built| built.transaction)
+}
+
+pub async fn build_swap(
+    order: &SwapOrder,
+    lifi: &lifi::LiFi,
+    wallet_address: Option<String>, // evm output
+    pubkey: Option<String>,         // solana output
+) -> Result<BuiltSwap, SwapOrderError> {
     let from_chain_id =
         caip2_to_chain_id(&order.from_chain_caip2).ok_or(SwapOrderError::InvalidCaip2)?;
     let to_chain_id =
@@ -134,14 +204,23 @@ pub async fn swap_order_to_transaction(
     if pubkey.is_none() && order.is_solana() {
         return Err(SwapOrderError::SolanaWalletNotAvailable);
     }
+    if let Some(deadline) = order.deadline {
+        if Utc::now() > deadline {
+            return Err(SwapOrderError::DeadlineExceeded(deadline));
+        }
+    }
 
     if from_chain_id == to_chain_id && is_solana(&order.from_chain_caip2) {
         tracing::info!("Solana swap order to transaction");
         if let Some(pubkey) = pubkey {
-            return retry_with_backoff("solana swap to transaction", || async {
+            let transaction = retry_with_backoff("solana swap to transaction", || async {
                 try_solana_swap_order_to_transaction(order, &pubkey).await
             })
-            .await;
+            .await?;
+            return Ok(BuiltSwap {
+                transaction,
+                input_usd: None,
+            });
         } else {
             return Err(SwapOrderError::NoWalletAddress);
         }
@@ -162,7 +241,7 @@ async fn try_lifi_swap_order_to_transaction(
     lifi: &lifi::LiFi,
     wallet_address: &str,
     pubkey: &str,
-) -> Result<SwapOrderTransaction, SwapOrderError> {
+) -> Result<BuiltSwap, SwapOrderError> {
     let from_chain_id =
         caip2_to_chain_id(&order.from_chain_caip2).ok_or(SwapOrderError::InvalidCaip2)?;
     let to_chain_id =
@@ -180,8 +259,13 @@ async fn try_lifi_swap_order_to_transaction(
         pubkey
     };
 
+    let options = lifi::quote::QuoteOptions {
+        slippage: order.slippage_bps.map(|bps| bps as f64 / 10_000.0),
+        max_price_impact: order.max_price_impact_pct.map(|pct| pct / 100.0),
+    };
+
     let quote = lifi
-        .get_quote(
+        .get_quote_with_options(
             &from_chain_id.to_string(),
             &to_chain_id.to_string(),
             &order.input_token,
@@ -189,24 +273,34 @@ async fn try_lifi_swap_order_to_transaction(
             from_address,
             to_address,
             &order.amount,
+            &options,
         )
         .await
         .map_err(SwapOrderError::LiFiError)?;
 
-    match quote.transaction_request {
+    let input_usd = quote
+        .estimate
+        .from_amount_usd
+        .as_deref()
+        .and_then(|usd| usd.parse::<f64>().ok());
+    let transaction = match quote.transaction_request {
         Some(transaction_request) => {
             if transaction_request.is_solana() {
-                Ok(SwapOrderTransaction::Solana(transaction_request.data))
+                SwapOrderTransaction::Solana(transaction_request.data)
             } else {
-                Ok(SwapOrderTransaction::Evm(
+                SwapOrderTransaction::Evm(
                     transaction_request
                         .to_json_rpc()
                         .map_err(SwapOrderError::SerializeError)?,
-                ))
+                )
             }
         }
-        None => Err(SwapOrderError::NoTransactionRequest),
-    }
+        None => return Err(SwapOrderError::NoTransactionRequest),
+    };
+    Ok(BuiltSwap {
+        transaction,
+        input_usd,
+    })
 }
 
 // Helper function that actually performs the swap operation
@@ -221,13 +315,23 @@ async fn try_solana_swap_order_to_transaction(
             .amount
             .parse::<u64>()
             .map_err(|e| SwapOrderError::InvalidAmount(anyhow::anyhow!(e)))?,
+        order.slippage_bps,
     )
     .await
     .map_err(SwapOrderError::JupiterError)?;
 
+    order.check_quoted_price_impact(&quote.price_impact_pct)?;
+
+    let params = SwapParams {
+        dynamic_slippage: order.slippage_bps.is_none(),
+        priority_fee: order.priority_fee,
+        max_priority_fee_lamports: order.max_priority_fee_lamports,
+    };
+
     let tx = Jupiter::swap(
         quote,
         &Pubkey::from_str(pubkey).map_err(|e| SwapOrderError::InvalidPubkey(anyhow::anyhow!(e)))?,
+        &params,
     )
     .await
     .map_err(SwapOrderError::JupiterError)?;
@@ -253,6 +357,53 @@ mod tests {
 
     use super::*;
 
+    #[tokio::test]
+    async fn test_order_past_deadline() {
+        let order = SwapOrder {
+            input_token: "So11111111111111111111111111111111111111112".to_string(),
+            output_token: "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v".to_string(),
+            amount: "1000".to_string(),
+            from_chain_caip2: Caip2::SOLANA.to_string(),
+            to_chain_caip2: Caip2::SOLANA.to_string(),
+            deadline: Some(Utc::now() - chrono::Duration::seconds(1)),
+            ..Default::default()
+        };
+
+        let result = swap_order_to_transaction(
+            &order,
+            &lifi::LiFi::new(None),
+            None,
+            Some(TEST_ADDRESS_SOL.to_string()),
+        )
+        .await;
+        assert!(matches!(result, Err(SwapOrderError::DeadlineExceeded(_))));
+    }
+
+    #[test]
+    fn test_check_price_impact() {
+        let order = SwapOrder {
+            max_price_impact_pct: Some(5.0),
+            ..Default::default()
+        };
+        assert!(order.check_price_impact(4.9).is_ok());
+        assert!(matches!(
+            order.check_price_impact(5.1),
+            Err(SwapOrderError::PriceImpactTooHigh { .. })
+        ));
+        assert!(SwapOrder::default().check_price_impact(99.0).is_ok());
+
+        // quotes report a fraction, unreadable ones don't pass a maximum
+        assert!(order.check_quoted_price_impact("0.049").is_ok());
+        assert!(order.check_quoted_price_impact("0.051").is_err());
+        for invalid in ["", "n/a", "NaN"] {
+            assert!(matches!(
+                order.check_quoted_price_impact(invalid),
+                Err(SwapOrderError::InvalidPriceImpact(_))
+            ));
+        }
+        assert!(SwapOrder::default().check_quoted_price_impact("").is_ok());
+    }
+
     async fn test_swap_generic(
         input_token: &str,
         output_token: &str,
@@ -269,12 +420,10 @@ mod tests {
             output_token: output_token.to_string(),
             from_chain_caip2: from_chain_caip2.to_string(),
             to_chain_caip2: to_chain_caip2.to_string(),
+            ..Default::default()
         };
 
-        let lifi_api_key: Option<String> = match std::env::var("LIFI_API_KEY") {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        };
+        let lifi_api_key: Option<String> = std::env::var("LIFI_API_KEY").ok();
 
         let lifi = lifi::LiFi::new(lifi_api_key);
         let transaction = swap_order_to_transaction(
@@ -334,7 +483,7 @@ mod tests {
                     .unwrap(),
                 &swap_order,
                 &privy_tx,
-                privy.clone(),
+                &crate::engine::executor::PrivyExecutor::new(privy.clone()),
             )
             .await
             .unwrap();
@@ -478,12 +627,9 @@ mod tests {
 
         let privy = privy::Privy::new(privy::config::PrivyConfig::from_env().unwrap());
 
-        let value = format!("0x{}", hex::encode((10e8 as u64).to_le_bytes().to_vec()));
-        let gas_limit = format!("0x{}", hex::encode((1000000 as u64).to_le_bytes().to_vec()));
-        let gas_price = format!(
-            "0x{}",
-            hex::encode((1000000000 as u64).to_le_bytes().to_vec())
-        );
+        let value = format!("0x{}", hex::encode((10e8 as u64).to_le_bytes()));
+        let gas_limit = format!("0x{}", hex::encode(1000000_u64.to_le_bytes()));
+        let gas_price = format!("0x{}", hex::encode(1000000000_u64.to_le_bytes()));
         println!("Value: {:#?}", value);
 
         // Execute the transaction
@@ -1,5 +1,8 @@
 use std::hash::{Hash, Hasher};
-use std::{collections::HashMap, hash::DefaultHasher};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::DefaultHasher,
+};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -9,29 +12,295 @@ use crate::engine::order::SwapOrder;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ConditionType {
-    PriceAbove { asset: String, value: f64 },
-    PriceBelow { asset: String, value: f64 },
-    Now { asset: String },
+    PriceAbove {
+        asset: String,
+        value: f64,
+    },
+    PriceBelow {
+        asset: String,
+        value: f64,
+    },
+    MarketCapAbove {
+        asset: String,
+        value: f64,
+    },
+    MarketCapBelow {
+        asset: String,
+        value: f64,
+    },
+    /// Fires when the USD volume traded over the last `window` seconds is at
+    /// least `value`. Volume is only tracked while the asset has active
+    /// pipelines, so the window fills up after the pipeline is created.
+    RollingVolumeAbove {
+        asset: String,
+        window: u64,
+        value: f64,
+    },
+    /// Fires on a single buy of at least `min_usd`, made after the pipeline was created
+    LargeBuy {
+        asset: String,
+        min_usd: f64,
+    },
+    /// Fires when `wallet` swaps at least `min_usd` on the given side, after
+    /// the pipeline was created. `trade` is the last matched swap, persisted
+    /// so that the step's order can mirror it, see `copy`.
+    WalletTraded {
+        wallet: String,
+        #[serde(default)]
+        side: TradeSide,
+        min_usd: f64,
+        #[serde(default)]
+        trade: Option<LeaderTrade>,
+    },
+    /// Fires once the price drops `trail_pct` percent below the highest price
+    /// seen since the condition was created. `peak` is the high-water mark,
+    /// persisted with the pipeline so it survives restarts.
+    TrailingStop {
+        asset: String,
+        trail_pct: f64,
+        #[serde(default)]
+        peak: Option<f64>,
+    },
+    Now {
+        asset: String,
+    },
+    /// Fires once the given point in time has passed
+    At {
+        timestamp: DateTime<Utc>,
+    },
+    /// Fires once the given number of seconds has passed since the pipeline was created
+    After {
+        duration_since_created: u64,
+    },
+    /// Fires on every tick of the cron schedule, `expr` uses the 6/7 field
+    /// format with seconds (e.g. "0 */5 * * * *"). Steps with a cron condition
+    /// re-arm after executing instead of completing. `next_run` is the next
+    /// scheduled time, persisted with the pipeline, and only advances once
+    /// the step fires.
+    Cron {
+        expr: String,
+        #[serde(default)]
+        next_run: Option<DateTime<Utc>>,
+    },
     And(Vec<Condition>),
     Or(Vec<Condition>),
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+    #[default]
+    Any,
+}
+
+impl TradeSide {
+    pub fn matches(&self, is_buy: bool) -> bool {
+        match self {
+            TradeSide::Buy => is_buy,
+            TradeSide::Sell => !is_buy,
+            TradeSide::Any => true,
+        }
+    }
+}
+
+/// Swap of a watched wallet, as seen in the price updates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderTrade {
+    pub mint: String,
+    pub is_buy: bool,
+    pub usd: f64,
+    pub price: f64,
+    pub signature: String,
+    pub timestamp: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Condition {
     pub condition_type: ConditionType,
     pub triggered: bool,
     pub last_evaluated: Option<DateTime<Utc>>,
+    /// Protection against stale and outlier prices, price and market cap
+    /// conditions only
+    #[serde(default)]
+    pub guard: PriceGuard,
+    /// Consecutive prints the condition has held on, see `PriceGuard`
+    #[serde(default)]
+    pub confirmations: u32,
+    /// Price the condition was last evaluated at
+    #[serde(default)]
+    pub observed: Option<ObservedPrice>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceGuard {
+    /// Prices older than this many seconds never satisfy the condition
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    /// Consecutive prints past the threshold needed to fire, 1 when unset
+    #[serde(default)]
+    pub confirmations: Option<u32>,
+    #[serde(default)]
+    pub source: PriceSource,
+}
+
+/// Price a condition is evaluated against, market caps are scaled along
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PriceSource {
+    /// The last swap
+    #[default]
+    Last,
+    /// Volume weighted average price of the swaps within `window` seconds
+    Vwap { window: u64 },
+    /// Median price of the swaps within `window` seconds
+    Median { window: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObservedPrice {
+    pub price: f64,
+    pub market_cap: f64,
+    /// slot and time of the last swap that went into the price
+    pub slot: u64,
+    pub timestamp: u64,
+}
+
+impl Condition {
+    /// Hash the parts of the condition that change during evaluation,
+    /// so that updated state gets picked up by `Engine::save_pipeline`
+    fn hash_state<H: Hasher>(&self, state: &mut H) {
+        self.triggered.hash(state);
+        self.confirmations.hash(state);
+        match &self.condition_type {
+            ConditionType::TrailingStop { peak, .. } => {
+                peak.map(f64::to_bits).hash(state);
+            }
+            ConditionType::Cron { next_run, .. } => {
+                next_run.hash(state);
+            }
+            ConditionType::WalletTraded { trade, .. } => {
+                trade.as_ref().map(|t| &t.signature).hash(state);
+            }
+            ConditionType::And(sub) | ConditionType::Or(sub) => {
+                for condition in sub {
+                    condition.hash_state(state);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Where a notification is delivered, defaults to the user's email
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum NotificationTarget {
+    #[default]
+    Email,
+    /// signed POST, see `notifications::webhook`
+    Webhook {
+        url: String,
+        secret: String,
+    },
+    Telegram {
+        chat_id: String,
+    },
+}
+
+/// Pipelines are logged, the webhook secret is left out
+impl std::fmt::Debug for NotificationTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotificationTarget::Email => write!(f, "Email"),
+            NotificationTarget::Webhook { url, .. } => f
+                .debug_struct("Webhook")
+                .field("url", url)
+                .field("secret", &"<redacted>")
+                .finish(),
+            NotificationTarget::Telegram { chat_id } => f
+                .debug_struct("Telegram")
+                .field("chat_id", chat_id)
+                .finish(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Notification {
     pub message: String,
+    #[serde(default)]
+    pub channel: NotificationTarget,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     Order(SwapOrder),
     Notification(Notification),
+    /// Dollar-cost averaging, `order` is split into `count` equal slices
+    /// executed `interval` seconds apart, starting once the conditions are met
+    Dca {
+        order: SwapOrder,
+        interval: u64,
+        count: u32,
+    },
+    /// Time-weighted execution, `order` is split into `slices` equal slices
+    /// spread evenly over `duration` seconds
+    Twap {
+        order: SwapOrder,
+        duration: u64,
+        slices: u32,
+    },
+    Rebalance(Rebalance),
+}
+
+/// Target allocation of the wallet over a set of Solana mints, see `rebalance`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rebalance {
+    /// mint -> target weight in percent, the weights add up to 100
+    pub targets: BTreeMap<String, f64>,
+    /// Only rebalance once a weight is this many percentage points off its
+    /// target, the step then re-arms instead of completing
+    #[serde(default)]
+    pub drift_pct: Option<f64>,
+    #[serde(default)]
+    pub slippage_bps: Option<u16>,
+    #[serde(default)]
+    pub max_price_impact_pct: Option<f64>,
+}
+
+impl Action {
+    /// The swap of the action, sliced orders included
+    pub fn order(&self) -> Option<&SwapOrder> {
+        match self {
+            Action::Order(order) | Action::Dca { order, .. } | Action::Twap { order, .. } => {
+                Some(order)
+            }
+            Action::Notification(_) | Action::Rebalance(_) => None,
+        }
+    }
+
+    pub fn order_mut(&mut self) -> Option<&mut SwapOrder> {
+        match self {
+            Action::Order(order) | Action::Dca { order, .. } | Action::Twap { order, .. } => {
+                Some(order)
+            }
+            Action::Notification(_) | Action::Rebalance(_) => None,
+        }
+    }
+
+    /// Number of slices and the seconds between them, for sliced orders
+    pub fn slicing(&self) -> Option<(u32, u64)> {
+        match self {
+            Action::Dca {
+                interval, count, ..
+            } => Some((*count, *interval)),
+            Action::Twap {
+                duration, slices, ..
+            } => Some((*slices, duration / (*slices).max(1) as u64)),
+            Action::Order(_) | Action::Notification(_) | Action::Rebalance(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +312,144 @@ pub struct PipelineStep {
     pub status: Status,
     pub transaction_hash: Option<String>,
     pub error: Option<String>,
+    /// One-cancels-other group, once a step in the group executes the other
+    /// pending steps in the group are cancelled
+    #[serde(default)]
+    pub oco_group: Option<String>,
+    /// Set instead of a real transaction when the order ran in dry-run mode
+    #[serde(default)]
+    pub simulated_fill: Option<SimulatedFill>,
+    /// When the transaction was sent, steps that don't confirm in time fail
+    #[serde(default)]
+    pub submitted_at: Option<DateTime<Utc>>,
+    /// Daily notional reserved for the transaction, released if it fails
+    #[serde(default)]
+    pub reserved_usd: u32,
+    /// What the confirmed transaction actually swapped
+    #[serde(default)]
+    pub fill: Option<Fill>,
+    /// Good-till-time, the step and its downstream steps are cancelled if it
+    /// hasn't executed by then
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Last time the user edited the step, see `WireStepEdit`
+    #[serde(default)]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Child executions of a DCA/TWAP order, planned once the conditions are met
+    #[serde(default)]
+    pub slices: Vec<OrderSlice>,
+    /// Base units of the sliced order that haven't been filled yet
+    #[serde(default)]
+    pub remaining_amount: Option<String>,
+    /// Last drift check and swaps of a rebalance
+    #[serde(default)]
+    pub rebalance: Option<RebalanceState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RebalanceState {
+    pub checked_at: DateTime<Utc>,
+    /// Largest distance of a weight from its target at the last check, in
+    /// percentage points
+    pub drift_pct: f64,
+    pub rebalanced_at: Option<DateTime<Utc>>,
+    /// Swaps of the last rebalance, in the order they were sent
+    pub orders: Vec<RebalanceOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RebalanceOrder {
+    pub input_token: String,
+    pub output_token: String,
+    /// base units of `input_token`
+    pub amount: String,
+    pub status: Status,
+    pub transaction_hash: Option<String>,
+    pub submitted_at: Option<DateTime<Utc>>,
+    /// Daily notional reserved for the transaction, released if it fails
+    #[serde(default)]
+    pub reserved_usd: u32,
+    pub simulated_fill: Option<SimulatedFill>,
+    pub fill: Option<Fill>,
+    pub error: Option<String>,
+}
+
+/// One scheduled execution of a sliced order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderSlice {
+    /// base units
+    pub amount: String,
+    pub due_at: DateTime<Utc>,
+    pub status: Status,
+    pub transaction_hash: Option<String>,
+    pub submitted_at: Option<DateTime<Utc>>,
+    /// Daily notional reserved for the transaction, released if it fails
+    #[serde(default)]
+    pub reserved_usd: u32,
+    pub simulated_fill: Option<SimulatedFill>,
+    pub fill: Option<Fill>,
+    pub error: Option<String>,
+}
+
+/// Fill recorded for a dry-run order, priced off the engine's price cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulatedFill {
+    pub amount: String,
+    pub input_price: Option<f64>,
+    pub output_price: Option<f64>,
+    pub filled_at: DateTime<Utc>,
+}
+
+/// Amounts moved by a confirmed order transaction, in base units
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    /// `None` when not visible in the transaction, e.g. native EVM input
+    pub input_amount: Option<String>,
+    /// `None` for cross-chain orders, the output lands in a later transaction
+    pub output_amount: Option<String>,
+    /// Input tokens paid per output token, adjusted for decimals
+    pub effective_price: Option<f64>,
+    pub confirmed_at: DateTime<Utc>,
+}
+
+impl PipelineStep {
+    /// Recurring steps are re-armed after executing instead of completing,
+    /// steps on a cron schedule and drift triggered rebalances
+    pub fn is_recurring(&self) -> bool {
+        if matches!(&self.action, Action::Rebalance(rebalance) if rebalance.drift_pct.is_some()) {
+            return true;
+        }
+        let mut stack: Vec<&Condition> = self.conditions.iter().collect();
+        while let Some(condition) = stack.pop() {
+            match &condition.condition_type {
+                ConditionType::Cron { .. } => return true,
+                ConditionType::And(sub) | ConditionType::Or(sub) => stack.extend(sub.iter()),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Sliced orders are past their conditions once the first slice is planned
+    pub fn is_slicing(&self) -> bool {
+        !self.slices.is_empty()
+    }
+
+    /// Whether a transaction of the step was sent and has neither confirmed
+    /// nor failed yet
+    pub fn in_flight(&self) -> bool {
+        matches!(self.status, Status::Confirming)
+            || self
+                .slices
+                .iter()
+                .any(|slice| matches!(slice.status, Status::Confirming))
+            || self.rebalance.as_ref().is_some_and(|state| {
+                state
+                    .orders
+                    .iter()
+                    .any(|order| matches!(order.status, Status::Confirming))
+            })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,14 +462,22 @@ pub struct Pipeline {
     pub steps: HashMap<Uuid, PipelineStep>,
     pub status: Status,
     pub created_at: DateTime<Utc>,
+    /// Orders are simulated instead of sent, see `Engine::execute_order`
+    #[serde(default)]
+    pub dry_run: bool,
+    /// The pipeline is cancelled once this time has passed
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Status {
-    Pending,   // Not yet started
-    Completed, // Successfully finished
-    Failed,    // Execution failed
-    Cancelled, // Manually cancelled
+    Pending,    // Not yet started
+    Confirming, // Transaction sent, waiting for it to confirm
+    Completed,  // Successfully finished
+    Failed,     // Execution failed
+    Cancelled,  // Manually cancelled or expired
+    Paused,     // Pipeline is not evaluated until resumed
 }
 
 impl Hash for Status {
@@ -72,11 +487,134 @@ impl Hash for Status {
             Status::Completed => state.write_u8(1),
             Status::Failed => state.write_u8(2),
             Status::Cancelled => state.write_u8(3),
+            Status::Confirming => state.write_u8(4),
+            Status::Paused => state.write_u8(5),
         }
     }
 }
 
 impl Pipeline {
+    /// Steps that have the given step in their `next_steps`
+    pub fn parent_steps(&self, step_id: Uuid) -> Vec<&PipelineStep> {
+        self.steps
+            .values()
+            .filter(|step| step.next_steps.contains(&step_id))
+            .collect()
+    }
+
+    /// Cancels the step if it is pending, along with all of its pending
+    /// downstream steps. Returns the ids of the cancelled steps.
+    pub fn cancel_step_and_downstream(&mut self, step_id: Uuid) -> Vec<Uuid> {
+        let mut cancelled = Vec::new();
+        let mut queue = vec![step_id];
+
+        while let Some(current_id) = queue.pop() {
+            if let Some(step) = self.steps.get_mut(&current_id) {
+                if matches!(step.status, Status::Pending) {
+                    step.status = Status::Cancelled;
+                    cancelled.push(current_id);
+                    queue.extend(step.next_steps.iter().copied());
+                }
+            }
+        }
+
+        cancelled
+    }
+
+    /// Whether another step of the step's OCO group has a transaction in
+    /// flight, the group is settled by whether that one confirms
+    pub fn oco_sibling_in_flight(&self, step_id: Uuid) -> bool {
+        let Some(group) = self
+            .steps
+            .get(&step_id)
+            .and_then(|step| step.oco_group.as_ref())
+        else {
+            return false;
+        };
+        self.steps.iter().any(|(id, step)| {
+            *id != step_id && step.oco_group.as_ref() == Some(group) && step.in_flight()
+        })
+    }
+
+    /// Cancels the other steps in the OCO group of the given step, along with
+    /// their downstream steps. Returns the ids of the cancelled steps.
+    pub fn cancel_oco_siblings(&mut self, step_id: Uuid) -> Vec<Uuid> {
+        let Some(group) = self
+            .steps
+            .get(&step_id)
+            .and_then(|step| step.oco_group.clone())
+        else {
+            return Vec::new();
+        };
+
+        let siblings: Vec<Uuid> = self
+            .steps
+            .iter()
+            .filter(|(id, step)| **id != step_id && step.oco_group.as_ref() == Some(&group))
+            .map(|(id, _)| *id)
+            .collect();
+
+        siblings
+            .into_iter()
+            .flat_map(|id| self.cancel_step_and_downstream(id))
+            .collect()
+    }
+
+    /// Earliest time the pipeline or one of its pending steps expires, steps
+    /// that started slicing have already executed
+    pub fn next_expiry(&self) -> Option<DateTime<Utc>> {
+        self.steps
+            .values()
+            .filter(|step| matches!(step.status, Status::Pending) && !step.is_slicing())
+            .filter_map(|step| step.expires_at)
+            .chain(self.expires_at)
+            .min()
+    }
+
+    /// Cancels the pipeline if it has expired, otherwise the pending steps
+    /// that have expired along with their downstream steps. Returns the ids
+    /// of the cancelled steps. Transactions in flight are still confirmed,
+    /// an expired pipeline is only cancelled once none are left.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<Uuid> {
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            let mut cancelled = Vec::new();
+            for step in self.steps.values_mut() {
+                if step.in_flight() {
+                    // no further slices are sent
+                    for slice in step.slices.iter_mut() {
+                        if matches!(slice.status, Status::Pending) {
+                            slice.status = Status::Cancelled;
+                        }
+                    }
+                } else if matches!(step.status, Status::Pending) {
+                    step.status = Status::Cancelled;
+                    cancelled.push(step.id);
+                }
+            }
+            if !self.steps.values().any(PipelineStep::in_flight) {
+                self.status = Status::Cancelled;
+            }
+            return cancelled;
+        }
+
+        let expired: Vec<Uuid> = self
+            .steps
+            .values()
+            .filter(|step| {
+                matches!(step.status, Status::Pending)
+                    && !step.is_slicing()
+                    && !step.in_flight()
+                    && step.expires_at.is_some_and(|expires_at| expires_at <= now)
+            })
+            .map(|step| step.id)
+            .collect();
+
+        expired
+            .into_iter()
+            .flat_map(|id| self.cancel_step_and_downstream(id))
+            .collect()
+    }
+
     pub fn hash(&self) -> String {
         let mut hasher = DefaultHasher::new();
 
@@ -96,6 +634,21 @@ impl Pipeline {
             value.status.hash(&mut hasher);
             value.transaction_hash.hash(&mut hasher);
             value.error.hash(&mut hasher);
+            value.edited_at.hash(&mut hasher);
+            for slice in &value.slices {
+                slice.status.hash(&mut hasher);
+                slice.transaction_hash.hash(&mut hasher);
+            }
+            if let Some(rebalance) = &value.rebalance {
+                rebalance.checked_at.hash(&mut hasher);
+                rebalance.rebalanced_at.hash(&mut hasher);
+                for order in &rebalance.orders {
+                    order.status.hash(&mut hasher);
+                }
+            }
+            for condition in &value.conditions {
+                condition.hash_state(&mut hasher);
+            }
         }
 
         self.status.hash(&mut hasher);
@@ -0,0 +1,484 @@
+//! Portfolio rebalancing. `Action::Rebalance` holds target weights over a set
+//! of Solana mints, the wallet's balances of those mints are valued at the
+//! engine's prices and every overweight mint is swapped straight into the
+//! underweight ones. Each swap only spends a balance the wallet already holds,
+//! so the batch goes out at once without waiting on confirmations in between.
+//! The step completes, or with a drift threshold re-arms, once every swap of
+//! the batch confirmed, and then only trades again once a weight drifts that
+//! far off its target.
+
+use std::collections::BTreeMap;
+
+use chrono::Duration;
+use privy::caip2::Caip2;
+
+use crate::engine::{
+    amount::spendable_balance,
+    chain::{self, ChainError},
+    confirm::{check_transaction, Confirmation},
+    events::PipelineEventKind,
+    execute::ExecutionContext,
+    order::SwapOrder,
+    pipeline::{Action, PipelineStep, Rebalance, RebalanceOrder, RebalanceState, Status},
+    Engine, EngineError,
+};
+
+/// Drift triggered rebalances read the wallet's balances at most this often
+pub const DRIFT_CHECK_INTERVAL_SECS: i64 = 60;
+
+/// Swaps worth less than this (USD) are left out of the batch
+const MIN_SWAP_USD: f64 = 1.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RebalanceError {
+    #[error("[Rebalance] No price for {0}")]
+    MissingPrice(String),
+
+    #[error("[Rebalance] {0}")]
+    ChainError(#[from] ChainError),
+
+    #[error("[Rebalance] The wallet holds none of the target mints")]
+    EmptyPortfolio,
+}
+
+/// Outcome of `Engine::rebalance`
+#[derive(Debug, PartialEq)]
+pub enum RebalanceOutcome {
+    /// The weights were checked less than `DRIFT_CHECK_INTERVAL_SECS` ago
+    Waiting,
+    /// The weights are within the drift threshold
+    WithinDrift,
+    /// The batch was sent or some of its swaps confirmed, the rest is still
+    /// in flight
+    InFlight,
+    /// Every swap of the batch confirmed, or filled right away in dry-run mode
+    Rebalanced,
+    Failed(String),
+}
+
+/// Balance of one of the target mints
+#[derive(Debug, Clone)]
+pub struct Holding {
+    pub mint: String,
+    /// base units
+    pub balance: u128,
+    pub decimals: u8,
+    pub price: f64,
+}
+
+impl Holding {
+    fn usd(&self) -> f64 {
+        self.balance as f64 / 10f64.powi(self.decimals as i32) * self.price
+    }
+}
+
+/// Largest distance of a holding's weight from its target, in percentage
+/// points, `None` for an empty portfolio
+pub fn max_drift(holdings: &[Holding], targets: &BTreeMap<String, f64>) -> Option<f64> {
+    let total: f64 = holdings.iter().map(Holding::usd).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    holdings
+        .iter()
+        .map(|holding| {
+            let target = targets.get(&holding.mint).copied().unwrap_or_default();
+            (holding.usd() / total * 100.0 - target).abs()
+        })
+        .max_by(f64::total_cmp)
+}
+
+/// Swaps from the overweight into the underweight holdings that bring every
+/// weight to its target, largest first
+pub fn plan_rebalance(holdings: &[Holding], rebalance: &Rebalance) -> Vec<SwapOrder> {
+    let total: f64 = holdings.iter().map(Holding::usd).sum();
+    // USD above (positive) or below (negative) the target of every holding
+    let excess: Vec<(usize, f64)> = holdings
+        .iter()
+        .enumerate()
+        .map(|(i, holding)| {
+            let target = rebalance
+                .targets
+                .get(&holding.mint)
+                .copied()
+                .unwrap_or_default();
+            (i, holding.usd() - total * target / 100.0)
+        })
+        .collect();
+    let mut sells: Vec<(usize, f64)> = excess
+        .iter()
+        .filter(|(_, usd)| *usd >= MIN_SWAP_USD)
+        .copied()
+        .collect();
+    let mut buys: Vec<(usize, f64)> = excess
+        .iter()
+        .filter(|(_, usd)| -*usd >= MIN_SWAP_USD)
+        .map(|(i, usd)| (*i, -usd))
+        .collect();
+    sells.sort_by(|a, b| b.1.total_cmp(&a.1));
+    buys.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut orders = Vec::new();
+    let (mut s, mut b) = (0, 0);
+    while s < sells.len() && b < buys.len() {
+        let usd = sells[s].1.min(buys[b].1);
+        let seller = &holdings[sells[s].0];
+        let amount = ((usd / seller.price * 10f64.powi(seller.decimals as i32)).floor() as u128)
+            .min(seller.balance);
+        if usd >= MIN_SWAP_USD && amount > 0 {
+            orders.push(SwapOrder {
+                input_token: seller.mint.clone(),
+                output_token: holdings[buys[b].0].mint.clone(),
+                amount: amount.to_string(),
+                from_chain_caip2: Caip2::SOLANA.to_string(),
+                to_chain_caip2: Caip2::SOLANA.to_string(),
+                slippage_bps: rebalance.slippage_bps,
+                max_price_impact_pct: rebalance.max_price_impact_pct,
+                ..Default::default()
+            });
+        }
+        sells[s].1 -= usd;
+        buys[b].1 -= usd;
+        if sells[s].1 < MIN_SWAP_USD {
+            s += 1;
+        }
+        if buys[b].1 < MIN_SWAP_USD {
+            b += 1;
+        }
+    }
+    orders
+}
+
+fn swap_order(record: &RebalanceOrder) -> SwapOrder {
+    SwapOrder {
+        input_token: record.input_token.clone(),
+        output_token: record.output_token.clone(),
+        amount: record.amount.clone(),
+        from_chain_caip2: Caip2::SOLANA.to_string(),
+        to_chain_caip2: Caip2::SOLANA.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Outcome of a batch once none of its swaps are polled anymore
+fn settle(state: &RebalanceState, failure: Option<String>) -> RebalanceOutcome {
+    match failure {
+        Some(error) => RebalanceOutcome::Failed(error),
+        None if state
+            .orders
+            .iter()
+            .any(|order| matches!(order.status, Status::Confirming)) =>
+        {
+            RebalanceOutcome::InFlight
+        }
+        None => RebalanceOutcome::Rebalanced,
+    }
+}
+
+impl Engine {
+    /// Polls the swaps of the batch in flight
+    async fn confirm_rebalance(
+        &self,
+        state: &mut RebalanceState,
+        ctx: &ExecutionContext<'_>,
+    ) -> Result<RebalanceOutcome, EngineError> {
+        let mut changed = false;
+        let mut failure = None;
+        for record in state
+            .orders
+            .iter_mut()
+            .filter(|record| matches!(record.status, Status::Confirming))
+        {
+            match check_transaction(
+                &swap_order(record),
+                record.transaction_hash.as_deref().unwrap_or_default(),
+                record.submitted_at,
+                ctx.wallet_address,
+                ctx.pubkey,
+            )
+            .await?
+            {
+                Confirmation::Pending => {}
+                Confirmation::Confirmed(fill) => {
+                    record.status = Status::Completed;
+                    record.fill = Some(fill);
+                    record.reserved_usd = 0;
+                    changed = true;
+                }
+                Confirmation::Failed(reason) => {
+                    self.release_notional(ctx.user_id, std::mem::take(&mut record.reserved_usd))
+                        .await;
+                    record.status = Status::Failed;
+                    record.error = Some(reason.clone());
+                    changed = true;
+                    failure.get_or_insert(reason);
+                }
+            }
+        }
+        Ok(match settle(state, failure) {
+            RebalanceOutcome::InFlight if !changed => RebalanceOutcome::Waiting,
+            outcome => outcome,
+        })
+    }
+
+    async fn holdings(
+        &self,
+        rebalance: &Rebalance,
+        owner: &str,
+    ) -> Result<Vec<Holding>, RebalanceError> {
+        let mut holdings = Vec::with_capacity(rebalance.targets.len());
+        for mint in rebalance.targets.keys() {
+            let balance = spendable_balance(Caip2::SOLANA, owner, mint).await?;
+            let decimals = chain::token_decimals(Caip2::SOLANA, mint).await?;
+            let price = self
+                .token_price(mint)
+                .await
+                .ok_or_else(|| RebalanceError::MissingPrice(mint.clone()))?;
+            holdings.push(Holding {
+                mint: mint.clone(),
+                balance,
+                decimals,
+                price,
+            });
+        }
+        Ok(holdings)
+    }
+
+    /// Checks the weights of the step's rebalance and sends the swaps when
+    /// they drifted past the threshold, or right away without one, then
+    /// polls the swaps until all of them confirmed. Errors are transient,
+    /// the step should be retried on the next evaluation.
+    pub async fn rebalance(
+        &self,
+        step: &mut PipelineStep,
+        ctx: &ExecutionContext<'_>,
+    ) -> Result<RebalanceOutcome, EngineError> {
+        let Action::Rebalance(rebalance) = &step.action else {
+            return Ok(RebalanceOutcome::Failed(
+                "the step is not a rebalance".to_string(),
+            ));
+        };
+        let rebalance = rebalance.clone();
+        let Some(owner) = ctx.pubkey else {
+            return Ok(RebalanceOutcome::Failed(
+                EngineError::SolanaWalletNotAvailable.to_string(),
+            ));
+        };
+        let now = self.clock.now();
+        // the weights aren't checked again before the last batch settled
+        if let Some(state) = step.rebalance.as_mut().filter(|state| {
+            state
+                .orders
+                .iter()
+                .any(|record| matches!(record.status, Status::Confirming))
+        }) {
+            return self.confirm_rebalance(state, ctx).await;
+        }
+        if rebalance.drift_pct.is_some()
+            && step.rebalance.as_ref().is_some_and(|state| {
+                now - state.checked_at < Duration::seconds(DRIFT_CHECK_INTERVAL_SECS)
+            })
+        {
+            return Ok(RebalanceOutcome::Waiting);
+        }
+
+        let holdings = self
+            .holdings(&rebalance, owner)
+            .await
+            .map_err(EngineError::RebalanceError)?;
+        let Some(drift) = max_drift(&holdings, &rebalance.targets) else {
+            return Ok(RebalanceOutcome::Failed(
+                RebalanceError::EmptyPortfolio.to_string(),
+            ));
+        };
+        let state = step.rebalance.get_or_insert_with(|| RebalanceState {
+            checked_at: now,
+            drift_pct: drift,
+            rebalanced_at: None,
+            orders: Vec::new(),
+        });
+        state.checked_at = now;
+        state.drift_pct = drift;
+        if rebalance
+            .drift_pct
+            .is_some_and(|threshold| drift < threshold)
+        {
+            return Ok(RebalanceOutcome::WithinDrift);
+        }
+
+        let orders = plan_rebalance(&holdings, &rebalance);
+        tracing::info!(step_id = %step.id, drift, swaps = orders.len(), "Rebalancing");
+        self.publish_event(
+            ctx.user_id,
+            ctx.pipeline_id,
+            step.id,
+            PipelineEventKind::Triggered,
+        )
+        .await;
+        self.publish_event(
+            ctx.user_id,
+            ctx.pipeline_id,
+            step.id,
+            PipelineEventKind::Executing,
+        )
+        .await;
+
+        let mut sent = Vec::with_capacity(orders.len());
+        let mut failure = None;
+        for order in orders {
+            let mut record = RebalanceOrder {
+                input_token: order.input_token.clone(),
+                output_token: order.output_token.clone(),
+                amount: order.amount.clone(),
+                status: Status::Pending,
+                transaction_hash: None,
+                submitted_at: None,
+                reserved_usd: 0,
+                simulated_fill: None,
+                fill: None,
+                error: None,
+            };
+            match self
+                .execute_order(
+                    &order,
+                    ctx.user_id,
+                    ctx.wallet_address.map(str::to_string),
+                    Some(owner.to_string()),
+                    ctx.dry_run,
+                )
+                .await
+            {
+                Ok(executed) => {
+                    self.publish_event(
+                        ctx.user_id,
+                        ctx.pipeline_id,
+                        step.id,
+                        PipelineEventKind::TransactionSubmitted {
+                            transaction_hash: executed.transaction_hash.clone(),
+                            dry_run: executed.simulated_fill.is_some(),
+                        },
+                    )
+                    .await;
+                    record.status = match executed.simulated_fill {
+                        Some(_) => Status::Completed,
+                        None => Status::Confirming,
+                    };
+                    record.transaction_hash = Some(executed.transaction_hash);
+                    record.submitted_at = Some(now);
+                    record.reserved_usd = executed.reserved_usd;
+                    record.simulated_fill = executed.simulated_fill;
+                    sent.push(record);
+                }
+                Err(e) => {
+                    // the rest of the batch is dropped, the swaps that went
+                    // out stay on the step
+                    record.status = Status::Failed;
+                    record.error = Some(e.to_string());
+                    sent.push(record);
+                    failure = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+
+        state.orders = sent;
+        state.rebalanced_at = Some(now);
+        Ok(settle(state, failure))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{chain::SOLANA_NATIVE_MINTS, constants::USDC_MINT};
+
+    fn rebalance(targets: &[(&str, f64)], drift_pct: Option<f64>) -> Rebalance {
+        Rebalance {
+            targets: targets
+                .iter()
+                .map(|(mint, weight)| (mint.to_string(), *weight))
+                .collect(),
+            drift_pct,
+            slippage_bps: Some(100),
+            max_price_impact_pct: None,
+        }
+    }
+
+    #[test]
+    fn test_plan_rebalance() {
+        let sol = SOLANA_NATIVE_MINTS[0];
+        let bonk = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+        let holding = |mint: &str, balance: u128, decimals: u8, price: f64| Holding {
+            mint: mint.to_string(),
+            balance,
+            decimals,
+            price,
+        };
+        // $800 of SOL, $200 of USDC and nothing of BONK
+        let holdings = vec![
+            holding(sol, 4_000_000_000, 9, 200.0),
+            holding(USDC_MINT, 200_000_000, 6, 1.0),
+            holding(bonk, 0, 5, 0.00002),
+        ];
+        let targets = rebalance(&[(sol, 50.0), (USDC_MINT, 30.0), (bonk, 20.0)], None);
+        assert_eq!(max_drift(&holdings, &targets.targets), Some(30.0));
+
+        let orders = plan_rebalance(&holdings, &targets);
+        let swaps: Vec<(&str, &str, &str)> = orders
+            .iter()
+            .map(|o| {
+                (
+                    o.input_token.as_str(),
+                    o.output_token.as_str(),
+                    o.amount.as_str(),
+                )
+            })
+            .collect();
+        // $200 of SOL into BONK, then $100 of SOL into USDC
+        assert_eq!(
+            swaps,
+            [(sol, bonk, "1000000000"), (sol, USDC_MINT, "500000000")]
+        );
+        assert_eq!(orders[0].slippage_bps, Some(100));
+
+        // at target, nothing to trade
+        let balanced = vec![
+            holding(sol, 2_500_000_000, 9, 200.0),
+            holding(USDC_MINT, 500_000_000, 6, 1.0),
+        ];
+        let targets = rebalance(&[(sol, 50.0), (USDC_MINT, 50.0)], Some(5.0));
+        assert!(max_drift(&balanced, &targets.targets).unwrap() < 1e-9);
+        assert!(plan_rebalance(&balanced, &targets).is_empty());
+        assert_eq!(max_drift(&[], &targets.targets), None);
+    }
+
+    #[test]
+    fn test_rebalance_settles_once_swaps_confirm() {
+        let record = |status: Status| RebalanceOrder {
+            input_token: SOLANA_NATIVE_MINTS[0].to_string(),
+            output_token: USDC_MINT.to_string(),
+            amount: "1000".to_string(),
+            status,
+            transaction_hash: Some("sig".to_string()),
+            submitted_at: None,
+            reserved_usd: 0,
+            simulated_fill: None,
+            fill: None,
+            error: None,
+        };
+        let mut state = RebalanceState {
+            checked_at: chrono::Utc::now(),
+            drift_pct: 10.0,
+            rebalanced_at: None,
+            orders: vec![record(Status::Completed), record(Status::Confirming)],
+        };
+        assert_eq!(settle(&state, None), RebalanceOutcome::InFlight);
+        assert_eq!(
+            settle(&state, Some("failed".to_string())),
+            RebalanceOutcome::Failed("failed".to_string())
+        );
+
+        state.orders[1].status = Status::Completed;
+        assert_eq!(settle(&state, None), RebalanceOutcome::Rebalanced);
+    }
+}
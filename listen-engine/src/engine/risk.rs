@@ -0,0 +1,473 @@
+//! Per-user risk limits, checked by `Engine::execute_order` before an order
+//! is signed: the USD notional of a single order, the USD notional sent per
+//! day and allow/deny lists of mints. The limits live next to the
+//! notification rate limits (see `redis::rate_limits`) and are managed
+//! through the internal admin API. Users have no notional limits until one
+//! is set for them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{
+    chain::{self, ChainError},
+    order::SwapOrder,
+    Engine, EngineError,
+};
+use crate::redis::rate_limits::{MintList, RateLimit, RateLimitType, UNLIMITED};
+use crate::store::StoreError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RiskLimitError {
+    #[error("[RiskLimit] {0} is on the user's deny list")]
+    MintDenied(String),
+
+    #[error("[RiskLimit] {0} is not on the user's allow list")]
+    MintNotAllowed(String),
+
+    #[error("[RiskLimit] No price for {0}, the order's notional can't be checked")]
+    UnknownNotional(String),
+
+    #[error("[RiskLimit] Order notional ${usd:.2} is above the ${limit} per order limit")]
+    OrderNotional { usd: f64, limit: u32 },
+
+    #[error(
+        "[RiskLimit] Order notional ${usd:.2} is above the ${remaining} left of the ${limit} daily limit"
+    )]
+    DailyNotional {
+        usd: f64,
+        remaining: u32,
+        limit: u32,
+    },
+
+    #[error("[RiskLimit] Invalid limits: {0}")]
+    InvalidLimits(String),
+
+    #[error("[RiskLimit] {0}")]
+    ChainError(#[from] ChainError),
+
+    #[error("[RiskLimit] {0}")]
+    StoreError(#[from] StoreError),
+}
+
+impl From<RiskLimitError> for EngineError {
+    fn from(err: RiskLimitError) -> Self {
+        EngineError::RiskLimitError(err)
+    }
+}
+
+/// `None` for the notional limits that aren't set
+#[derive(Debug, Serialize)]
+pub struct RiskLimits {
+    pub max_order_usd: Option<u32>,
+    pub daily_notional_usd: Option<RateLimit>,
+    /// orders may only trade these mints, any mint when empty
+    pub allowed_mints: Vec<String>,
+    pub denied_mints: Vec<String>,
+}
+
+/// Changes to a user's limits, omitted fields are left as they are
+#[derive(Debug, Default, Deserialize)]
+pub struct RiskLimitsUpdate {
+    #[serde(default)]
+    pub max_order_usd: Option<u32>,
+    #[serde(default)]
+    pub max_daily_usd: Option<u32>,
+    #[serde(default)]
+    pub allowed_mints: Option<Vec<String>>,
+    #[serde(default)]
+    pub denied_mints: Option<Vec<String>>,
+}
+
+/// EVM addresses are compared case insensitively
+fn same_token(a: &str, b: &str) -> bool {
+    match a.starts_with("0x") && b.starts_with("0x") {
+        true => a.eq_ignore_ascii_case(b),
+        false => a == b,
+    }
+}
+
+fn validate_mints(mints: &[String]) -> Result<(), RiskLimitError> {
+    if mints.iter().any(|mint| mint.trim().is_empty()) {
+        return Err(RiskLimitError::InvalidLimits(
+            "mints can't be empty".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl Engine {
+    pub async fn get_risk_limits(&self, user_id: &str) -> Result<RiskLimits, EngineError> {
+        let store_error = |e| EngineError::RiskLimitError(RiskLimitError::StoreError(e));
+        let max_order_usd = self
+            .store
+            .get_rate_limit(user_id, &RateLimitType::OrderNotionalUsd)
+            .await
+            .map_err(store_error)?
+            .limit;
+        let daily_notional_usd = self
+            .store
+            .get_rate_limit(user_id, &RateLimitType::DailyNotionalUsd)
+            .await
+            .map_err(store_error)?;
+        let max_order_usd = Some(max_order_usd).filter(|limit| *limit != UNLIMITED);
+        let daily_notional_usd = Some(daily_notional_usd).filter(|daily| daily.limit != UNLIMITED);
+        let allowed_mints = self
+            .store
+            .get_mint_list(user_id, MintList::Allow)
+            .await
+            .map_err(store_error)?;
+        let denied_mints = self
+            .store
+            .get_mint_list(user_id, MintList::Deny)
+            .await
+            .map_err(store_error)?;
+
+        Ok(RiskLimits {
+            max_order_usd,
+            daily_notional_usd,
+            allowed_mints,
+            denied_mints,
+        })
+    }
+
+    pub async fn set_risk_limits(
+        &self,
+        user_id: &str,
+        update: &RiskLimitsUpdate,
+    ) -> Result<RiskLimits, EngineError> {
+        for mints in [&update.allowed_mints, &update.denied_mints]
+            .into_iter()
+            .flatten()
+        {
+            validate_mints(mints)?;
+        }
+
+        let store_error = |e| EngineError::RiskLimitError(RiskLimitError::StoreError(e));
+        for (limit_type, limit) in [
+            (RateLimitType::OrderNotionalUsd, update.max_order_usd),
+            (RateLimitType::DailyNotionalUsd, update.max_daily_usd),
+        ] {
+            if let Some(limit) = limit {
+                self.store
+                    .set_user_limit(user_id, &limit_type, limit)
+                    .await
+                    .map_err(store_error)?;
+            }
+        }
+        for (list, mints) in [
+            (MintList::Allow, &update.allowed_mints),
+            (MintList::Deny, &update.denied_mints),
+        ] {
+            if let Some(mints) = mints {
+                self.store
+                    .set_mint_list(user_id, list, mints)
+                    .await
+                    .map_err(store_error)?;
+            }
+        }
+        tracing::info!(%user_id, ?update, "Updated risk limits");
+
+        self.get_risk_limits(user_id).await
+    }
+
+    /// USD value of the order's input amount. Orders routed through LiFi
+    /// (EVM and cross-chain) are valued by their quote's `fromAmountUSD`,
+    /// Solana swaps and quotes without an estimate by the engine's price of
+    /// the input, which only exists for Solana mints.
+    async fn order_notional_usd(
+        &self,
+        order: &SwapOrder,
+        quoted_usd: Option<f64>,
+    ) -> Result<f64, RiskLimitError> {
+        let unknown = || RiskLimitError::UnknownNotional(order.input_token.clone());
+        if let Some(usd) = quoted_usd.filter(|usd| usd.is_finite() && *usd >= 0.0) {
+            return Ok(usd);
+        }
+        if !order.is_solana() {
+            return Err(unknown());
+        }
+        let amount = order.amount.parse::<u128>().map_err(|_| unknown())?;
+        let price = self
+            .token_price(&order.input_token)
+            .await
+            .ok_or_else(unknown)?;
+        let decimals = chain::token_decimals(&order.from_chain_caip2, &order.input_token).await?;
+        Ok(amount as f64 / 10f64.powi(decimals as i32) * price)
+    }
+
+    /// Fails if the order is past any of the user's limits, returns the
+    /// order's notional to reserve before it is sent. `quoted_usd` is the
+    /// input's value per the order's quote, if it has one. Orders are only
+    /// valued if a notional limit is set, those that can't be valued are then
+    /// refused since the limit can't be enforced.
+    pub(crate) async fn check_risk_limits(
+        &self,
+        order: &SwapOrder,
+        user_id: &str,
+        quoted_usd: Option<f64>,
+    ) -> Result<f64, RiskLimitError> {
+        let tokens = [&order.input_token, &order.output_token];
+        let denied = self.store.get_mint_list(user_id, MintList::Deny).await?;
+        if let Some(token) = tokens
+            .iter()
+            .find(|token| denied.iter().any(|mint| same_token(mint, token)))
+        {
+            return Err(RiskLimitError::MintDenied(token.to_string()));
+        }
+        let allowed = self.store.get_mint_list(user_id, MintList::Allow).await?;
+        if !allowed.is_empty() {
+            if let Some(token) = tokens
+                .iter()
+                .find(|token| !allowed.iter().any(|mint| same_token(mint, token)))
+            {
+                return Err(RiskLimitError::MintNotAllowed(token.to_string()));
+            }
+        }
+
+        let per_order = self
+            .store
+            .get_rate_limit(user_id, &RateLimitType::OrderNotionalUsd)
+            .await?;
+        let daily = self
+            .store
+            .get_rate_limit(user_id, &RateLimitType::DailyNotionalUsd)
+            .await?;
+        if per_order.limit == UNLIMITED && daily.limit == UNLIMITED {
+            return Ok(0.0);
+        }
+
+        let usd = self.order_notional_usd(order, quoted_usd).await?;
+        if usd > per_order.limit as f64 {
+            return Err(RiskLimitError::OrderNotional {
+                usd,
+                limit: per_order.limit,
+            });
+        }
+        if usd > daily.remaining as f64 {
+            return Err(RiskLimitError::DailyNotional {
+                usd,
+                remaining: daily.remaining,
+                limit: daily.limit,
+            });
+        }
+        Ok(usd)
+    }
+
+    /// Counts the order towards the user's daily notional before it is
+    /// signed, fails if other orders took the rest of the limit since it was
+    /// checked. Returns the reserved amount to release if the send fails.
+    pub(crate) async fn reserve_notional(
+        &self,
+        user_id: &str,
+        usd: f64,
+    ) -> Result<u32, RiskLimitError> {
+        let amount = usd.ceil().min(u32::MAX as f64) as u32;
+        if amount == 0 {
+            return Ok(0);
+        }
+        match self
+            .store
+            .reserve_rate_limit(user_id, &RateLimitType::DailyNotionalUsd, amount)
+            .await?
+        {
+            Some(_) => Ok(amount),
+            None => {
+                let daily = self
+                    .store
+                    .get_rate_limit(user_id, &RateLimitType::DailyNotionalUsd)
+                    .await?;
+                Err(RiskLimitError::DailyNotional {
+                    usd,
+                    remaining: daily.remaining,
+                    limit: daily.limit,
+                })
+            }
+        }
+    }
+
+    /// Takes back the reservation of an order that wasn't sent, or whose
+    /// transaction failed to confirm
+    pub(crate) async fn release_notional(&self, user_id: &str, amount: u32) {
+        if amount == 0 {
+            return;
+        }
+        if let Err(e) = self
+            .store
+            .release_rate_limit(user_id, &RateLimitType::DailyNotionalUsd, amount)
+            .await
+        {
+            tracing::error!(%user_id, amount, error = %e, "Failed to release order notional");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use privy::caip2::Caip2;
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::engine::{
+        chain::SOLANA_NATIVE_MINTS, constants::TEST_ADDRESS_SOL, market::MarketSnapshot,
+        testing::engine,
+    };
+
+    #[tokio::test]
+    async fn test_risk_limits_block_orders_before_signing() {
+        let (engine, _) = engine();
+        let sol = SOLANA_NATIVE_MINTS[0];
+        let meme = Pubkey::new_unique().to_string();
+        let denied = Pubkey::new_unique().to_string();
+        engine.price_cache.write().await.insert(
+            sol.to_string(),
+            MarketSnapshot {
+                price: 100.0,
+                ..Default::default()
+            },
+        );
+        let order = |output_token: &str, lamports: u64| SwapOrder {
+            input_token: sol.to_string(),
+            output_token: output_token.to_string(),
+            amount: lamports.to_string(),
+            from_chain_caip2: Caip2::SOLANA.to_string(),
+            to_chain_caip2: Caip2::SOLANA.to_string(),
+            ..Default::default()
+        };
+
+        let limits = engine
+            .set_risk_limits(
+                "user",
+                &RiskLimitsUpdate {
+                    max_order_usd: Some(100),
+                    max_daily_usd: Some(150),
+                    denied_mints: Some(vec![denied.clone()]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(limits.max_order_usd, Some(100));
+        assert_eq!(limits.daily_notional_usd.unwrap().remaining, 150);
+
+        // $200 of SOL, rejected before any transaction is built
+        let result = engine
+            .execute_order(
+                &order(&meme, 2_000_000_000),
+                "user",
+                None,
+                Some(TEST_ADDRESS_SOL.to_string()),
+                false,
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(EngineError::RiskLimitError(RiskLimitError::OrderNotional {
+                limit: 100,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            engine
+                .check_risk_limits(&order(&denied, 1_000_000), "user", None)
+                .await,
+            Err(RiskLimitError::MintDenied(mint)) if mint == denied
+        ));
+
+        let usd = engine
+            .check_risk_limits(&order(&meme, 900_000_000), "user", None)
+            .await
+            .unwrap();
+        assert_eq!(usd, 90.0);
+        let reserved = engine.reserve_notional("user", usd).await.unwrap();
+        assert_eq!(reserved, 90);
+        assert!(matches!(
+            engine
+                .check_risk_limits(&order(&meme, 900_000_000), "user", None)
+                .await,
+            Err(RiskLimitError::DailyNotional { remaining: 60, .. })
+        ));
+        // an evaluation that checked before the reservation can't reserve
+        // past the limit either
+        assert!(matches!(
+            engine.reserve_notional("user", usd).await,
+            Err(RiskLimitError::DailyNotional { remaining: 60, .. })
+        ));
+        // released when the send fails
+        engine.release_notional("user", reserved).await;
+        assert_eq!(engine.reserve_notional("user", usd).await.unwrap(), 90);
+        // other users have no limits, their orders aren't even valued
+        let limits = engine.get_risk_limits("other").await.unwrap();
+        assert!(limits.max_order_usd.is_none() && limits.daily_notional_usd.is_none());
+        let unpriced = SwapOrder {
+            input_token: Pubkey::new_unique().to_string(),
+            ..order(&meme, 900_000_000_000)
+        };
+        assert_eq!(
+            engine
+                .check_risk_limits(&unpriced, "other", None)
+                .await
+                .unwrap(),
+            0.0
+        );
+        assert!(matches!(
+            engine.check_risk_limits(&unpriced, "user", None).await,
+            Err(RiskLimitError::UnknownNotional(_))
+        ));
+
+        engine
+            .set_risk_limits(
+                "user",
+                &RiskLimitsUpdate {
+                    allowed_mints: Some(vec![sol.to_string()]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine
+                .check_risk_limits(&order(&meme, 1_000_000), "user", None)
+                .await,
+            Err(RiskLimitError::MintNotAllowed(mint)) if mint == meme
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_risk_limits_value_evm_orders_by_their_quote() {
+        let (engine, _) = engine();
+        engine
+            .set_risk_limits(
+                "user",
+                &RiskLimitsUpdate {
+                    max_order_usd: Some(100),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        // USDC on Arbitrum into ETH
+        let order = SwapOrder {
+            input_token: "0xaf88d065e77c8cC2239327C5EDb3A432268e5831".to_string(),
+            output_token: "0x0000000000000000000000000000000000000000".to_string(),
+            amount: "50000000".to_string(),
+            from_chain_caip2: "eip155:42161".to_string(),
+            to_chain_caip2: "eip155:42161".to_string(),
+            ..Default::default()
+        };
+
+        let usd = engine
+            .check_risk_limits(&order, "user", Some(50.0))
+            .await
+            .unwrap();
+        assert_eq!(usd, 50.0);
+        assert!(matches!(
+            engine.check_risk_limits(&order, "user", Some(500.0)).await,
+            Err(RiskLimitError::OrderNotional { limit: 100, .. })
+        ));
+        // the engine has no prices for EVM tokens, without a quote estimate
+        // the order is refused
+        assert!(matches!(
+            engine.check_risk_limits(&order, "user", None).await,
+            Err(RiskLimitError::UnknownNotional(_))
+        ));
+    }
+}
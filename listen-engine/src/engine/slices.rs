@@ -0,0 +1,387 @@
+//! DCA and TWAP orders. Once the step's conditions are met the order amount
+//! is resolved and split into `OrderSlice`s, which are executed one at a time
+//! as they come due. Sliced steps are indexed under "TIME", so the scheduler
+//! tick picks up due slices even when no price moves. The step completes once
+//! every slice has filled, a slice that fails fails the step.
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::engine::{
+    amount::resolve_order_amount,
+    confirm::{check_transaction, Confirmation},
+    events::PipelineEventKind,
+    execute::ExecutionContext,
+    order::SwapOrder,
+    pipeline::{OrderSlice, PipelineStep, Status},
+    Engine, EngineError,
+};
+
+/// Upper bound on `count`/`slices`, every slice is a transaction
+pub const MAX_SLICES: u32 = 500;
+
+/// Upper bound on the time a sliced order takes, 90 days
+pub const MAX_SLICED_DURATION_SECS: u64 = 90 * 24 * 60 * 60;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SliceError {
+    #[error("[Slices] Invalid amount: {0}")]
+    InvalidAmount(String),
+
+    #[error("[Slices] Amount {amount} is too small for {count} slices")]
+    AmountTooSmall { amount: String, count: u32 },
+
+    #[error("[Slices] {count} slices {interval}s apart run past the supported dates")]
+    OutOfRange { count: u32, interval: u64 },
+}
+
+/// Outcome of `Engine::advance_slices`
+#[derive(Debug, PartialEq)]
+pub enum SliceProgress {
+    /// Nothing changed, the next slice isn't due or one is still in flight
+    Waiting,
+    /// The slice in flight confirmed
+    Progressed,
+    /// A slice was sent, or filled right away in dry-run mode
+    Executed,
+    /// Every slice has filled
+    Filled,
+    Failed(String),
+}
+
+/// Splits `amount` (base units) into `count` slices, `interval` seconds
+/// apart starting at `start`. The last slice takes the rounding remainder.
+pub fn plan_slices(
+    amount: &str,
+    count: u32,
+    interval: u64,
+    start: DateTime<Utc>,
+) -> Result<Vec<OrderSlice>, SliceError> {
+    let total = amount
+        .parse::<u128>()
+        .map_err(|_| SliceError::InvalidAmount(amount.to_string()))?;
+    let count = count.max(1);
+    let base = total / count as u128;
+    if base == 0 {
+        return Err(SliceError::AmountTooSmall {
+            amount: amount.to_string(),
+            count,
+        });
+    }
+
+    (0..count)
+        .map(|i| {
+            let amount = match i + 1 == count {
+                true => base + total % count as u128,
+                false => base,
+            };
+            let due_at = interval
+                .checked_mul(i as u64)
+                .and_then(|offset| i64::try_from(offset).ok())
+                .and_then(TimeDelta::try_seconds)
+                .and_then(|offset| start.checked_add_signed(offset))
+                .ok_or(SliceError::OutOfRange { count, interval })?;
+            Ok(OrderSlice {
+                amount: amount.to_string(),
+                due_at,
+                status: Status::Pending,
+                transaction_hash: None,
+                submitted_at: None,
+                reserved_usd: 0,
+                simulated_fill: None,
+                fill: None,
+                error: None,
+            })
+        })
+        .collect()
+}
+
+/// Base units of the slices that haven't filled yet
+fn remaining_amount(slices: &[OrderSlice]) -> String {
+    slices
+        .iter()
+        .filter(|slice| !matches!(slice.status, Status::Completed))
+        .filter_map(|slice| slice.amount.parse::<u128>().ok())
+        .sum::<u128>()
+        .to_string()
+}
+
+/// Fails the slice and cancels the ones that haven't been sent
+fn fail_slices(step: &mut PipelineStep, index: usize, error: String) -> SliceProgress {
+    step.slices[index].status = Status::Failed;
+    step.slices[index].error = Some(error.clone());
+    for slice in step.slices.iter_mut() {
+        if matches!(slice.status, Status::Pending) {
+            slice.status = Status::Cancelled;
+        }
+    }
+    SliceProgress::Failed(error)
+}
+
+impl Engine {
+    /// Moves a sliced order along: plans the slices on the first call, then
+    /// polls the slice in flight or sends the next one that is due. Errors
+    /// are transient, the step should be retried on the next evaluation.
+    pub async fn advance_slices(
+        &self,
+        step: &mut PipelineStep,
+        parents: &[PipelineStep],
+        ctx: &ExecutionContext<'_>,
+    ) -> Result<SliceProgress, EngineError> {
+        let (Some(order), Some((count, interval))) =
+            (step.action.order().cloned(), step.action.slicing())
+        else {
+            return Ok(SliceProgress::Failed(
+                "the step is not a sliced order".to_string(),
+            ));
+        };
+        let now = self.clock.now();
+        let mut progress = SliceProgress::Waiting;
+
+        if !step.is_slicing() {
+            let resolved = match self.mirror_order(&order, &step.conditions).await {
+                Ok(mirrored) => {
+                    resolve_order_amount(&mirrored, ctx.wallet_address, ctx.pubkey, parents).await
+                }
+                Err(e) => Err(e),
+            };
+            let resolved = match resolved {
+                // the parent's transaction has not confirmed yet
+                Ok(None) => return Ok(SliceProgress::Waiting),
+                Ok(Some(resolved)) => resolved,
+                Err(e) => return Ok(SliceProgress::Failed(e.to_string())),
+            };
+            step.slices = match plan_slices(&resolved.amount, count, interval, now) {
+                Ok(slices) => slices,
+                Err(e) => return Ok(SliceProgress::Failed(e.to_string())),
+            };
+            step.remaining_amount = Some(resolved.amount.clone());
+            tracing::info!(step_id = %step.id, amount = %resolved.amount, count, interval, "Planned order slices");
+            self.publish_event(
+                ctx.user_id,
+                ctx.pipeline_id,
+                step.id,
+                PipelineEventKind::Triggered,
+            )
+            .await;
+            progress = SliceProgress::Progressed;
+        }
+
+        if let Some(index) = step
+            .slices
+            .iter()
+            .position(|slice| matches!(slice.status, Status::Confirming))
+        {
+            let slice = &step.slices[index];
+            match check_transaction(
+                &order,
+                slice.transaction_hash.as_deref().unwrap_or_default(),
+                slice.submitted_at,
+                ctx.wallet_address,
+                ctx.pubkey,
+            )
+            .await?
+            {
+                Confirmation::Pending => return Ok(progress),
+                Confirmation::Confirmed(fill) => {
+                    tracing::info!(step_id = %step.id, index, ?fill, "Order slice confirmed");
+                    step.slices[index].fill = Some(fill);
+                    step.slices[index].reserved_usd = 0;
+                    step.slices[index].status = Status::Completed;
+                    step.remaining_amount = Some(remaining_amount(&step.slices));
+                    progress = SliceProgress::Progressed;
+                }
+                Confirmation::Failed(reason) => {
+                    tracing::warn!(step_id = %step.id, index, %reason, "Order slice did not confirm");
+                    let reserved = std::mem::take(&mut step.slices[index].reserved_usd);
+                    self.release_notional(ctx.user_id, reserved).await;
+                    return Ok(fail_slices(step, index, reason));
+                }
+            }
+        }
+
+        if step
+            .slices
+            .iter()
+            .all(|slice| matches!(slice.status, Status::Completed))
+        {
+            return Ok(SliceProgress::Filled);
+        }
+
+        // one slice at a time, overdue slices catch up on the following ticks
+        let Some(index) = step
+            .slices
+            .iter()
+            .position(|slice| matches!(slice.status, Status::Pending) && slice.due_at <= now)
+        else {
+            return Ok(progress);
+        };
+
+        self.publish_event(
+            ctx.user_id,
+            ctx.pipeline_id,
+            step.id,
+            PipelineEventKind::Executing,
+        )
+        .await;
+        // copied trades keep the leader's token for every slice
+        let slice_order = match self
+            .mirror_order(
+                &SwapOrder {
+                    amount: step.slices[index].amount.clone(),
+                    ..order
+                },
+                &step.conditions,
+            )
+            .await
+        {
+            Ok(slice_order) => slice_order,
+            Err(e) => return Ok(fail_slices(step, index, e.to_string())),
+        };
+        match self
+            .execute_order(
+                &slice_order,
+                ctx.user_id,
+                ctx.wallet_address.map(str::to_string),
+                ctx.pubkey.map(str::to_string),
+                ctx.dry_run,
+            )
+            .await
+        {
+            Ok(executed) => {
+                let slice = &mut step.slices[index];
+                slice.transaction_hash = Some(executed.transaction_hash.clone());
+                slice.reserved_usd = executed.reserved_usd;
+                if executed.simulated_fill.is_none() {
+                    slice.status = Status::Confirming;
+                    slice.submitted_at = Some(now);
+                } else {
+                    slice.status = Status::Completed;
+                }
+                slice.simulated_fill = executed.simulated_fill.clone();
+                // the latest slice, for the step's transaction events
+                step.transaction_hash = Some(executed.transaction_hash);
+                step.simulated_fill = executed.simulated_fill;
+                step.remaining_amount = Some(remaining_amount(&step.slices));
+                Ok(SliceProgress::Executed)
+            }
+            Err(e) => Ok(fail_slices(step, index, e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use chrono::Duration;
+    use serde_json::json;
+
+    use super::*;
+    use crate::engine::{
+        api::{PipelineParams, WirePipeline},
+        clock::Clock,
+        constants::TEST_ADDRESS_SOL,
+        pipeline::Pipeline,
+        testing::engine,
+    };
+
+    #[test]
+    fn test_plan_slices() {
+        let start = Utc::now();
+        let slices = plan_slices("1000", 3, 60, start).unwrap();
+        let amounts: Vec<&str> = slices.iter().map(|s| s.amount.as_str()).collect();
+        assert_eq!(amounts, ["333", "333", "334"]);
+        assert_eq!(slices[2].due_at, start + Duration::seconds(120));
+        assert_eq!(remaining_amount(&slices), "1000");
+
+        assert!(matches!(
+            plan_slices("2", 3, 60, start),
+            Err(SliceError::AmountTooSmall { .. })
+        ));
+        assert!(matches!(
+            plan_slices("50%", 3, 60, start),
+            Err(SliceError::InvalidAmount(_))
+        ));
+        assert!(matches!(
+            plan_slices("1000", 3, u64::MAX / 2, start),
+            Err(SliceError::OutOfRange { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_twap_fills_slices_on_schedule() {
+        let (mut engine, store) = engine();
+        let start = Utc::now();
+        // fills at the cached prices without building transactions
+        engine.backtest = true;
+        engine.clock = Clock::replay(start);
+
+        let wire: WirePipeline = serde_json::from_value(json!({
+            "steps": [{
+                "action": {
+                    "type": "Twap",
+                    "input_token": "So11111111111111111111111111111111111111112",
+                    "output_token": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+                    "amount": "900",
+                    "duration": 180,
+                    "slices": 3
+                }
+            }]
+        }))
+        .unwrap();
+        let mut pipeline = Pipeline::try_from((
+            wire,
+            PipelineParams {
+                user_id: "user".to_string(),
+                wallet_address: None,
+                pubkey: Some(TEST_ADDRESS_SOL.to_string()),
+            },
+        ))
+        .unwrap();
+        engine.add_pipeline(&pipeline).await.unwrap();
+        let key = format!("user:{}", pipeline.id);
+        assert!(engine.active_pipelines.get("TIME").unwrap().contains(&key));
+
+        let step_id = pipeline.current_steps[0];
+        assert!(!engine.evaluate_pipeline(&mut pipeline).await.unwrap());
+        let step = &pipeline.steps[&step_id];
+        assert!(matches!(step.status, Status::Pending));
+        assert_eq!(step.slices.len(), 3);
+        assert!(matches!(step.slices[0].status, Status::Completed));
+        assert!(step.slices[0].simulated_fill.is_some());
+        assert_eq!(step.remaining_amount.as_deref(), Some("600"));
+
+        // the next slice isn't due yet
+        engine.evaluate_pipeline(&mut pipeline).await.unwrap();
+        assert!(matches!(
+            pipeline.steps[&step_id].slices[1].status,
+            Status::Pending
+        ));
+
+        // overdue slices go out one per evaluation
+        engine.clock.set(start + Duration::seconds(200));
+        engine.evaluate_pipeline(&mut pipeline).await.unwrap();
+        assert_eq!(
+            pipeline.steps[&step_id].remaining_amount.as_deref(),
+            Some("300")
+        );
+        engine.evaluate_pipeline(&mut pipeline).await.unwrap();
+        assert!(engine.evaluate_pipeline(&mut pipeline).await.unwrap());
+
+        let step = &pipeline.steps[&step_id];
+        assert!(matches!(step.status, Status::Completed));
+        assert_eq!(step.remaining_amount.as_deref(), Some("0"));
+        let hashes: HashSet<String> = step
+            .slices
+            .iter()
+            .filter_map(|slice| slice.transaction_hash.clone())
+            .collect();
+        assert_eq!(hashes.len(), 3);
+        let submitted = store
+            .events()
+            .iter()
+            .filter(|e| matches!(e.kind, PipelineEventKind::TransactionSubmitted { .. }))
+            .count();
+        assert_eq!(submitted, 3);
+    }
+}
@@ -0,0 +1,142 @@
+//! Helpers for running the engine against the in-memory store
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use solana_sdk::signature::Keypair;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+use super::api::{PipelineParams, WirePipeline};
+use super::executor::LocalKeypairExecutor;
+use super::pipeline::{Action, NotificationTarget, Pipeline};
+use super::Engine;
+use crate::redis::subscriber::{make_redis_subscriber, PriceUpdate};
+use crate::store::MemoryStore;
+
+pub fn engine() -> (Engine, Arc<MemoryStore>) {
+    let store = Arc::new(MemoryStore::new());
+    (engine_on(store.clone()), store)
+}
+
+/// Engine on a shared store, stands in for one of several instances
+pub fn engine_on(store: Arc<MemoryStore>) -> Engine {
+    let (tx, _) = mpsc::channel(1);
+    Engine::new(
+        store,
+        make_redis_subscriber(tx, "test").unwrap(),
+        None,
+        Arc::new(LocalKeypairExecutor::new(Keypair::new(), String::new())),
+    )
+}
+
+/// Waits for the evaluations spawned by price updates and scheduler ticks
+pub async fn settle(engine: &Engine) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while engine.pending_tasks.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("pipeline evaluations did not finish");
+}
+
+pub fn price_update(asset: &str, price: f64) -> PriceUpdate {
+    PriceUpdate {
+        name: asset.to_string(),
+        pubkey: asset.to_string(),
+        price,
+        market_cap: price * 1_000_000.0,
+        timestamp: chrono::Utc::now().timestamp() as u64,
+        slot: 1,
+        swap_amount: 100.0,
+        owner: String::new(),
+        signature: String::new(),
+        multi_hop: false,
+        is_buy: true,
+        is_pump: false,
+    }
+}
+
+/// Single step pipeline posting to `url` once the price of `asset` is above `value`
+pub fn webhook_pipeline(asset: &str, value: f64, url: &str) -> Pipeline {
+    let wire: WirePipeline = serde_json::from_value(json!({
+        "steps": [{
+            "action": { "type": "Notification", "input_token": asset, "message": "hit" },
+            "conditions": [{ "type": "PriceAbove", "asset": asset, "value": value }]
+        }]
+    }))
+    .unwrap();
+    let mut pipeline = Pipeline::try_from((
+        wire,
+        PipelineParams {
+            user_id: "user".to_string(),
+            wallet_address: None,
+            pubkey: None,
+        },
+    ))
+    .unwrap();
+
+    // the wire format only accepts https webhooks to public hosts
+    for step in pipeline.steps.values_mut() {
+        if let Action::Notification(notification) = &mut step.action {
+            notification.channel = NotificationTarget::Webhook {
+                url: url.to_string(),
+                secret: "s3cret".to_string(),
+            };
+        }
+    }
+    pipeline
+}
+
+pub struct CapturedRequest {
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Minimal HTTP server standing in for a webhook receiver, answers the
+/// first request with `status` and hands it back for inspection
+pub async fn stand_in(status: u16) -> (String, tokio::task::JoinHandle<CapturedRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+    let handle = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 4096];
+        let (head_len, headers) = loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            raw.extend_from_slice(&buf[..n]);
+            if let Some(pos) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&raw[..pos]).to_string();
+                let headers = head
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| line.split_once(": "))
+                    .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+                    .collect::<HashMap<_, _>>();
+                break (pos + 4, headers);
+            }
+        };
+        let content_length: usize = headers["content-length"].parse().unwrap();
+        while raw.len() < head_len + content_length {
+            let n = socket.read(&mut buf).await.unwrap();
+            raw.extend_from_slice(&buf[..n]);
+        }
+        socket
+            .write_all(format!("HTTP/1.1 {} OK\r\nContent-Length: 0\r\n\r\n", status).as_bytes())
+            .await
+            .unwrap();
+
+        CapturedRequest {
+            headers,
+            body: raw[head_len..head_len + content_length].to_vec(),
+        }
+    });
+
+    (url, handle)
+}
@@ -148,6 +148,42 @@ pub struct AccountMeta {
     pub is_writable: bool,
 }
 
+/// Priority fee levels understood by the Jupiter swap API
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PriorityFee {
+    Medium,
+    High,
+    VeryHigh,
+}
+
+/// Cap used when a priority level is given without a maximum, 0.005 SOL
+pub const DEFAULT_MAX_PRIORITY_FEE_LAMPORTS: u64 = 5_000_000;
+
+#[derive(Debug, Default, Clone)]
+pub struct SwapParams {
+    /// Let Jupiter pick the slippage at swap time instead of the quoted one
+    pub dynamic_slippage: bool,
+    pub priority_fee: Option<PriorityFee>,
+    pub max_priority_fee_lamports: Option<u64>,
+}
+
+impl SwapParams {
+    fn prioritization_fee(&self) -> Option<serde_json::Value> {
+        if self.priority_fee.is_none() && self.max_priority_fee_lamports.is_none() {
+            return None;
+        }
+        Some(serde_json::json!({
+            "priorityLevelWithMaxLamports": {
+                "priorityLevel": self.priority_fee.unwrap_or(PriorityFee::VeryHigh),
+                "maxLamports": self
+                    .max_priority_fee_lamports
+                    .unwrap_or(DEFAULT_MAX_PRIORITY_FEE_LAMPORTS),
+            }
+        }))
+    }
+}
+
 pub struct Jupiter;
 
 impl Jupiter {
@@ -155,25 +191,43 @@ impl Jupiter {
         input_mint: &str,
         output_mint: &str,
         amount: u64,
+        slippage_bps: Option<u16>,
     ) -> Result<QuoteResponse> {
-        let url = format!(
+        let mut url = format!(
             "https://quote-api.jup.ag/v6/quote?inputMint={}&outputMint={}&amount={}",
             input_mint, output_mint, amount,
         );
+        if let Some(slippage_bps) = slippage_bps {
+            url.push_str(&format!("&slippageBps={}", slippage_bps));
+        }
 
         let response = reqwest::get(&url).await?.json::<QuoteResponse>().await?;
         Ok(response)
     }
 
-    pub async fn swap(
-        quote_response: QuoteResponse,
+    fn swap_request(
+        quote_response: &QuoteResponse,
         owner: &Pubkey,
-    ) -> Result<VersionedTransaction> {
-        let swap_request = serde_json::json!({
+        params: &SwapParams,
+    ) -> serde_json::Value {
+        let mut swap_request = serde_json::json!({
             "userPublicKey": owner.to_string(),
             "quoteResponse": quote_response,
-            "dynamicSlippage": true
+            "dynamicSlippage": params.dynamic_slippage,
+            "dynamicComputeUnitLimit": true
         });
+        if let Some(fee) = params.prioritization_fee() {
+            swap_request["prioritizationFeeLamports"] = fee;
+        }
+        swap_request
+    }
+
+    pub async fn swap(
+        quote_response: QuoteResponse,
+        owner: &Pubkey,
+        params: &SwapParams,
+    ) -> Result<VersionedTransaction> {
+        let swap_request = Self::swap_request(&quote_response, owner, params);
         let client = reqwest::Client::new();
         let raw_res = client
             .post("https://quote-api.jup.ag/v6/swap")
@@ -225,3 +279,60 @@ impl Jupiter {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote() -> QuoteResponse {
+        serde_json::from_value(serde_json::json!({
+            "inputMint": "So11111111111111111111111111111111111111112",
+            "inAmount": "1000",
+            "outputMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+            "outAmount": "1",
+            "otherAmountThreshold": "1",
+            "swapMode": "ExactIn",
+            "slippageBps": 50,
+            "platformFee": null,
+            "priceImpactPct": "0",
+            "routePlan": [],
+            "contextSlot": 1,
+            "timeTaken": 0.01
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_swap_request_priority_fee() {
+        let owner = Pubkey::new_unique();
+
+        let request = Jupiter::swap_request(&quote(), &owner, &SwapParams::default());
+        assert!(request.get("prioritizationFeeLamports").is_none());
+        assert_eq!(request["dynamicSlippage"], false);
+
+        let params = SwapParams {
+            dynamic_slippage: true,
+            priority_fee: Some(PriorityFee::High),
+            max_priority_fee_lamports: None,
+        };
+        let request = Jupiter::swap_request(&quote(), &owner, &params);
+        assert_eq!(request["dynamicSlippage"], true);
+        assert_eq!(
+            request["prioritizationFeeLamports"]["priorityLevelWithMaxLamports"],
+            serde_json::json!({
+                "priorityLevel": "high",
+                "maxLamports": DEFAULT_MAX_PRIORITY_FEE_LAMPORTS
+            })
+        );
+
+        let params = SwapParams {
+            max_priority_fee_lamports: Some(1_000),
+            ..Default::default()
+        };
+        let request = Jupiter::swap_request(&quote(), &owner, &params);
+        assert_eq!(
+            request["prioritizationFeeLamports"]["priorityLevelWithMaxLamports"]["priorityLevel"],
+            "veryHigh"
+        );
+    }
+}
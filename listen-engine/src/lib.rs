@@ -3,4 +3,5 @@ pub mod jup;
 pub mod metrics;
 pub mod redis;
 pub mod server;
+pub mod store;
 pub use engine::Engine;
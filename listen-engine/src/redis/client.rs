@@ -60,6 +60,23 @@ impl RedisClient {
         Ok(())
     }
 
+    pub async fn publish<T: Serialize>(
+        &self,
+        channel: &str,
+        value: &T,
+    ) -> Result<(), RedisClientError> {
+        let mut conn = self.pool.get().await?;
+        let serialized = serde_json::to_string(value)?;
+
+        let _: i64 = cmd("PUBLISH")
+            .arg(channel)
+            .arg(serialized)
+            .query_async(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, RedisClientError> {
         let mut conn = self.pool.get().await?;
 
@@ -200,32 +217,15 @@ impl RedisClient {
         Ok(())
     }
 
-    pub async fn execute_redis_pipe(
-        &self,
-        pipe: bb8_redis::redis::Pipeline,
-    ) -> Result<Vec<Option<Pipeline>>, RedisClientError> {
-        let mut conn = self.get_connection().await?;
-        let results: Vec<Option<String>> = pipe.query_async(&mut *conn).await?;
-
-        let mut pipelines = Vec::with_capacity(results.len());
-        for json_str in results.into_iter().flatten() {
-            match serde_json::from_str(&json_str) {
-                Ok(pipeline) => pipelines.push(Some(pipeline)),
-                Err(e) => {
-                    tracing::warn!("Failed to deserialize pipeline: {}", e);
-                    pipelines.push(None);
-                }
-            }
-        }
-
-        Ok(pipelines)
+    pub async fn get_price(&self, asset: &str) -> Result<f64, RedisClientError> {
+        Ok(self.get_price_update(asset).await?.price)
     }
 
-    pub async fn get_price(&self, asset: &str) -> Result<f64, RedisClientError> {
+    pub async fn get_price_update(&self, asset: &str) -> Result<PriceUpdate, RedisClientError> {
         let price_key = format!("solana:price:{}", asset);
         let price: Option<PriceUpdate> = self.get(&price_key).await?;
         match price {
-            Some(price) => Ok(price.price),
+            Some(price) => Ok(price),
             None => Err(RedisClientError::KeyNotFound(price_key)),
         }
     }
@@ -240,6 +240,16 @@ impl RedisClient {
         Ok(result)
     }
 
+    pub async fn decr(&self, key: &str, decrement: u32) -> Result<i64, RedisClientError> {
+        let mut conn = self.pool.get().await?;
+        let result: i64 = cmd("DECRBY")
+            .arg(key)
+            .arg(decrement)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(result)
+    }
+
     pub async fn expire(&self, key: &str, seconds: usize) -> Result<(), RedisClientError> {
         let mut conn = self.pool.get().await?;
         let _: () = cmd("EXPIRE")
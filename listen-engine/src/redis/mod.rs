@@ -1,3 +1,4 @@
 pub mod client;
 pub mod rate_limits;
+pub mod store;
 pub mod subscriber;
@@ -2,9 +2,34 @@ use crate::redis::client::{RedisClient, RedisClientError};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+/// Limit of the order notionals when the user has none set
+pub const UNLIMITED: u32 = u32::MAX;
+
 pub enum RateLimitType {
     EmailNotifications,
+    WebhookNotifications,
+    TelegramNotifications,
     ActivePipelines,
+    /// USD notional of a single order, a cap without a counter
+    OrderNotionalUsd,
+    /// USD notional of the orders sent within a day
+    DailyNotionalUsd,
+}
+
+/// Per-user lists of mints orders may or may not trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MintList {
+    Allow,
+    Deny,
+}
+
+impl MintList {
+    pub fn key(&self) -> &str {
+        match self {
+            MintList::Allow => "allowed_mints",
+            MintList::Deny => "denied_mints",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -26,34 +51,44 @@ impl RateLimitType {
     pub fn key(&self) -> &str {
         match self {
             RateLimitType::EmailNotifications => "email_notifications",
+            RateLimitType::WebhookNotifications => "webhook_notifications",
+            RateLimitType::TelegramNotifications => "telegram_notifications",
             RateLimitType::ActivePipelines => "active_pipelines",
+            RateLimitType::OrderNotionalUsd => "order_notional_usd",
+            RateLimitType::DailyNotionalUsd => "daily_notional_usd",
         }
     }
 
-    pub fn default_limit(&self, plan: Option<UserPlan>) -> u32 {
-        let plan = plan.unwrap_or(UserPlan::Free);
-
+    pub fn default_limit(&self, _plan: Option<UserPlan>) -> u32 {
         match self {
-            RateLimitType::EmailNotifications => match plan {
-                _ => 5,
-            },
-            RateLimitType::ActivePipelines => match plan {
-                _ => 1000,
-            },
+            RateLimitType::EmailNotifications => 5,
+            RateLimitType::WebhookNotifications => 100,
+            RateLimitType::TelegramNotifications => 50,
+            RateLimitType::ActivePipelines => 1000,
+            // only enforced once set for the user, see `risk`
+            RateLimitType::OrderNotionalUsd | RateLimitType::DailyNotionalUsd => UNLIMITED,
         }
     }
 
     pub fn default_window(&self) -> Duration {
         match self {
-            RateLimitType::EmailNotifications => Duration::from_secs(24 * 60 * 60), // 24 hours
-            RateLimitType::ActivePipelines => Duration::from_secs(0), // No expiry for active pipelines
+            RateLimitType::EmailNotifications
+            | RateLimitType::WebhookNotifications
+            | RateLimitType::TelegramNotifications
+            | RateLimitType::DailyNotionalUsd => Duration::from_secs(24 * 60 * 60), // 24 hours
+            RateLimitType::ActivePipelines | RateLimitType::OrderNotionalUsd => {
+                Duration::from_secs(0) // No expiry for active pipelines and caps
+            }
         }
     }
 
     pub fn is_blocking(&self) -> bool {
         match self {
-            RateLimitType::EmailNotifications => true, // Block when limit reached
-            RateLimitType::ActivePipelines => true,    // Block when limit reached
+            RateLimitType::EmailNotifications
+            | RateLimitType::WebhookNotifications
+            | RateLimitType::TelegramNotifications => true, // Block when limit reached
+            RateLimitType::ActivePipelines => true, // Block when limit reached
+            RateLimitType::OrderNotionalUsd | RateLimitType::DailyNotionalUsd => true,
         }
     }
 }
@@ -76,7 +111,7 @@ impl RedisClient {
         let ttl: Option<i64> = self.ttl(&key).await?;
 
         let count = count.unwrap_or(0);
-        let remaining = if count > limit { 0 } else { limit - count };
+        let remaining = limit.saturating_sub(count);
 
         // Convert TTL to reset timestamp if available
         let reset_at = ttl.and_then(|ttl| {
@@ -98,14 +133,24 @@ impl RedisClient {
         &self,
         user_id: &str,
         limit_type: &RateLimitType,
+    ) -> Result<RateLimit, RedisClientError> {
+        self.increment_rate_limit_by(user_id, limit_type, 1).await
+    }
+
+    // Add `amount` to the counter, e.g. the USD notional of an order
+    pub async fn increment_rate_limit_by(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
     ) -> Result<RateLimit, RedisClientError> {
         let key = format!("rate_limit:{}:{}", user_id, limit_type.key());
 
         // Increment the counter
-        let new_count: u32 = self.incr(&key, 1).await?;
+        let new_count: u32 = self.incr(&key, amount).await?;
 
         // If this is the first increment, set the expiry
-        if new_count == 1 {
+        if new_count == amount {
             let window = limit_type.default_window();
             if window.as_secs() > 0 {
                 self.expire(&key, window.as_secs() as usize).await?;
@@ -113,11 +158,7 @@ impl RedisClient {
         }
 
         let limit = self.get_user_limit(user_id, limit_type).await?;
-        let remaining = if new_count > limit {
-            0
-        } else {
-            limit - new_count
-        };
+        let remaining = limit.saturating_sub(new_count);
 
         // Get the TTL to determine when the limit resets
         let ttl: Option<i64> = self.ttl(&key).await?;
@@ -136,6 +177,62 @@ impl RedisClient {
         })
     }
 
+    /// Adds `amount` to the counter unless that takes it past the limit, in
+    /// which case the increment is rolled back and `None` returned. The
+    /// counter is compared after the increment, so concurrent reservations
+    /// can't overshoot the limit together.
+    pub async fn reserve_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<Option<RateLimit>, RedisClientError> {
+        let key = format!("rate_limit:{}:{}", user_id, limit_type.key());
+        let limit = self.get_user_limit(user_id, limit_type).await?;
+
+        let new_count: u32 = self.incr(&key, amount).await?;
+        if new_count == amount {
+            let window = limit_type.default_window();
+            if window.as_secs() > 0 {
+                self.expire(&key, window.as_secs() as usize).await?;
+            }
+        }
+        if new_count > limit {
+            self.release_rate_limit(user_id, limit_type, amount).await?;
+            return Ok(None);
+        }
+
+        let ttl: Option<i64> = self.ttl(&key).await?;
+        let reset_at = ttl.and_then(|ttl| {
+            if ttl > 0 {
+                Some((chrono::Utc::now().timestamp() as u64) + (ttl as u64))
+            } else {
+                None
+            }
+        });
+
+        Ok(Some(RateLimit {
+            limit,
+            remaining: limit - new_count,
+            reset_at,
+        }))
+    }
+
+    // Take back a reservation, e.g. of an order that failed to send
+    pub async fn release_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<(), RedisClientError> {
+        let key = format!("rate_limit:{}:{}", user_id, limit_type.key());
+        // the counter expired since the reservation
+        if self.decr(&key, amount).await? < 0 {
+            self.del(&key).await?;
+        }
+        Ok(())
+    }
+
     pub async fn check_rate_limit(
         &self,
         user_id: &str,
@@ -171,11 +268,7 @@ impl RedisClient {
                 let new_count: u32 = self.incr(&key, u32::MAX - 1 + 1).await?; // Equivalent to -1
 
                 let limit = self.get_user_limit(user_id, limit_type).await?;
-                let remaining = if new_count > limit {
-                    0
-                } else {
-                    limit - new_count
-                };
+                let remaining = limit.saturating_sub(new_count);
 
                 // Get the TTL
                 let ttl: Option<i64> = self.ttl(&key).await?;
@@ -225,4 +318,25 @@ impl RedisClient {
         let limit: Option<u32> = self.get(&key).await?;
         Ok(limit.unwrap_or_else(|| limit_type.default_limit(None)))
     }
+
+    pub async fn get_mint_list(
+        &self,
+        user_id: &str,
+        list: MintList,
+    ) -> Result<Vec<String>, RedisClientError> {
+        let key = format!("user_limit:{}:{}", user_id, list.key());
+        let mints: Option<Vec<String>> = self.get(&key).await?;
+        Ok(mints.unwrap_or_default())
+    }
+
+    pub async fn set_mint_list(
+        &self,
+        user_id: &str,
+        list: MintList,
+        mints: &[String],
+    ) -> Result<(), RedisClientError> {
+        let key = format!("user_limit:{}:{}", user_id, list.key());
+        self.set(&key, &mints).await?;
+        Ok(())
+    }
 }
@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bb8_redis::redis::{cmd, pipe};
+
+use crate::engine::cluster::{IndexUpdate, PIPELINE_INDEX_CHANNEL};
+use crate::engine::events::{PipelineEvent, PIPELINE_EVENTS_CHANNEL};
+use crate::engine::pipeline::Pipeline;
+use crate::redis::client::{RedisClient, RedisClientError};
+use crate::redis::rate_limits::{MintList, RateLimit, RateLimitType};
+use crate::redis::subscriber::PriceUpdate;
+use crate::store::{PipelineStore, StoreError};
+
+/// Sorted set of engine instances, scored by the time their heartbeat expires
+const INSTANCES_KEY: &str = "engine:instances";
+
+// leases are compared against the owner before they are touched, so that an
+// instance whose lease ran out can't extend or release someone else's
+const ACQUIRE_LEASE_SCRIPT: &str = r#"
+local holder = redis.call('GET', KEYS[1])
+if holder == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+elseif holder then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+return 1
+"#;
+
+const RELEASE_LEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// Lease keys must not start with "pipeline:", that prefix is scanned for pipelines
+fn lease_key(key: &str) -> String {
+    format!("lease:{}", key)
+}
+
+#[async_trait]
+impl PipelineStore for RedisClient {
+    async fn save_pipeline(&self, pipeline: &Pipeline) -> Result<(), StoreError> {
+        Ok(RedisClient::save_pipeline(self, pipeline).await?)
+    }
+
+    async fn get_pipeline(&self, user_id: &str, id: &str) -> Result<Option<Pipeline>, StoreError> {
+        Ok(RedisClient::get_pipeline(self, user_id, id).await?)
+    }
+
+    async fn get_pipelines(&self, keys: &[String]) -> Result<Vec<Option<Pipeline>>, StoreError> {
+        let mut conn = self.get_connection().await?;
+        let mut pipe = pipe();
+        for key in keys {
+            pipe.get(format!("pipeline:{}", key));
+        }
+        let results: Vec<Option<String>> = pipe
+            .query_async(&mut *conn)
+            .await
+            .map_err(RedisClientError::RedisError)?;
+
+        // keep missing and malformed entries as None so results line up with keys
+        Ok(results
+            .into_iter()
+            .map(|json_str| {
+                json_str.and_then(|json_str| match serde_json::from_str(&json_str) {
+                    Ok(pipeline) => Some(pipeline),
+                    Err(e) => {
+                        tracing::warn!("Failed to deserialize pipeline: {}", e);
+                        None
+                    }
+                })
+            })
+            .collect())
+    }
+
+    async fn get_all_pipelines(&self) -> Result<Vec<Pipeline>, StoreError> {
+        Ok(RedisClient::get_all_pipelines(self).await?)
+    }
+
+    async fn get_all_pipelines_for_user(&self, user_id: &str) -> Result<Vec<Pipeline>, StoreError> {
+        Ok(RedisClient::get_all_pipelines_for_user(self, user_id).await?)
+    }
+
+    async fn delete_pipeline(&self, user_id: &str, id: &str) -> Result<(), StoreError> {
+        Ok(RedisClient::delete_pipeline(self, user_id, id).await?)
+    }
+
+    async fn get_price_update(&self, asset: &str) -> Result<Option<PriceUpdate>, StoreError> {
+        match RedisClient::get_price_update(self, asset).await {
+            Ok(update) => Ok(Some(update)),
+            Err(RedisClientError::KeyNotFound(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn publish_event(&self, event: &PipelineEvent) -> Result<(), StoreError> {
+        Ok(self.publish(PIPELINE_EVENTS_CHANNEL, event).await?)
+    }
+
+    async fn get_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+    ) -> Result<RateLimit, StoreError> {
+        Ok(RedisClient::get_rate_limit(self, user_id, limit_type).await?)
+    }
+
+    async fn increment_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+    ) -> Result<RateLimit, StoreError> {
+        Ok(RedisClient::increment_rate_limit(self, user_id, limit_type).await?)
+    }
+
+    async fn reserve_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<Option<RateLimit>, StoreError> {
+        Ok(RedisClient::reserve_rate_limit(self, user_id, limit_type, amount).await?)
+    }
+
+    async fn release_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<(), StoreError> {
+        Ok(RedisClient::release_rate_limit(self, user_id, limit_type, amount).await?)
+    }
+
+    async fn set_user_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        limit: u32,
+    ) -> Result<(), StoreError> {
+        Ok(RedisClient::set_user_limit(self, user_id, limit_type, limit).await?)
+    }
+
+    async fn get_mint_list(
+        &self,
+        user_id: &str,
+        list: MintList,
+    ) -> Result<Vec<String>, StoreError> {
+        Ok(RedisClient::get_mint_list(self, user_id, list).await?)
+    }
+
+    async fn set_mint_list(
+        &self,
+        user_id: &str,
+        list: MintList,
+        mints: &[String],
+    ) -> Result<(), StoreError> {
+        Ok(RedisClient::set_mint_list(self, user_id, list, mints).await?)
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError> {
+        let mut conn = self.get_connection().await?;
+        let acquired: i64 = cmd("EVAL")
+            .arg(ACQUIRE_LEASE_SCRIPT)
+            .arg(1)
+            .arg(lease_key(key))
+            .arg(owner)
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut *conn)
+            .await
+            .map_err(RedisClientError::RedisError)?;
+        Ok(acquired == 1)
+    }
+
+    async fn release_lease(&self, key: &str, owner: &str) -> Result<(), StoreError> {
+        let mut conn = self.get_connection().await?;
+        let _: i64 = cmd("EVAL")
+            .arg(RELEASE_LEASE_SCRIPT)
+            .arg(1)
+            .arg(lease_key(key))
+            .arg(owner)
+            .query_async(&mut *conn)
+            .await
+            .map_err(RedisClientError::RedisError)?;
+        Ok(())
+    }
+
+    async fn heartbeat(&self, instance_id: &str, ttl: Duration) -> Result<Vec<String>, StoreError> {
+        let mut conn = self.get_connection().await?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let (instances,): (Vec<String>,) = pipe()
+            .atomic()
+            .zadd(INSTANCES_KEY, instance_id, now + ttl.as_millis() as i64)
+            .ignore()
+            .zrembyscore(INSTANCES_KEY, "-inf", now)
+            .ignore()
+            .zrange(INSTANCES_KEY, 0, -1)
+            .query_async(&mut *conn)
+            .await
+            .map_err(RedisClientError::RedisError)?;
+        Ok(instances)
+    }
+
+    async fn leave(&self, instance_id: &str) -> Result<(), StoreError> {
+        let mut conn = self.get_connection().await?;
+        let _: () = pipe()
+            .zrem(INSTANCES_KEY, instance_id)
+            .ignore()
+            .query_async(&mut *conn)
+            .await
+            .map_err(RedisClientError::RedisError)?;
+        Ok(())
+    }
+
+    async fn publish_index_update(&self, update: &IndexUpdate) -> Result<(), StoreError> {
+        Ok(self.publish(PIPELINE_INDEX_CHANNEL, update).await?)
+    }
+}
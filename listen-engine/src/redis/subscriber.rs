@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
@@ -9,6 +10,47 @@ use thiserror::Error;
 use tokio::sync::mpsc;
 use tracing::error;
 
+/// Pub/sub channel the indexer publishes price updates to
+pub const PRICE_UPDATES_CHANNEL: &str = "price_updates";
+/// Stream the indexer appends price updates to, see `PriceTransport::Stream`
+pub const PRICE_UPDATES_STREAM: &str = "price_updates:stream";
+
+const STREAM_READ_COUNT: usize = 100;
+const STREAM_BLOCK_MS: usize = 1000;
+
+/// How price updates get from the indexer to the engine, set with
+/// `PRICE_UPDATES_TRANSPORT` (`pubsub` or `stream`, the variable is shared
+/// with listen-data where `both` publishes to both, the engine then reads the
+/// stream). Pub/sub drops whatever
+/// is published while the engine isn't listening. The stream is read through
+/// a consumer group per engine instance, entries are acknowledged once the
+/// pipelines they triggered have been evaluated and whatever wasn't is
+/// replayed when the instance starts again, which requires a stable
+/// `ENGINE_INSTANCE_ID`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PriceTransport {
+    #[default]
+    PubSub,
+    Stream,
+}
+
+impl PriceTransport {
+    pub fn from_env() -> Self {
+        Self::parse(std::env::var("PRICE_UPDATES_TRANSPORT").ok().as_deref())
+    }
+
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            None | Some("pubsub") => PriceTransport::PubSub,
+            Some("stream") | Some("both") => PriceTransport::Stream,
+            Some(other) => {
+                tracing::warn!("Unknown PRICE_UPDATES_TRANSPORT {:?}, using pubsub", other);
+                PriceTransport::PubSub
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PriceUpdate {
     pub name: String,
@@ -25,6 +67,14 @@ pub struct PriceUpdate {
     pub is_pump: bool,
 }
 
+/// Price update as received, streamed updates carry the entry id to
+/// acknowledge once the update has been evaluated
+#[derive(Debug, Clone)]
+pub struct PriceMessage {
+    pub update: PriceUpdate,
+    pub stream_id: Option<String>,
+}
+
 #[derive(Error, Debug)]
 pub enum RedisSubscriberError {
     #[error("[RedisSubscriber] Redis error: {0}")]
@@ -42,27 +92,62 @@ pub enum RedisSubscriberError {
 
 pub struct RedisSubscriber {
     client: redis::Client,
-    tx: mpsc::Sender<PriceUpdate>,
+    tx: mpsc::Sender<PriceMessage>,
     task_handle: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+    transport: PriceTransport,
+    consumer: String,
+    ack_tx: mpsc::UnboundedSender<String>,
+    ack_rx: Arc<Mutex<mpsc::UnboundedReceiver<String>>>,
 }
 
 impl RedisSubscriber {
+    /// `consumer` names the consumer group when reading the stream, so it
+    /// has to be unique per engine instance and stable across its restarts
     pub fn new(
         redis_url: &str,
-        tx: mpsc::Sender<PriceUpdate>,
+        tx: mpsc::Sender<PriceMessage>,
+        transport: PriceTransport,
+        consumer: &str,
     ) -> Result<Self, RedisSubscriberError> {
         let client = redis::Client::open(redis_url)?;
+        let (ack_tx, ack_rx) = mpsc::unbounded_channel();
         Ok(Self {
             client,
             tx,
             task_handle: Arc::new(Mutex::new(None)),
+            transport,
+            consumer: consumer.to_string(),
+            ack_tx,
+            ack_rx: Arc::new(Mutex::new(ack_rx)),
         })
     }
 
+    pub fn transport(&self) -> PriceTransport {
+        self.transport
+    }
+
+    /// Acknowledges a streamed update, it won't be replayed after a restart
+    pub fn ack(&self, stream_id: String) {
+        // the receiver lives as long as the subscriber
+        let _ = self.ack_tx.send(stream_id);
+    }
+
     pub async fn start_listening(&self) -> Result<(), RedisSubscriberError> {
+        let handle = match self.transport {
+            PriceTransport::PubSub => self.listen_pubsub().await?,
+            PriceTransport::Stream => self.consume_stream(),
+        };
+
+        let mut task_handle = self.task_handle.lock().await;
+        *task_handle = Some(handle);
+
+        Ok(())
+    }
+
+    async fn listen_pubsub(&self) -> Result<tokio::task::JoinHandle<()>, RedisSubscriberError> {
         let conn = self.client.get_async_connection().await?;
         let mut pubsub = conn.into_pubsub();
-        pubsub.subscribe("price_updates").await?;
+        pubsub.subscribe(PRICE_UPDATES_CHANNEL).await?;
         let tx = self.tx.clone();
 
         let handle = tokio::spawn(async move {
@@ -96,14 +181,18 @@ impl RedisSubscriber {
                                     update.timestamp
                                 );
 
-                                match tx.try_send(update) {
+                                let message = PriceMessage {
+                                    update,
+                                    stream_id: None,
+                                };
+                                match tx.try_send(message) {
                                     Ok(_) => {
                                         metrics::counter!("price_updates_sent", 1);
                                     }
                                     Err(e) => match e {
-                                        tokio::sync::mpsc::error::TrySendError::Full(update) => {
+                                        tokio::sync::mpsc::error::TrySendError::Full(message) => {
                                             metrics::counter!("price_update_channel_full", 1);
-                                            if let Err(e) = tx.blocking_send(update) {
+                                            if let Err(e) = tx.blocking_send(message) {
                                                 error!(
                                                     "Failed to send price update (blocking): {}",
                                                     e
@@ -118,7 +207,10 @@ impl RedisSubscriber {
                                             }
                                         }
                                         tokio::sync::mpsc::error::TrySendError::Closed(e) => {
-                                            error!("Failed to send price update: {}", e.signature);
+                                            error!(
+                                                "Failed to send price update: {}",
+                                                e.update.signature
+                                            );
                                             metrics::counter!("price_updates_send_errors", 1);
                                             if tx.is_closed() {
                                                 error!("Channel closed, stopping subscriber task");
@@ -152,10 +244,32 @@ impl RedisSubscriber {
             metrics::counter!("redis_subscriber_exits", 1);
         });
 
-        let mut task_handle = self.task_handle.lock().await;
-        *task_handle = Some(handle);
+        Ok(handle)
+    }
 
-        Ok(())
+    fn consume_stream(&self) -> tokio::task::JoinHandle<()> {
+        let client = self.client.clone();
+        let tx = self.tx.clone();
+        let consumer = self.consumer.clone();
+        let ack_rx = self.ack_rx.clone();
+
+        tokio::spawn(async move {
+            // held for as long as the task runs, a restarted task picks up the
+            // acks this one didn't get to
+            let mut ack_rx = ack_rx.lock().await;
+            loop {
+                match read_stream(&client, &tx, &consumer, &mut ack_rx).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        error!("Price update stream error: {}", e);
+                        metrics::counter!("redis_stream_errors", 1);
+                    }
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+
+            metrics::counter!("redis_subscriber_exits", 1);
+        })
     }
 
     pub async fn check_health(&self) -> bool {
@@ -182,11 +296,128 @@ impl RedisSubscriber {
     }
 }
 
+/// Reads the stream through the consumer group of the instance. Entries that
+/// were delivered before but never acknowledged are read first, then the
+/// ones added since the group last read. Returns once the engine is gone.
+async fn read_stream(
+    client: &redis::Client,
+    tx: &mpsc::Sender<PriceMessage>,
+    consumer: &str,
+    ack_rx: &mut mpsc::UnboundedReceiver<String>,
+) -> Result<(), RedisSubscriberError> {
+    let mut conn = client.get_multiplexed_async_connection().await?;
+    let group = format!("engine:{}", consumer);
+
+    let created: redis::RedisResult<()> = redis::cmd("XGROUP")
+        .arg("CREATE")
+        .arg(PRICE_UPDATES_STREAM)
+        .arg(&group)
+        .arg("$")
+        .arg("MKSTREAM")
+        .query_async(&mut conn)
+        .await;
+    match created {
+        Err(e) if e.code() != Some("BUSYGROUP") => return Err(e.into()),
+        _ => {}
+    }
+
+    let mut cursor = "0".to_string();
+    let mut to_ack: Vec<String> = Vec::new();
+    loop {
+        while let Ok(stream_id) = ack_rx.try_recv() {
+            to_ack.push(stream_id);
+        }
+        if !to_ack.is_empty() {
+            let _: i64 = redis::cmd("XACK")
+                .arg(PRICE_UPDATES_STREAM)
+                .arg(&group)
+                .arg(&to_ack)
+                .query_async(&mut conn)
+                .await?;
+            metrics::counter!("price_updates_acked", to_ack.len() as u64);
+            to_ack.clear();
+        }
+
+        let reply: redis::Value = redis::cmd("XREADGROUP")
+            .arg("GROUP")
+            .arg(&group)
+            .arg(consumer)
+            .arg("COUNT")
+            .arg(STREAM_READ_COUNT)
+            .arg("BLOCK")
+            .arg(STREAM_BLOCK_MS)
+            .arg("STREAMS")
+            .arg(PRICE_UPDATES_STREAM)
+            .arg(&cursor)
+            .query_async(&mut conn)
+            .await?;
+        let entries = stream_entries(&reply)?;
+
+        // pending entries are paged through by id, once they run out new
+        // entries are read
+        if cursor != ">" {
+            match entries.last() {
+                Some((stream_id, _)) => cursor = stream_id.clone(),
+                None => {
+                    tracing::info!("Replayed unacknowledged price updates");
+                    cursor = ">".to_string();
+                }
+            }
+            metrics::counter!("price_updates_replayed", entries.len() as u64);
+        }
+
+        for (stream_id, data) in entries {
+            match data.map(|data| serde_json::from_str::<PriceUpdate>(&data)) {
+                Some(Ok(update)) => {
+                    metrics::counter!("price_updates_parsed", 1);
+                    let message = PriceMessage {
+                        update,
+                        stream_id: Some(stream_id),
+                    };
+                    if tx.send(message).await.is_err() {
+                        error!("Channel closed, stopping subscriber task");
+                        return Ok(());
+                    }
+                    metrics::counter!("price_updates_sent", 1);
+                }
+                Some(Err(e)) => {
+                    error!("Failed to parse price update: {}", e);
+                    metrics::counter!("price_updates_parse_errors", 1);
+                    to_ack.push(stream_id);
+                }
+                // trimmed from the stream before it was acknowledged
+                None => to_ack.push(stream_id),
+            }
+        }
+    }
+}
+
+/// Entry ids of an XREADGROUP reply with their `data` field, which is `None`
+/// for pending entries that have since been trimmed from the stream
+fn stream_entries(reply: &redis::Value) -> redis::RedisResult<Vec<(String, Option<String>)>> {
+    // nil when the read timed out
+    let streams: Vec<redis::Value> = redis::from_redis_value(reply)?;
+    let mut entries = Vec::new();
+    for stream in &streams {
+        let (_, stream_entries): (String, Vec<redis::Value>) = redis::from_redis_value(stream)?;
+        for entry in &stream_entries {
+            let (stream_id, fields): (String, Option<HashMap<String, String>>) =
+                redis::from_redis_value(entry)?;
+            entries.push((
+                stream_id,
+                fields.and_then(|mut fields| fields.remove("data")),
+            ));
+        }
+    }
+    Ok(entries)
+}
+
 pub fn make_redis_subscriber(
-    tx: mpsc::Sender<PriceUpdate>,
+    tx: mpsc::Sender<PriceMessage>,
+    consumer: &str,
 ) -> Result<Arc<RedisSubscriber>, RedisSubscriberError> {
     let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
-    let subscriber = RedisSubscriber::new(&redis_url, tx)?;
+    let subscriber = RedisSubscriber::new(&redis_url, tx, PriceTransport::from_env(), consumer)?;
     Ok(Arc::new(subscriber))
 }
 
@@ -197,12 +428,59 @@ mod tests {
     #[tokio::test]
     async fn test_redis_subscriber() {
         let (tx, mut rx) = mpsc::channel(100);
-        let subscriber = make_redis_subscriber(tx).unwrap();
+        let subscriber = make_redis_subscriber(tx, "test").unwrap();
 
         subscriber.start_listening().await.unwrap();
 
-        let msg = rx.recv().await.unwrap();
+        let msg = rx.recv().await.unwrap().update;
         assert!(!msg.pubkey.is_empty());
         assert!(msg.price > 0.0);
     }
+
+    #[test]
+    fn test_price_transport_parse() {
+        assert_eq!(PriceTransport::parse(None), PriceTransport::PubSub);
+        assert_eq!(
+            PriceTransport::parse(Some("pubsub")),
+            PriceTransport::PubSub
+        );
+        assert_eq!(
+            PriceTransport::parse(Some("stream")),
+            PriceTransport::Stream
+        );
+        // listen-data publishes to both, the stream can be replayed
+        assert_eq!(PriceTransport::parse(Some("both")), PriceTransport::Stream);
+        assert_eq!(
+            PriceTransport::parse(Some("streams")),
+            PriceTransport::PubSub
+        );
+    }
+
+    #[test]
+    fn test_stream_reply() {
+        use redis::Value;
+        let data = |s: &str| Value::Data(s.as_bytes().to_vec());
+
+        let reply = Value::Bulk(vec![Value::Bulk(vec![
+            data(PRICE_UPDATES_STREAM),
+            Value::Bulk(vec![
+                Value::Bulk(vec![
+                    data("1-0"),
+                    Value::Bulk(vec![data("data"), data("{}")]),
+                ]),
+                // pending, but trimmed from the stream
+                Value::Bulk(vec![data("2-0"), Value::Nil]),
+            ]),
+        ])]);
+        assert_eq!(
+            stream_entries(&reply).unwrap(),
+            vec![
+                ("1-0".to_string(), Some("{}".to_string())),
+                ("2-0".to_string(), None)
+            ]
+        );
+
+        // the read timed out
+        assert!(stream_entries(&Value::Nil).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,74 @@
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, HttpResponse, Responder,
+};
+
+use tokio::sync::Semaphore;
+
+use super::common::verify_auth;
+use super::state::AppState;
+use crate::engine::backtest::{run_backtest, BacktestError, BacktestRequest, PriceHistory};
+
+/// Replays are buffered in memory, only a couple run at once
+const MAX_CONCURRENT_BACKTESTS: usize = 2;
+static BACKTEST_SLOTS: Semaphore = Semaphore::const_new(MAX_CONCURRENT_BACKTESTS);
+
+/// Backtests run on their own engine, so they don't go through the engine
+/// bridge and never touch live pipelines
+pub async fn backtest_pipeline(
+    state: Data<AppState>,
+    req: HttpRequest,
+    request: web::Json<BacktestRequest>,
+) -> impl Responder {
+    if let Err(response) = verify_auth(&state, &req).await {
+        return response;
+    }
+
+    let Ok(_slot) = BACKTEST_SLOTS.try_acquire() else {
+        return HttpResponse::TooManyRequests().json(serde_json::json!({
+            "status": "error",
+            "message": "Too many backtests running, try again later"
+        }));
+    };
+
+    let start = std::time::Instant::now();
+    metrics::counter!("backtest_runs", 1);
+
+    // the replay is CPU bound, it runs on a runtime of its own on a blocking
+    // thread so that it doesn't hold up the server's workers, the slot is
+    // held until it is done
+    let request = request.into_inner();
+    let result = tokio::task::spawn_blocking(move || {
+        let history = PriceHistory::from_env()?;
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| BacktestError::RuntimeError(e.to_string()))?
+            .block_on(run_backtest(request, &history))
+    })
+    .await
+    .unwrap_or_else(|e| Err(BacktestError::RuntimeError(e.to_string())));
+
+    metrics::histogram!("backtest_duration", start.elapsed());
+
+    match result {
+        Ok(report) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "message": "Backtest completed",
+            "response": report
+        })),
+        Err(e) => {
+            let mut response = match e {
+                BacktestError::InvalidPipeline(_)
+                | BacktestError::InvalidRange(_)
+                | BacktestError::Unsupported(_) => HttpResponse::BadRequest(),
+                BacktestError::ClickhouseConfigError(_) => HttpResponse::ServiceUnavailable(),
+                _ => HttpResponse::InternalServerError(),
+            };
+            response.json(serde_json::json!({
+                "status": "error",
+                "message": format!("Backtest failed: {}", e)
+            }))
+        }
+    }
+}
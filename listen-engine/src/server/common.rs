@@ -1,4 +1,7 @@
-use crate::{engine::error::EngineError, server::state::AppState};
+use crate::{
+    engine::{error::EngineError, risk::RiskLimitError},
+    server::state::AppState,
+};
 use actix_web::{web::Data, HttpRequest, HttpResponse};
 use privy::auth::UserSession;
 use serde::Serialize;
@@ -29,9 +32,15 @@ pub async fn verify_auth(
         }
     };
 
+    let Some(privy) = state.privy.as_ref() else {
+        return Err(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "error",
+            "message": "User authentication is not configured"
+        })));
+    };
+
     // Authenticate the user
-    match state
-        .privy
+    match privy
         .authenticate_user(auth_token)
         .await
         .map_err(|_| HttpResponse::Unauthorized())
@@ -55,10 +64,26 @@ pub async fn handle_engine_response<T: Serialize>(
                 "message": success_message,
                 "response": response
             })),
-            Ok(Err(e)) => HttpResponse::InternalServerError().json(serde_json::json!({
-                "status": "error",
-                "message": format!("Operation failed: {}", e)
-            })),
+            Ok(Err(e)) => {
+                let mut response = match e {
+                    EngineError::PipelineNotFound(_) | EngineError::StepNotFound(_) => {
+                        HttpResponse::NotFound()
+                    }
+                    EngineError::Unauthorized => HttpResponse::Forbidden(),
+                    EngineError::PipelineBusy(_)
+                    | EngineError::InvalidPipelineStatus(_)
+                    | EngineError::StepNotEditable => HttpResponse::Conflict(),
+                    EngineError::InvalidStepEdit(_)
+                    | EngineError::RiskLimitError(RiskLimitError::InvalidLimits(_)) => {
+                        HttpResponse::BadRequest()
+                    }
+                    _ => HttpResponse::InternalServerError(),
+                };
+                response.json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Operation failed: {}", e)
+                }))
+            }
             Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
                 "status": "error",
                 "message": format!("Failed to receive response from engine: {}", e)
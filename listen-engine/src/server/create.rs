@@ -30,7 +30,16 @@ pub async fn create_pipeline_common(
         }));
     }
 
-    let pipeline: Pipeline = (wire, pipeline_params).into();
+    let pipeline = match Pipeline::try_from((wire, pipeline_params)) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            metrics::counter!("pipeline_creation_errors_invalid", 1);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "message": format!("Invalid pipeline: {}", e)
+            }));
+        }
+    };
 
     tracing::info!(pipeline = ?pipeline, "creating pipeline");
 
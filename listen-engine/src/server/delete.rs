@@ -0,0 +1,44 @@
+use super::state::{AppState, EngineMessage};
+use actix_web::{
+    web::{Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use super::common::{handle_engine_response, verify_auth};
+
+pub async fn delete_pipeline(
+    state: Data<AppState>,
+    req: HttpRequest,
+    path: Path<Uuid>,
+) -> impl Responder {
+    let pipeline_id = path.into_inner();
+
+    // Authenticate user
+    let user = match verify_auth(&state, &req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    // Create channel for response
+    let (response_tx, response_rx) = oneshot::channel();
+
+    // Send delete message to engine
+    if let Err(e) = state
+        .engine_bridge_tx
+        .send(EngineMessage::DeletePipeline {
+            user_id: user.user_id.clone(),
+            pipeline_id,
+            response_tx,
+        })
+        .await
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to communicate with engine: {}", e)
+        }));
+    }
+
+    handle_engine_response(response_rx, "Pipeline deleted successfully").await
+}
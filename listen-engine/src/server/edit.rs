@@ -0,0 +1,58 @@
+use super::state::{AppState, EngineMessage};
+use crate::engine::api::WireStepEdit;
+use actix_web::{
+    web::{self, Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use super::common::{handle_engine_response, verify_auth};
+
+#[derive(Deserialize)]
+pub struct EditStepParams {
+    pipeline_id: Uuid,
+    step_id: Uuid,
+}
+
+pub async fn edit_step(
+    state: Data<AppState>,
+    req: HttpRequest,
+    params: Path<EditStepParams>,
+    edit: web::Json<WireStepEdit>,
+) -> impl Responder {
+    let EditStepParams {
+        pipeline_id,
+        step_id,
+    } = params.into_inner();
+
+    // Authenticate user
+    let user = match verify_auth(&state, &req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    // Create channel for response
+    let (response_tx, response_rx) = oneshot::channel();
+
+    // Send edit step message to engine
+    if let Err(e) = state
+        .engine_bridge_tx
+        .send(EngineMessage::EditStep {
+            user_id: user.user_id.clone(),
+            pipeline_id,
+            step_id,
+            edit: edit.into_inner(),
+            response_tx,
+        })
+        .await
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to communicate with engine: {}", e)
+        }));
+    }
+
+    handle_engine_response(response_rx, "Step edited successfully").await
+}
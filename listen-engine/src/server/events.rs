@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use actix_web::{web::Bytes, web::Data, HttpRequest, HttpResponse, Responder};
+use futures_util::StreamExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::common::verify_auth;
+use super::state::AppState;
+use crate::engine::events::{PipelineEvent, PIPELINE_EVENTS_CHANNEL};
+
+/// Streams the step transitions of the authenticated user's pipelines as
+/// server-sent events
+pub async fn pipeline_events(state: Data<AppState>, req: HttpRequest) -> impl Responder {
+    let user = match verify_auth(&state, &req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let rx = state.pipeline_events_tx.subscribe();
+    // Comments keep idle connections from being closed by proxies
+    let keep_alive = tokio::time::interval(Duration::from_secs(15));
+
+    metrics::counter!("pipeline_event_streams_opened", 1);
+
+    let stream = futures_util::stream::unfold(
+        (rx, keep_alive, user.user_id),
+        |(mut rx, mut keep_alive, user_id)| async move {
+            loop {
+                let chunk = tokio::select! {
+                    event = rx.recv() => match event {
+                        Ok(event) if event.user_id == user_id => {
+                            match serde_json::to_string(&event) {
+                                Ok(json) => format!("data: {}\n\n", json),
+                                Err(e) => {
+                                    tracing::error!("Failed to serialize pipeline event: {}", e);
+                                    continue;
+                                }
+                            }
+                        }
+                        Ok(_) => continue,
+                        // Let the client know it missed events and should refetch
+                        Err(RecvError::Lagged(skipped)) => {
+                            format!("event: lagged\ndata: {}\n\n", skipped)
+                        }
+                        Err(RecvError::Closed) => return None,
+                    },
+                    _ = keep_alive.tick() => ": keep-alive\n\n".to_string(),
+                };
+
+                return Some((
+                    Ok::<_, actix_web::Error>(Bytes::from(chunk)),
+                    (rx, keep_alive, user_id),
+                ));
+            }
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+}
+
+/// Relays pipeline events from Redis to the local broadcast channel,
+/// reconnecting whenever the subscription drops
+pub fn spawn_event_relay(tx: broadcast::Sender<PipelineEvent>) {
+    let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = relay_events(&redis_url, &tx).await {
+                tracing::error!("Pipeline event relay error: {}", e);
+                metrics::counter!("pipeline_event_relay_errors", 1);
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+async fn relay_events(
+    redis_url: &str,
+    tx: &broadcast::Sender<PipelineEvent>,
+) -> Result<(), redis::RedisError> {
+    let client = redis::Client::open(redis_url)?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(PIPELINE_EVENTS_CHANNEL).await?;
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let payload: String = msg.get_payload()?;
+        match serde_json::from_str::<PipelineEvent>(&payload) {
+            // sending only fails when there are no connected clients
+            Ok(event) => {
+                let _ = tx.send(event);
+            }
+            Err(e) => tracing::warn!("Failed to parse pipeline event: {}", e),
+        }
+    }
+
+    Ok(())
+}
@@ -1,32 +1,16 @@
+use super::common::{handle_engine_response, verify_auth};
 use super::state::{AppState, EngineMessage};
-use actix_web::{web::Data, HttpRequest, HttpResponse, Responder};
+use actix_web::{
+    web::{Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
 use tokio::sync::oneshot;
+use uuid::Uuid;
 
 pub async fn get_pipelines(state: Data<AppState>, req: HttpRequest) -> impl Responder {
-    let auth_token = match req.headers().get("authorization") {
-        Some(auth_token) => auth_token.to_str().unwrap(),
-        None => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "status": "error",
-                "message": "Authorization header is required"
-            }));
-        }
-    };
-    let auth_token = auth_token.split(" ").nth(1).unwrap();
-
-    let user = match state
-        .privy
-        .authenticate_user(auth_token)
-        .await
-        .map_err(|_| HttpResponse::Unauthorized())
-    {
+    let user = match verify_auth(&state, &req).await {
         Ok(user) => user,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "status": "error",
-                "message": "Unauthorized"
-            }));
-        }
+        Err(response) => return response,
     };
 
     let (response_tx, response_rx) = oneshot::channel();
@@ -76,3 +60,35 @@ pub async fn get_pipelines(state: Data<AppState>, req: HttpRequest) -> impl Resp
         "pipelines": pipelines
     }))
 }
+
+pub async fn get_pipeline(
+    state: Data<AppState>,
+    req: HttpRequest,
+    path: Path<Uuid>,
+) -> impl Responder {
+    let pipeline_id = path.into_inner();
+
+    let user = match verify_auth(&state, &req).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let (response_tx, response_rx) = oneshot::channel();
+
+    if let Err(e) = state
+        .engine_bridge_tx
+        .send(EngineMessage::GetPipeline {
+            user_id: user.user_id.clone(),
+            pipeline_id,
+            response_tx,
+        })
+        .await
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to communicate with engine: {}", e)
+        }));
+    }
+
+    handle_engine_response(response_rx, "Pipeline retrieved successfully").await
+}
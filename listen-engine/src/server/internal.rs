@@ -9,6 +9,12 @@ use crate::server::state::AppState;
 pub struct CreatePipelineRequest {
     pub user_id: String,
     pub pipeline: WirePipeline,
+    /// Used as is when Privy is not configured, e.g. with the local keypair
+    /// executor, otherwise looked up from the user
+    #[serde(default)]
+    pub wallet_address: Option<String>,
+    #[serde(default)]
+    pub pubkey: Option<String>,
 }
 
 pub async fn create_pipeline_internal(
@@ -16,9 +22,18 @@ pub async fn create_pipeline_internal(
     json: web::Json<CreatePipelineRequest>,
 ) -> impl Responder {
     let request = json.into_inner();
-    match data.privy.get_user_by_id(&request.user_id).await {
+    let Some(privy) = data.privy.clone() else {
+        let pipeline_params = PipelineParams {
+            user_id: request.user_id,
+            wallet_address: request.wallet_address,
+            pubkey: request.pubkey,
+        };
+        return create_pipeline_common(data, request.pipeline, pipeline_params).await;
+    };
+
+    match privy.get_user_by_id(&request.user_id).await {
         Ok(user) => {
-            let user_info = data.privy.user_to_user_info(&user);
+            let user_info = privy.user_to_user_info(&user);
 
             let pipeline_params = PipelineParams {
                 user_id: request.user_id.to_string(),
@@ -0,0 +1,59 @@
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpResponse, Responder,
+};
+use tokio::sync::oneshot;
+
+use super::common::handle_engine_response;
+use super::state::{AppState, EngineMessage};
+use crate::engine::risk::RiskLimitsUpdate;
+
+/// Admin only, served on the internal listener
+pub async fn get_risk_limits(state: Data<AppState>, path: Path<String>) -> impl Responder {
+    let user_id = path.into_inner();
+    let (response_tx, response_rx) = oneshot::channel();
+
+    if let Err(e) = state
+        .engine_bridge_tx
+        .send(EngineMessage::GetRiskLimits {
+            user_id,
+            response_tx,
+        })
+        .await
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to communicate with engine: {}", e)
+        }));
+    }
+
+    handle_engine_response(response_rx, "Risk limits retrieved successfully").await
+}
+
+/// Admin only, served on the internal listener. Omitted limits are left as
+/// they are, empty mint lists clear them.
+pub async fn set_risk_limits(
+    state: Data<AppState>,
+    path: Path<String>,
+    json: Json<RiskLimitsUpdate>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    let (response_tx, response_rx) = oneshot::channel();
+
+    if let Err(e) = state
+        .engine_bridge_tx
+        .send(EngineMessage::SetRiskLimits {
+            user_id,
+            update: json.into_inner(),
+            response_tx,
+        })
+        .await
+    {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "message": format!("Failed to communicate with engine: {}", e)
+        }));
+    }
+
+    handle_engine_response(response_rx, "Risk limits updated successfully").await
+}
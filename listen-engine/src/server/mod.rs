@@ -5,16 +5,21 @@ use actix_web::{
     App, HttpResponse, HttpServer, Responder,
 };
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 
 use crate::{engine::Engine, metrics::metrics_handler, server::state::AppState};
-use privy::{config::PrivyConfig, Privy};
 
+pub mod backtest;
 pub mod cancel;
 pub mod common;
 pub mod create;
+pub mod delete;
+pub mod edit;
+pub mod events;
 pub mod get;
 pub mod internal;
+pub mod limits;
+pub mod pause;
 pub mod state;
 
 pub async fn run() -> std::io::Result<()> {
@@ -30,10 +35,7 @@ pub async fn run() -> std::io::Result<()> {
         Ok((engine, rx)) => (engine, rx),
         Err(e) => {
             tracing::error!("Failed to create engine: {}", e);
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to create engine",
-            ));
+            return Err(std::io::Error::other("Failed to create engine"));
         }
     };
     let engine = Arc::new(engine);
@@ -49,14 +51,22 @@ pub async fn run() -> std::io::Result<()> {
         }
     });
 
-    let privy = Arc::new(Privy::new(PrivyConfig::from_env().map_err(|_| {
-        std::io::Error::new(std::io::ErrorKind::Other, "Failed to create privy config")
-    })?));
+    // Privy is optional, without it only the internal routes accept requests
+    let privy = engine.privy.clone();
+    if privy.is_none() {
+        tracing::warn!("Privy is not configured, user authentication is disabled");
+    }
+
+    // Pipeline events are relayed from Redis so that clients get updates
+    // regardless of which instance evaluated the pipeline
+    let (pipeline_events_tx, _) = broadcast::channel(1024);
+    events::spawn_event_relay(pipeline_events_tx.clone());
 
     // Create a shared AppState for both servers
     let app_state = Data::new(AppState {
         engine_bridge_tx: server_tx.clone(),
-        privy: privy.clone(),
+        privy,
+        pipeline_events_tx,
     });
 
     // Create separate app states for each server
@@ -82,10 +92,29 @@ pub async fn run() -> std::io::Result<()> {
             .route("/healthz", web::get().to(healthz))
             .route("/pipeline", web::post().to(create::create_pipeline))
             .route("/pipelines", web::get().to(get::get_pipelines))
+            .route("/backtest", web::post().to(backtest::backtest_pipeline))
+            .route("/pipelines/events", web::get().to(events::pipeline_events))
+            .route("/pipeline/{pipeline_id}", web::get().to(get::get_pipeline))
+            .route(
+                "/pipeline/{pipeline_id}",
+                web::delete().to(delete::delete_pipeline),
+            )
             .route(
                 "/pipeline/{pipeline_id}/cancel",
                 web::post().to(cancel::cancel_pipeline),
             )
+            .route(
+                "/pipeline/{pipeline_id}/pause",
+                web::post().to(pause::pause_pipeline),
+            )
+            .route(
+                "/pipeline/{pipeline_id}/resume",
+                web::post().to(pause::resume_pipeline),
+            )
+            .route(
+                "/pipeline/{pipeline_id}/step/{step_id}",
+                web::patch().to(edit::edit_step),
+            )
             .route(
                 "/pipeline/{pipeline_id}/step/{step_id}/cancel",
                 web::post().to(cancel::cancel_step),
@@ -103,6 +132,14 @@ pub async fn run() -> std::io::Result<()> {
                 "/internal/create_pipeline",
                 web::post().to(internal::create_pipeline_internal),
             )
+            .route(
+                "/internal/limits/{user_id}",
+                web::get().to(limits::get_risk_limits),
+            )
+            .route(
+                "/internal/limits/{user_id}",
+                web::put().to(limits::set_risk_limits),
+            )
     })
     .bind(("127.0.0.1", 6901))?; // Different port, localhost only
 
@@ -1,8 +1,12 @@
+use crate::engine::api::WireStepEdit;
 use crate::engine::error::EngineError;
-use crate::engine::pipeline::Pipeline;
+use crate::engine::events::PipelineEvent;
+use crate::engine::pipeline::{Pipeline, PipelineStep};
+use crate::engine::risk::{RiskLimits, RiskLimitsUpdate};
 use std::sync::Arc;
 
 use privy::Privy;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 use uuid::Uuid;
@@ -38,9 +42,36 @@ pub enum EngineMessage {
         step_id: Uuid,
         response_tx: oneshot::Sender<Result<(), EngineError>>,
     },
+    EditStep {
+        user_id: String,
+        pipeline_id: Uuid,
+        step_id: Uuid,
+        edit: WireStepEdit,
+        response_tx: oneshot::Sender<Result<PipelineStep, EngineError>>,
+    },
+    PausePipeline {
+        user_id: String,
+        pipeline_id: Uuid,
+        response_tx: oneshot::Sender<Result<(), EngineError>>,
+    },
+    ResumePipeline {
+        user_id: String,
+        pipeline_id: Uuid,
+        response_tx: oneshot::Sender<Result<(), EngineError>>,
+    },
+    GetRiskLimits {
+        user_id: String,
+        response_tx: oneshot::Sender<Result<RiskLimits, EngineError>>,
+    },
+    SetRiskLimits {
+        user_id: String,
+        update: RiskLimitsUpdate,
+        response_tx: oneshot::Sender<Result<RiskLimits, EngineError>>,
+    },
 }
 
 pub struct AppState {
     pub engine_bridge_tx: mpsc::Sender<EngineMessage>,
-    pub privy: Arc<Privy>,
+    pub privy: Option<Arc<Privy>>,
+    pub pipeline_events_tx: broadcast::Sender<PipelineEvent>,
 }
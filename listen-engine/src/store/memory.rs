@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use parking_lot::RwLock;
+
+use super::{PipelineStore, StoreError};
+use crate::engine::cluster::IndexUpdate;
+use crate::engine::events::PipelineEvent;
+use crate::engine::pipeline::Pipeline;
+use crate::redis::rate_limits::{MintList, RateLimit, RateLimitType};
+use crate::redis::subscriber::PriceUpdate;
+
+/// Process local store, for tests and single instance setups without Redis.
+/// Rate limit counters never reset and published events are only recorded.
+/// Engines sharing a store see each other's leases and heartbeats, but index
+/// updates are dropped, there is nobody else to tell.
+#[derive(Default)]
+pub struct MemoryStore {
+    pipelines: RwLock<HashMap<String, Pipeline>>,
+    prices: RwLock<HashMap<String, PriceUpdate>>,
+    events: RwLock<Vec<PipelineEvent>>,
+    rate_limits: RwLock<HashMap<String, u32>>,
+    user_limits: RwLock<HashMap<String, u32>>,
+    mint_lists: RwLock<HashMap<String, Vec<String>>>,
+    leases: RwLock<HashMap<String, (String, Instant)>>, // key -> (owner, expiry)
+    instances: RwLock<HashMap<String, Instant>>,        // instance id -> expiry
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stands in for the indexer writing the latest price of an asset
+    pub fn set_price_update(&self, update: PriceUpdate) {
+        self.prices.write().insert(update.pubkey.clone(), update);
+    }
+
+    pub fn events(&self) -> Vec<PipelineEvent> {
+        self.events.read().clone()
+    }
+
+    fn rate_limit(&self, user_id: &str, limit_type: &RateLimitType) -> RateLimit {
+        let count = self
+            .rate_limits
+            .read()
+            .get(&format!("{}:{}", user_id, limit_type.key()))
+            .copied()
+            .unwrap_or(0);
+        let limit = self
+            .user_limits
+            .read()
+            .get(&format!("{}:{}", user_id, limit_type.key()))
+            .copied()
+            .unwrap_or_else(|| limit_type.default_limit(None));
+        RateLimit {
+            limit,
+            remaining: limit.saturating_sub(count),
+            reset_at: None,
+        }
+    }
+}
+
+#[async_trait]
+impl PipelineStore for MemoryStore {
+    async fn save_pipeline(&self, pipeline: &Pipeline) -> Result<(), StoreError> {
+        self.pipelines.write().insert(
+            format!("{}:{}", pipeline.user_id, pipeline.id),
+            pipeline.clone(),
+        );
+        Ok(())
+    }
+
+    async fn get_pipeline(&self, user_id: &str, id: &str) -> Result<Option<Pipeline>, StoreError> {
+        Ok(self
+            .pipelines
+            .read()
+            .get(&format!("{}:{}", user_id, id))
+            .cloned())
+    }
+
+    async fn get_pipelines(&self, keys: &[String]) -> Result<Vec<Option<Pipeline>>, StoreError> {
+        let pipelines = self.pipelines.read();
+        Ok(keys.iter().map(|key| pipelines.get(key).cloned()).collect())
+    }
+
+    async fn get_all_pipelines(&self) -> Result<Vec<Pipeline>, StoreError> {
+        Ok(self.pipelines.read().values().cloned().collect())
+    }
+
+    async fn get_all_pipelines_for_user(&self, user_id: &str) -> Result<Vec<Pipeline>, StoreError> {
+        Ok(self
+            .pipelines
+            .read()
+            .values()
+            .filter(|pipeline| pipeline.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_pipeline(&self, user_id: &str, id: &str) -> Result<(), StoreError> {
+        self.pipelines
+            .write()
+            .remove(&format!("{}:{}", user_id, id));
+        Ok(())
+    }
+
+    async fn get_price_update(&self, asset: &str) -> Result<Option<PriceUpdate>, StoreError> {
+        Ok(self.prices.read().get(asset).cloned())
+    }
+
+    async fn publish_event(&self, event: &PipelineEvent) -> Result<(), StoreError> {
+        self.events.write().push(event.clone());
+        Ok(())
+    }
+
+    async fn get_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+    ) -> Result<RateLimit, StoreError> {
+        Ok(self.rate_limit(user_id, limit_type))
+    }
+
+    async fn increment_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+    ) -> Result<RateLimit, StoreError> {
+        let mut rate_limits = self.rate_limits.write();
+        let count = rate_limits
+            .entry(format!("{}:{}", user_id, limit_type.key()))
+            .or_default();
+        *count = count.saturating_add(1);
+        drop(rate_limits);
+        Ok(self.rate_limit(user_id, limit_type))
+    }
+
+    async fn reserve_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<Option<RateLimit>, StoreError> {
+        let limit = self.rate_limit(user_id, limit_type).limit;
+        let mut rate_limits = self.rate_limits.write();
+        let count = rate_limits
+            .entry(format!("{}:{}", user_id, limit_type.key()))
+            .or_default();
+        if count.saturating_add(amount) > limit {
+            return Ok(None);
+        }
+        *count += amount;
+        drop(rate_limits);
+        Ok(Some(self.rate_limit(user_id, limit_type)))
+    }
+
+    async fn release_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<(), StoreError> {
+        let mut rate_limits = self.rate_limits.write();
+        if let Some(count) = rate_limits.get_mut(&format!("{}:{}", user_id, limit_type.key())) {
+            *count = count.saturating_sub(amount);
+        }
+        Ok(())
+    }
+
+    async fn set_user_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        limit: u32,
+    ) -> Result<(), StoreError> {
+        self.user_limits
+            .write()
+            .insert(format!("{}:{}", user_id, limit_type.key()), limit);
+        Ok(())
+    }
+
+    async fn get_mint_list(
+        &self,
+        user_id: &str,
+        list: MintList,
+    ) -> Result<Vec<String>, StoreError> {
+        Ok(self
+            .mint_lists
+            .read()
+            .get(&format!("{}:{}", user_id, list.key()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_mint_list(
+        &self,
+        user_id: &str,
+        list: MintList,
+        mints: &[String],
+    ) -> Result<(), StoreError> {
+        self.mint_lists
+            .write()
+            .insert(format!("{}:{}", user_id, list.key()), mints.to_vec());
+        Ok(())
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError> {
+        let now = Instant::now();
+        let mut leases = self.leases.write();
+        match leases.get(key) {
+            Some((holder, expiry)) if holder != owner && *expiry > now => Ok(false),
+            _ => {
+                leases.insert(key.to_string(), (owner.to_string(), now + ttl));
+                Ok(true)
+            }
+        }
+    }
+
+    async fn release_lease(&self, key: &str, owner: &str) -> Result<(), StoreError> {
+        let mut leases = self.leases.write();
+        if leases.get(key).is_some_and(|(holder, _)| holder == owner) {
+            leases.remove(key);
+        }
+        Ok(())
+    }
+
+    async fn heartbeat(&self, instance_id: &str, ttl: Duration) -> Result<Vec<String>, StoreError> {
+        let now = Instant::now();
+        let mut instances = self.instances.write();
+        instances.insert(instance_id.to_string(), now + ttl);
+        instances.retain(|_, expiry| *expiry > now);
+        let mut live: Vec<String> = instances.keys().cloned().collect();
+        live.sort();
+        Ok(live)
+    }
+
+    async fn leave(&self, instance_id: &str) -> Result<(), StoreError> {
+        self.instances.write().remove(instance_id);
+        Ok(())
+    }
+
+    async fn publish_index_update(&self, _update: &IndexUpdate) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
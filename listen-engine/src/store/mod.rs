@@ -0,0 +1,111 @@
+pub mod memory;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::engine::cluster::IndexUpdate;
+use crate::engine::events::PipelineEvent;
+use crate::engine::pipeline::Pipeline;
+use crate::redis::client::RedisClientError;
+use crate::redis::rate_limits::{MintList, RateLimit, RateLimitType};
+use crate::redis::subscriber::PriceUpdate;
+
+pub use self::memory::MemoryStore;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("[Store] Redis error: {0}")]
+    RedisError(#[from] RedisClientError),
+}
+
+/// Everything the engine persists or reads outside of its own memory:
+/// pipelines, the latest prices written by the indexer, pipeline events,
+/// rate limit counters and the coordination between engine instances
+#[async_trait]
+pub trait PipelineStore: Send + Sync {
+    async fn save_pipeline(&self, pipeline: &Pipeline) -> Result<(), StoreError>;
+
+    async fn get_pipeline(&self, user_id: &str, id: &str) -> Result<Option<Pipeline>, StoreError>;
+
+    /// Batch lookup by `{user_id}:{id}` keys, results are in the order of the keys
+    async fn get_pipelines(&self, keys: &[String]) -> Result<Vec<Option<Pipeline>>, StoreError>;
+
+    async fn get_all_pipelines(&self) -> Result<Vec<Pipeline>, StoreError>;
+
+    async fn get_all_pipelines_for_user(&self, user_id: &str) -> Result<Vec<Pipeline>, StoreError>;
+
+    async fn delete_pipeline(&self, user_id: &str, id: &str) -> Result<(), StoreError>;
+
+    async fn get_price_update(&self, asset: &str) -> Result<Option<PriceUpdate>, StoreError>;
+
+    async fn publish_event(&self, event: &PipelineEvent) -> Result<(), StoreError>;
+
+    async fn get_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+    ) -> Result<RateLimit, StoreError>;
+
+    async fn increment_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+    ) -> Result<RateLimit, StoreError>;
+
+    /// Adds `amount` to the counter if it stays within the limit, e.g. the
+    /// USD notional of an order, `None` if it wouldn't
+    async fn reserve_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<Option<RateLimit>, StoreError>;
+
+    /// Subtracts a reserved `amount` from the counter again
+    async fn release_rate_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        amount: u32,
+    ) -> Result<(), StoreError>;
+
+    /// Overrides the default limit of `limit_type` for the user
+    async fn set_user_limit(
+        &self,
+        user_id: &str,
+        limit_type: &RateLimitType,
+        limit: u32,
+    ) -> Result<(), StoreError>;
+
+    async fn get_mint_list(&self, user_id: &str, list: MintList)
+        -> Result<Vec<String>, StoreError>;
+
+    async fn set_mint_list(
+        &self,
+        user_id: &str,
+        list: MintList,
+        mints: &[String],
+    ) -> Result<(), StoreError>;
+
+    /// Takes the lease on `key` for `owner`, or extends it if `owner` already
+    /// holds it. Returns false if another owner holds the lease.
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError>;
+
+    /// Releases the lease on `key`, unless it has since passed to another owner
+    async fn release_lease(&self, key: &str, owner: &str) -> Result<(), StoreError>;
+
+    /// Registers the instance as alive for `ttl` and returns all live instances
+    async fn heartbeat(&self, instance_id: &str, ttl: Duration) -> Result<Vec<String>, StoreError>;
+
+    /// Deregisters the instance, its pipelines move to the remaining instances
+    async fn leave(&self, instance_id: &str) -> Result<(), StoreError>;
+
+    /// Tells the other instances to re-read the pipeline into their index
+    async fn publish_index_update(&self, update: &IndexUpdate) -> Result<(), StoreError>;
+}
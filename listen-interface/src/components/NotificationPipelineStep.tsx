@@ -5,7 +5,7 @@ import { PipelineStepContainer } from "./PipelineStepContainer";
 
 interface NotificationPipelineStepProps {
   step: PipelineStep;
-  status?: "Pending" | "Completed" | "Failed" | "Cancelled";
+  status?: "Pending" | "Confirming" | "Completed" | "Failed" | "Cancelled";
 }
 
 export const NotificationPipelineStep = ({
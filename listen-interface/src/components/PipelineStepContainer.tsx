@@ -12,7 +12,7 @@ import { PipelineCondition, PipelineConditionType } from "../types/pipeline";
 interface PipelineStepContainerProps {
   children: React.ReactNode;
   conditions: PipelineCondition[];
-  status?: "Pending" | "Completed" | "Failed" | "Cancelled";
+  status?: "Pending" | "Confirming" | "Completed" | "Failed" | "Cancelled";
   transactionHash: string | null;
   error: string | null;
   compact?: boolean;
@@ -64,6 +64,7 @@ export const PipelineStepContainer = ({
 const renderStatus = (status: string, t: TFunction, error: string | null) => {
   switch (status) {
     case "Pending":
+    case "Confirming":
       return (
         <span className="text-yellow-300 flex items-center gap-1">
           <FaSpinner /> {t("pipelines.pending")}
@@ -7,7 +7,7 @@ import { SwapToken } from "./SwapToken";
 
 interface SwapPipelineStepProps {
   step: PipelineStep;
-  status?: "Pending" | "Completed" | "Failed" | "Cancelled";
+  status?: "Pending" | "Confirming" | "Completed" | "Failed" | "Cancelled";
   transactionHash: string | null;
   error: string | null;
   compact?: boolean;
@@ -24,6 +24,7 @@ export const pipelines = {
       Completed: "Completed",
       Failed: "Failed",
       Cancelled: "Cancelled",
+      Paused: "Paused",
     },
     approve: "Approve",
     reject: "Reject",
@@ -60,6 +61,7 @@ export const pipelines = {
       Completed: "已完成",
       Failed: "失败",
       Cancelled: "已取消",
+      Paused: "已暂停",
     },
     approve: "批准",
     reject: "拒绝",
@@ -50,6 +50,8 @@ const ConditionSchema = z.object({
 // Update status enum
 export const StatusSchema = z.enum([
   "Pending",
+  "Paused",
+  "Confirming",
   "Completed",
   "Failed",
   "Cancelled",
@@ -87,11 +89,13 @@ const ExtendedPipelineStepSchema = z.object({
   status: StatusSchema,
   transaction_hash: z.string().nullable(),
   error: z.string().nullable().optional(),
+  expires_at: z.string().datetime().nullable().optional(),
 });
 
 // Update pipeline schema
 export const ExtendedPipelineSchema = z.object({
   created_at: z.string().datetime(),
+  expires_at: z.string().datetime().nullable().optional(),
   current_steps: z.array(z.string().uuid()),
   id: z.string().uuid(),
   pubkey: z.string().optional().nullable(),